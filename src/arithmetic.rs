@@ -1,98 +1,478 @@
-use crate::Token;
+#[cfg(feature = "complex")]
+use crate::complex;
+use crate::{error, Eval, Token, Unwind};
 use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
+/// Integers that overflow an `Int` become a `Long`, only overflowing that is an error.
 impl std::ops::Add<Token> for Token {
-    type Output = Token;
+    type Output = Eval;
 
     fn add(self, rhs: Token) -> Self::Output {
-        if let Token::Int(i) = &self {
-            if let Token::Int(int_rhs) = &rhs {
-                return Token::Int(int_rhs + i);
-            }
+        if let (Some(i), Some(int_rhs)) = (self.as_integer(), rhs.as_integer()) {
+            return match i.checked_add(int_rhs) {
+                Some(result) => Ok(Token::integer(result)),
+                None => error("integer overflow"),
+            };
+        }
+        if let (Some((n1, d1)), Some((n2, d2))) = (self.as_ratio(), rhs.as_ratio()) {
+            return Token::ratio(n1 * d2 + n2 * d1, d1 * d2);
+        }
+        #[cfg(feature = "complex")]
+        if let Some(result) = complex::combine(&self, &rhs, complex::add) {
+            return Ok(result);
         }
-        panic!("you can add only integers");
+        if let (Some(f), Some(float_rhs)) = (self.as_float(), rhs.as_float()) {
+            return Ok(Token::Float(f + float_rhs));
+        }
+        error("you can add only numbers")
     }
 }
 
 impl std::ops::Sub<Token> for Token {
-    type Output = Token;
+    type Output = Eval;
 
     fn sub(self, rhs: Token) -> Self::Output {
-        if let Token::Int(i) = &self {
-            if let Token::Int(int_rhs) = &rhs {
-                return Token::Int(i - int_rhs);
-            }
+        if let (Some(i), Some(int_rhs)) = (self.as_integer(), rhs.as_integer()) {
+            return match i.checked_sub(int_rhs) {
+                Some(result) => Ok(Token::integer(result)),
+                None => error("integer overflow"),
+            };
+        }
+        if let (Some((n1, d1)), Some((n2, d2))) = (self.as_ratio(), rhs.as_ratio()) {
+            return Token::ratio(n1 * d2 - n2 * d1, d1 * d2);
         }
-        panic!("you can subtract only integers");
+        #[cfg(feature = "complex")]
+        if let Some(result) = complex::combine(&self, &rhs, complex::sub) {
+            return Ok(result);
+        }
+        if let (Some(f), Some(float_rhs)) = (self.as_float(), rhs.as_float()) {
+            return Ok(Token::Float(f - float_rhs));
+        }
+        error("you can subtract only numbers")
     }
 }
 
 impl std::ops::Mul<Token> for Token {
-    type Output = Token;
+    type Output = Eval;
 
     fn mul(self, rhs: Token) -> Self::Output {
-        if let Token::Int(i) = &self {
-            if let Token::Int(int_rhs) = &rhs {
-                return Token::Int(int_rhs * i);
-            }
+        if let (Some(i), Some(int_rhs)) = (self.as_integer(), rhs.as_integer()) {
+            return match i.checked_mul(int_rhs) {
+                Some(result) => Ok(Token::integer(result)),
+                None => error("integer overflow"),
+            };
+        }
+        if let (Some((n1, d1)), Some((n2, d2))) = (self.as_ratio(), rhs.as_ratio()) {
+            return Token::ratio(n1 * n2, d1 * d2);
+        }
+        #[cfg(feature = "complex")]
+        if let Some(result) = complex::combine(&self, &rhs, complex::mul) {
+            return Ok(result);
+        }
+        if let (Some(f), Some(float_rhs)) = (self.as_float(), rhs.as_float()) {
+            return Ok(Token::Float(f * float_rhs));
         }
-        panic!("you can multiply only integers");
+        error("you can multiply only numbers")
+    }
+}
+
+/// Integers divide into a ratio unless the division is exact, anything with a float into a
+/// float.
+impl std::ops::Div<Token> for Token {
+    type Output = Eval;
+
+    fn div(self, rhs: Token) -> Self::Output {
+        if let (Some((n1, d1)), Some((n2, d2))) = (self.as_ratio(), rhs.as_ratio()) {
+            return Token::ratio(n1 * d2, d1 * n2);
+        }
+        #[cfg(feature = "complex")]
+        if let Some(result) = complex::combine(&self, &rhs, complex::div) {
+            return Ok(result);
+        }
+        if let (Some(f), Some(float_rhs)) = (self.as_float(), rhs.as_float()) {
+            return Ok(Token::Float(f / float_rhs));
+        }
+        error("you can divide only numbers")
     }
 }
 
 impl PartialEq for Token {
     fn eq(&self, other: &Self) -> bool {
+        // Frozen values are equal to the same values that aren't.
+        if let Token::Frozen(value) = other {
+            return self == &**value;
+        }
         match self {
-            Token::Open => match other {
-                Token::Open => true,
+            Token::Open => matches!(other, Token::Open),
+            Token::Close => matches!(other, Token::Close),
+            Token::Quote(n1) => matches!(other, Token::Quote(n2) if n1 == n2),
+            Token::Int(i1) => match other {
+                Token::Int(i2) => i1 == i2,
                 _ => false,
             },
-            Token::Close => match other {
-                Token::Open => true,
+            Token::Long(i1) => matches!(other, Token::Long(i2) if i1 == i2),
+            Token::Ratio(n1, d1) => matches!(other, Token::Ratio(n2, d2) if n1 == n2 && d1 == d2),
+            // NaN equals itself here, so values holding it can still be found in sets.
+            Token::Float(f1) => match other {
+                Token::Float(f2) => f1 == f2 || f1.is_nan() && f2.is_nan(),
                 _ => false,
             },
-            Token::Int(i1) => match other {
-                Token::Int(i2) => i1 == i2,
+            #[cfg(feature = "complex")]
+            Token::Complex(re1, im1) => match other {
+                Token::Complex(re2, im2) => {
+                    Token::Float(*re1) == Token::Float(*re2)
+                        && Token::Float(*im1) == Token::Float(*im2)
+                }
                 _ => false,
             },
             Token::Symbol(s1) => match other {
                 Token::Symbol(s2) => s1 == s2,
                 _ => false,
             },
+            Token::Str(s1) => match other {
+                Token::Str(s2) => s1 == s2,
+                _ => false,
+            },
             Token::List(l1) => match other {
                 Token::List(l2) => l1 == l2,
                 _ => false,
             },
-            Token::True => match other {
-                Token::True => true,
+            Token::Set(s1) => match other {
+                Token::Set(s2) => s1 == s2,
+                _ => false,
+            },
+            Token::Map(m1) => matches!(other, Token::Map(m2) if m1 == m2),
+            Token::Vector(v1) => matches!(other, Token::Vector(v2) if v1 == v2),
+            Token::Lambda(p1, b1, e1) => match other {
+                Token::Lambda(p2, b2, e2) => p1 == p2 && b1 == b2 && Rc::ptr_eq(e1, e2),
+                _ => false,
+            },
+            Token::Values(v1) => match other {
+                Token::Values(v2) => v1 == v2,
                 _ => false,
             },
-            Token::False => match other {
-                Token::False => true,
+            Token::Bytes(b1) => match other {
+                Token::Bytes(b2) => b1 == b2,
                 _ => false,
             },
+            Token::Memoized(m1) => matches!(other, Token::Memoized(m2) if Rc::ptr_eq(m1, m2)),
+            Token::Error(e1) => matches!(other, Token::Error(e2) if e1 == e2),
+            Token::Frozen(value) => **value == *other,
+            Token::Native(n1) => matches!(other, Token::Native(n2) if Rc::ptr_eq(n1, n2)),
+            Token::Port(p1) => matches!(other, Token::Port(p2) if Rc::ptr_eq(p1, p2)),
+            Token::True => matches!(other, Token::True),
+            Token::False => matches!(other, Token::False),
+            Token::Nil => matches!(other, Token::Nil),
+            Token::Continuation(c1) => matches!(other, Token::Continuation(c2) if c1 == c2),
+            #[cfg(feature = "net")]
+            Token::Connection(c1) => matches!(other, Token::Connection(c2) if Rc::ptr_eq(c1, c2)),
+            #[cfg(feature = "net")]
+            Token::Listener(l1) => matches!(other, Token::Listener(l2) if Rc::ptr_eq(l1, l2)),
+            #[cfg(feature = "parallel")]
+            Token::Channel(c1) => matches!(other, Token::Channel(c2) if c1.same(c2)),
+            #[cfg(feature = "parallel")]
+            Token::Atom(a1) => matches!(other, Token::Atom(a2) if a1.same(a2)),
+            Token::Generator(g1) => matches!(other, Token::Generator(g2) if g1.same(g2)),
         }
     }
 }
 
-impl PartialOrd for Token {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl Eq for Token {}
+
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Token::Frozen(value) = self {
+            return value.hash(state);
+        }
+        self.tag().hash(state);
+        match self {
+            Token::Int(i) => i.hash(state),
+            Token::Long(i) => i.hash(state),
+            Token::Ratio(n, d) => (n, d).hash(state),
+            // Equal floats need equal hashes, whatever the sign of a zero or bits of a NaN.
+            Token::Float(f) if *f == 0.0 => 0u64.hash(state),
+            Token::Float(f) if f.is_nan() => f64::NAN.to_bits().hash(state),
+            Token::Float(f) => f.to_bits().hash(state),
+            #[cfg(feature = "complex")]
+            Token::Complex(re, im) => {
+                Token::Float(*re).hash(state);
+                Token::Float(*im).hash(state);
+            }
+            Token::Symbol(name) => name.hash(state),
+            Token::Str(s) => s.hash(state),
+            Token::List(l) | Token::Values(l) => l.hash(state),
+            Token::Bytes(b) => b.hash(state),
+            Token::Lambda(params, body, _) => {
+                params.hash(state);
+                body.hash(state);
+            }
+            Token::Set(set) => {
+                // Sets have no order, so their items' hashes are combined commutatively.
+                let combined = set.iter().fold(0u64, |acc, item| {
+                    let mut hasher = DefaultHasher::new();
+                    item.hash(&mut hasher);
+                    acc.wrapping_add(hasher.finish())
+                });
+                combined.hash(state);
+            }
+            Token::Map(map) => {
+                let combined = map.entries().iter().fold(0u64, |acc, entry| {
+                    let mut hasher = DefaultHasher::new();
+                    entry.hash(&mut hasher);
+                    acc.wrapping_add(hasher.finish())
+                });
+                combined.hash(state);
+            }
+            Token::Vector(vector) => vector.items().hash(state),
+            Token::Continuation(id) => id.hash(state),
+            Token::Port(port) => Rc::as_ptr(port).hash(state),
+            Token::Native(value) => (Rc::as_ptr(value) as *const ()).hash(state),
+            Token::Memoized(memo) => Rc::as_ptr(memo).hash(state),
+            Token::Error(failure) => failure.hash(state),
+            #[cfg(feature = "net")]
+            Token::Connection(stream) => Rc::as_ptr(stream).hash(state),
+            #[cfg(feature = "net")]
+            Token::Listener(listener) => Rc::as_ptr(listener).hash(state),
+            #[cfg(feature = "parallel")]
+            Token::Channel(channel) => channel.address().hash(state),
+            #[cfg(feature = "parallel")]
+            Token::Atom(atom) => atom.address().hash(state),
+            Token::Generator(generator) => generator.address().hash(state),
+            _ => {}
+        }
     }
 }
 
-impl Ord for Token {
-    fn cmp(&self, other: &Self) -> Ordering {
+impl Token {
+    /// A number for each kind of value, hashed before its contents. Fixed here rather than
+    /// taken from `mem::discriminant`, whose bytes depend on the compiler and on the features
+    /// built in, so that `hash` stays the same across builds.
+    fn tag(&self) -> u8 {
         match self {
-            Token::Int(i1) => match other {
-                Token::Int(i2) => i1.cmp(i2),
-                _ => panic!("comparison works only for numbers and symbols"),
-            },
-            Token::Symbol(s1) => match other {
-                Token::Symbol(s2) => s1.cmp(s2),
-                _ => panic!("comparison works only for numbers and symbols"),
-            },
-            _ => panic!("comparison works only for numbers and symbols"),
+            Token::Open => 0,
+            Token::Close => 1,
+            Token::Quote(_) => 2,
+            Token::Int(_) => 3,
+            Token::Long(_) => 4,
+            Token::Ratio(..) => 5,
+            Token::Float(_) => 6,
+            #[cfg(feature = "complex")]
+            Token::Complex(..) => 7,
+            Token::Symbol(_) => 8,
+            Token::Str(_) => 9,
+            Token::List(_) => 10,
+            Token::Set(_) => 11,
+            Token::Map(_) => 12,
+            Token::Vector(_) => 13,
+            Token::Lambda(..) => 14,
+            Token::True => 15,
+            Token::False => 16,
+            Token::Nil => 17,
+            Token::Continuation(_) => 18,
+            Token::Memoized(_) => 19,
+            Token::Error(_) => 20,
+            Token::Frozen(_) => 21,
+            Token::Native(_) => 22,
+            Token::Values(_) => 23,
+            Token::Bytes(_) => 24,
+            Token::Port(_) => 25,
+            #[cfg(feature = "net")]
+            Token::Connection(_) => 26,
+            #[cfg(feature = "net")]
+            Token::Listener(_) => 27,
+            #[cfg(feature = "parallel")]
+            Token::Channel(_) => 28,
+            #[cfg(feature = "parallel")]
+            Token::Atom(_) => 29,
+            Token::Generator(_) => 30,
         }
     }
+
+    /// The value of a number as a float, `None` for anything else.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Token::Int(i) => Some(*i as f64),
+            Token::Long(i) => Some(*i as f64),
+            Token::Ratio(n, d) => Some(*n as f64 / *d as f64),
+            Token::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// The value of an `Int` or `Long`, `None` for anything else.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Token::Int(i) => Some(*i as i64),
+            Token::Long(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// An integer as an `Int` when it fits one, a `Long` otherwise.
+    pub fn integer(i: i64) -> Token {
+        match i32::try_from(i) {
+            Ok(i) => Token::Int(i),
+            Err(_) => Token::Long(i),
+        }
+    }
+
+    /// The value of an integer or ratio as a numerator and a positive denominator, `None` for
+    /// anything else. Wide enough that sums and products of two of them can't overflow.
+    fn as_ratio(&self) -> Option<(i128, i128)> {
+        match self {
+            Token::Int(i) => Some((*i as i128, 1)),
+            Token::Long(i) => Some((*i as i128, 1)),
+            Token::Ratio(n, d) => Some((*n as i128, *d as i128)),
+            _ => None,
+        }
+    }
+
+    /// The number `numerator / denominator` in lowest terms, an integer when it divides evenly.
+    pub fn ratio(numerator: i128, denominator: i128) -> Eval {
+        if denominator == 0 {
+            return error("division by zero");
+        }
+        let divisor = gcd(numerator, denominator) * denominator.signum();
+        let (numerator, denominator) = (numerator / divisor, denominator / divisor);
+        match (i64::try_from(numerator), i64::try_from(denominator)) {
+            (Ok(n), Ok(1)) => Ok(Token::integer(n)),
+            (Ok(n), Ok(d)) => Ok(Token::Ratio(n, d)),
+            _ => error("integer overflow"),
+        }
+    }
+
+    /// The value a frozen one was made from, any other value as it is.
+    pub fn thawed(self) -> Token {
+        match self {
+            Token::Frozen(value) => Rc::try_unwrap(value).unwrap_or_else(|value| (*value).clone()),
+            value => value,
+        }
+    }
+
+    /// A native value holding `value`, for embedders to give to programs.
+    #[allow(dead_code)]
+    pub fn native<T: 'static>(value: T) -> Token {
+        Token::Native(Rc::new(value))
+    }
+
+    /// The Rust value a native one holds, when it's a `T`.
+    #[allow(dead_code)]
+    pub fn downcast<T: 'static>(&self) -> Option<&T> {
+        match self {
+            Token::Native(value) => value.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// The name of the type of a value, as `:type`, `type-of` and `assert-type` know it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Token::Int(_) | Token::Long(_) => "int",
+            Token::Ratio(..) => "ratio",
+            Token::Float(_) => "float",
+            #[cfg(feature = "complex")]
+            Token::Complex(..) => "complex",
+            Token::Symbol(_) => "symbol",
+            Token::Str(_) => "string",
+            Token::List(_) => "list",
+            Token::Set(_) => "set",
+            Token::Map(_) => "map",
+            Token::Vector(_) => "vector",
+            Token::Lambda(..) | Token::Memoized(_) => "function",
+            Token::True | Token::False => "boolean",
+            Token::Nil => "nil",
+            Token::Error(_) => "error",
+            Token::Frozen(value) => value.type_name(),
+            Token::Native(_) => "native",
+            Token::Continuation(_) => "continuation",
+            Token::Values(_) => "values",
+            Token::Bytes(_) => "bytes",
+            Token::Port(_) => "port",
+            #[cfg(feature = "net")]
+            Token::Connection(_) => "connection",
+            #[cfg(feature = "net")]
+            Token::Listener(_) => "listener",
+            #[cfg(feature = "parallel")]
+            Token::Channel(_) => "channel",
+            #[cfg(feature = "parallel")]
+            Token::Atom(_) => "atom",
+            Token::Generator(_) => "generator",
+            Token::Open | Token::Close | Token::Quote(_) => "syntax",
+        }
+    }
+
+    /// Whether a value counts as true in a test, for `if`, `while`, `filter` and the like:
+    /// everything but `false` and `nil` does.
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Token::False | Token::Nil)
+    }
+
+    /// Whether two values are the same for `=`: numbers by value, whether ints or floats, lists
+    /// item by item and everything else as `==` does.
+    pub fn equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Token::Frozen(value), other) | (other, Token::Frozen(value)) => value.equals(other),
+            (Token::Int(_) | Token::Long(_) | Token::Ratio(..), Token::Float(_))
+            | (Token::Float(_), Token::Int(_) | Token::Long(_) | Token::Ratio(..)) => {
+                self.as_float() == other.as_float()
+            }
+            (Token::List(l1), Token::List(l2)) | (Token::Values(l1), Token::Values(l2)) => {
+                l1.len() == l2.len() && l1.iter().zip(l2).all(|(v1, v2)| v1.equals(v2))
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Orders two numbers, strings or symbols, other values can't be compared.
+    pub fn compare(&self, other: &Self) -> Result<Ordering, Unwind> {
+        match (self, other) {
+            (Token::Int(i1), Token::Int(i2)) => Ok(i1.cmp(i2)),
+            (
+                Token::Int(_) | Token::Long(_) | Token::Ratio(..),
+                Token::Int(_) | Token::Long(_) | Token::Ratio(..),
+            ) => {
+                let (n1, d1) = self.as_ratio().unwrap();
+                let (n2, d2) = other.as_ratio().unwrap();
+                Ok((n1 * d2).cmp(&(n2 * d1)))
+            }
+            (
+                Token::Int(_) | Token::Long(_) | Token::Ratio(..) | Token::Float(_),
+                Token::Float(_),
+            )
+            | (Token::Float(_), Token::Int(_) | Token::Long(_) | Token::Ratio(..)) => {
+                match self.as_float().partial_cmp(&other.as_float()) {
+                    Some(ordering) => Ok(ordering),
+                    None => error("NaN can't be compared"),
+                }
+            }
+            (Token::Symbol(s1), Token::Symbol(s2)) => Ok(s1.as_str().cmp(s2.as_str())),
+            (Token::Str(s1), Token::Str(s2)) => Ok(s1.cmp(s2)),
+            _ => error("comparison works only for numbers, strings and symbols"),
+        }
+    }
+
+    /// An order for any two values, so sets print and list their items the same way in every
+    /// run: numbers, strings and symbols the way `compare` orders them, anything else by its
+    /// kind and then its printed form.
+    pub fn order(&self, other: &Self) -> Ordering {
+        match self.compare(other) {
+            Ok(ordering) if ordering != Ordering::Equal => ordering,
+            _ => self
+                .tag()
+                .cmp(&other.tag())
+                .then_with(|| self.to_string().cmp(&other.to_string())),
+        }
+    }
+}
+
+/// The greatest common divisor, positive unless both are 0.
+fn gcd(a: i128, b: i128) -> i128 {
+    match b {
+        0 => a.abs().max(1),
+        _ => gcd(b, a % b),
+    }
 }
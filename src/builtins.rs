@@ -0,0 +1,219 @@
+#[cfg(feature = "complex")]
+use crate::complex;
+#[cfg(feature = "datetime")]
+use crate::dates;
+#[cfg(feature = "http")]
+use crate::http;
+use crate::interpreter::Interpreter;
+#[cfg(feature = "net")]
+use crate::net;
+#[cfg(feature = "parallel")]
+use crate::parallel;
+use crate::symbols::Name;
+use crate::{
+    bytes, contracts, error, errors, files, generators, higher_order, identity, image, lists,
+    macros, math, persistent, ports, printing, reading, regexes, sets, strings, system, Eval,
+    Token, Unwind,
+};
+
+/// A function implemented in Rust, called with already evaluated arguments.
+pub type Builtin = fn(Vec<Token>, &mut Interpreter) -> Eval;
+
+/// Defines a module's `lookup`, finding the builtin called `name` among the ones listed, and its
+/// `NAMES`, the names it finds them by. Attributes before the builtins go on `lookup`.
+macro_rules! builtins {
+    ($(#[$attribute:meta])* $($name:literal => $function:expr,)*) => {
+        $(#[$attribute])*
+        pub fn lookup(name: &str) -> Option<crate::builtins::Builtin> {
+            let builtin: crate::builtins::Builtin = match name {
+                $($name => $function,)*
+                _ => return None,
+            };
+            Some(builtin)
+        }
+
+        /// The names of the builtins `lookup` finds.
+        pub const NAMES: &[&str] = &[$($name),*];
+    };
+}
+pub(crate) use builtins;
+
+/// The conversions between strings, numbers and symbols, which live in this module.
+mod conversions {
+    use super::{number_to_string, string_to_number, string_to_symbol, symbol_to_string};
+
+    builtins! {
+        /// Finds the conversion builtin called `name`.
+        "string->number" => string_to_number,
+        "number->string" => number_to_string,
+        "symbol->string" => symbol_to_string,
+        "string->symbol" => string_to_symbol,
+    }
+}
+
+/// Finds the builtin function called `name`.
+pub fn lookup(name: &str) -> Option<Builtin> {
+    GROUPS.iter().find_map(|(_, lookup)| lookup(name))
+}
+
+/// The `lookup` of a group of builtins.
+type Lookup = fn(&str) -> Option<Builtin>;
+
+/// The names and lookups of the builtin groups, all but the conversions living in their own
+/// modules.
+const GROUPS: &[(&[&str], Lookup)] = &[
+    (conversions::NAMES, conversions::lookup),
+    (lists::NAMES, lists::lookup),
+    (higher_order::NAMES, higher_order::lookup),
+    (macros::NAMES, macros::lookup),
+    (contracts::NAMES, contracts::lookup),
+    (errors::NAMES, errors::lookup),
+    (identity::NAMES, identity::lookup),
+    (image::NAMES, image::lookup),
+    (math::NAMES, math::lookup),
+    (strings::NAMES, strings::lookup),
+    (regexes::NAMES, regexes::lookup),
+    (sets::NAMES, sets::lookup),
+    (printing::NAMES, printing::lookup),
+    (reading::NAMES, reading::lookup),
+    (system::NAMES, system::lookup),
+    (files::NAMES, files::lookup),
+    (generators::NAMES, generators::lookup),
+    (bytes::NAMES, bytes::lookup),
+    (ports::NAMES, ports::lookup),
+    (persistent::NAMES, persistent::lookup),
+    #[cfg(feature = "datetime")]
+    (dates::NAMES, dates::lookup),
+    #[cfg(feature = "net")]
+    (net::NAMES, net::lookup),
+    #[cfg(feature = "http")]
+    (http::NAMES, http::lookup),
+    #[cfg(feature = "parallel")]
+    (parallel::NAMES, parallel::lookup),
+    #[cfg(feature = "complex")]
+    (complex::NAMES, complex::lookup),
+];
+
+/// The name of every builtin in this build.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    GROUPS.iter().flat_map(|(names, _)| names.iter().copied())
+}
+
+/// Checks that a builtin got between `min` and `max` arguments.
+pub fn arity(name: &str, args: &[Token], min: usize, max: usize) -> Result<(), Unwind> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return error(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            expected,
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the optional radix argument of the conversion functions.
+fn radix(args: &[Token]) -> Result<u32, Unwind> {
+    match args.get(1) {
+        None => Ok(10),
+        Some(Token::Int(radix)) if (2..=36).contains(radix) => Ok(*radix as u32),
+        Some(_) => error("radix needs to be an integer between 2 and 36"),
+    }
+}
+
+/// `(string->number "ff" 16)`, evaluates to `false` when the string isn't a number. Ratios like
+/// `"1/2"` read in any radix, floats like `"2.5e3"` only in radix 10.
+fn string_to_number(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("string->number", &args, 1, 2)?;
+    let radix = radix(&args)?;
+    let text = match &args[0] {
+        Token::Str(text) => text.trim(),
+        _ => return error("string->number expects a string"),
+    };
+    if let Ok(number) = i64::from_str_radix(text, radix) {
+        return Ok(Token::integer(number));
+    }
+    if let Some((numerator, denominator)) = text.split_once('/') {
+        let numerator = i64::from_str_radix(numerator, radix);
+        // The sign goes on the numerator only, `"1/-2"` isn't a ratio.
+        let unsigned = denominator.starts_with(|c: char| c.is_ascii_alphanumeric());
+        let denominator = i64::from_str_radix(denominator, radix);
+        return match (numerator, denominator) {
+            (Ok(n), Ok(d)) if unsigned && d != 0 => Token::ratio(n as i128, d as i128),
+            _ => Ok(Token::False),
+        };
+    }
+    // Only what the reader takes for a float, not Rust's `inf` or `NaN`.
+    let float = text.contains(|c: char| c.is_ascii_digit())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+    match text.parse::<f64>() {
+        Ok(number) if float && radix == 10 => Ok(Token::Float(number)),
+        Ok(_) if float && text.contains(['.', 'e', 'E']) => {
+            error("string->number reads floats only in radix 10")
+        }
+        _ => Ok(Token::False),
+    }
+}
+
+/// The digits of `number` in `radix`, with a `-` in front when it's negative.
+fn digits(number: i64, radix: u32) -> String {
+    let mut magnitude = number.unsigned_abs();
+    let mut digits = vec![];
+    loop {
+        let digit = std::char::from_digit((magnitude % radix as u64) as u32, radix);
+        digits.push(digit.unwrap());
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if number < 0 {
+        digits.push('-');
+    }
+    digits.into_iter().rev().collect()
+}
+
+/// `(number->string 255 16)`, floats only in radix 10.
+fn number_to_string(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("number->string", &args, 1, 2)?;
+    let radix = radix(&args)?;
+    match &args[0] {
+        Token::Int(_) | Token::Long(_) => {
+            Ok(Token::Str(digits(args[0].as_integer().unwrap(), radix)))
+        }
+        Token::Ratio(n, d) => Ok(Token::Str(format!(
+            "{}/{}",
+            digits(*n, radix),
+            digits(*d, radix)
+        ))),
+        Token::Float(_) if radix == 10 => Ok(Token::Str(args[0].to_string())),
+        Token::Float(_) => error("number->string writes floats only in radix 10"),
+        _ => error("number->string expects a number"),
+    }
+}
+
+/// `(symbol->string (quote abc))`
+fn symbol_to_string(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("symbol->string", &args, 1, 1)?;
+    match &args[0] {
+        Token::Symbol(symbol) => Ok(Token::Str(symbol.to_string())),
+        _ => error("symbol->string expects a symbol"),
+    }
+}
+
+/// `(string->symbol "abc")`
+fn string_to_symbol(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("string->symbol", &args, 1, 1)?;
+    match &args[0] {
+        Token::Str(text) if !text.is_empty() => Ok(Token::Symbol(Name::new(text))),
+        Token::Str(_) => error("string->symbol can't create an empty symbol"),
+        _ => error("string->symbol expects a string"),
+    }
+}
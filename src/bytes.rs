@@ -0,0 +1,97 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::system::allowed;
+use crate::{error, Eval, Token, Unwind};
+use std::fs;
+
+builtins! {
+    /// Finds the byte vector builtin called `name`.
+    "bytes" => bytes,
+    "bytes-len" => bytes_len,
+    "byte-ref" => byte_ref,
+    "string->bytes" => string_to_bytes,
+    "bytes->string" => bytes_to_string,
+    "read-bytes" => read_bytes,
+    "write-bytes" => write_bytes,
+}
+
+/// The bytes held by a byte vector argument.
+pub fn data<'a>(name: &str, value: &'a Token) -> Result<&'a [u8], Unwind> {
+    match value {
+        Token::Bytes(data) => Ok(data),
+        _ => error(format!("{} expects bytes, got {}", name, value)),
+    }
+}
+
+/// `(bytes 104 105)` builds a byte vector out of numbers from 0 to 255.
+fn bytes(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    args.iter()
+        .map(|arg| match arg {
+            Token::Int(i) if (0..=255).contains(i) => Ok(*i as u8),
+            _ => error(format!("bytes expects numbers from 0 to 255, got {}", arg)),
+        })
+        .collect::<Result<_, _>>()
+        .map(Token::Bytes)
+}
+
+/// `(bytes-len b)`
+fn bytes_len(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("bytes-len", &args, 1, 1)?;
+    Ok(Token::Int(data("bytes-len", &args[0])?.len() as i32))
+}
+
+/// `(byte-ref b 0)` evaluates to the byte at an index as a number.
+fn byte_ref(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("byte-ref", &args, 2, 2)?;
+    let data = data("byte-ref", &args[0])?;
+    match args[1] {
+        Token::Int(i) if i >= 0 && (i as usize) < data.len() => {
+            Ok(Token::Int(data[i as usize] as i32))
+        }
+        _ => error(format!(
+            "byte-ref index {} is out of bounds for {} bytes",
+            args[1],
+            data.len()
+        )),
+    }
+}
+
+/// `(string->bytes "hi")` encodes a string as UTF-8.
+fn string_to_bytes(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("string->bytes", &args, 1, 1)?;
+    Ok(Token::Bytes(
+        text("string->bytes", &args[0])?.as_bytes().to_vec(),
+    ))
+}
+
+/// `(bytes->string b)` decodes UTF-8, failing on invalid sequences rather than replacing them.
+fn bytes_to_string(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("bytes->string", &args, 1, 1)?;
+    match String::from_utf8(data("bytes->string", &args[0])?.to_vec()) {
+        Ok(s) => Ok(Token::Str(s)),
+        Err(e) => error(format!("bytes->string: {}", e)),
+    }
+}
+
+/// `(read-bytes "image.png")` reads a whole file into a byte vector.
+fn read_bytes(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("read-bytes", &args, 1, 1)?;
+    allowed("read-bytes", interpreter)?;
+    let path = text("read-bytes", &args[0])?;
+    match fs::read(path) {
+        Ok(data) => Ok(Token::Bytes(data)),
+        Err(e) => error(format!("read-bytes: can't read {:?}: {}", path, e)),
+    }
+}
+
+/// `(write-bytes "copy.png" b)` replaces the contents of a file.
+fn write_bytes(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("write-bytes", &args, 2, 2)?;
+    allowed("write-bytes", interpreter)?;
+    let path = text("write-bytes", &args[0])?;
+    match fs::write(path, data("write-bytes", &args[1])?) {
+        Ok(()) => Ok(Token::Nil),
+        Err(e) => error(format!("write-bytes: can't write {:?}: {}", path, e)),
+    }
+}
@@ -0,0 +1,206 @@
+//! Parsed programs cached in `.mlspc` files next to their source, so unchanged files skip
+//! lexing and parsing on the next run.
+
+use crate::formatter::{pretty, source};
+use crate::symbols::Name;
+use crate::{parse, Lexer, SyntaxError, Token};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"MLSPC3\n";
+
+/// The parsed form of the program at `path` whose source is `text`, read from its cache when
+/// the source is unchanged and cached otherwise. Programs with syntax errors aren't cached.
+pub fn parsed(path: &Path, text: String) -> Result<Vec<Token>, Vec<SyntaxError>> {
+    let cache = path.with_extension("mlspc");
+    let hash = fnv1a(text.as_bytes());
+    if let Some(ast) = fs::read(&cache).ok().and_then(|bytes| decode(&bytes, hash)) {
+        return Ok(ast);
+    }
+    let ast = parse(Lexer::new(text))?;
+    // The cache is only a speedup, e.g. a read-only directory shouldn't stop the program.
+    let _ = fs::write(&cache, encode(&ast, hash));
+    Ok(ast)
+}
+
+/// Hashes the source, stable across runs and builds unlike `DefaultHasher`.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn encode(ast: &[Token], hash: u64) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&hash.to_le_bytes());
+    bytes.extend_from_slice(&(ast.len() as u32).to_le_bytes());
+    for node in ast {
+        encode_node(node, &mut bytes);
+    }
+    bytes
+}
+
+fn encode_node(node: &Token, bytes: &mut Vec<u8>) {
+    match node {
+        Token::Int(i) => {
+            bytes.push(b'i');
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        Token::Long(i) => {
+            bytes.push(b'L');
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        Token::Float(f) => {
+            bytes.push(b'f');
+            bytes.extend_from_slice(&f.to_le_bytes());
+        }
+        Token::Symbol(name) => {
+            bytes.push(b's');
+            encode_text(name.as_str(), bytes);
+        }
+        Token::Str(s) => {
+            bytes.push(b't');
+            encode_text(s, bytes);
+        }
+        Token::True => bytes.push(b'T'),
+        Token::False => bytes.push(b'F'),
+        Token::List(items) => {
+            bytes.push(b'l');
+            bytes.extend_from_slice(&(items.len() as u32).to_le_bytes());
+            for item in items {
+                encode_node(item, bytes);
+            }
+        }
+        // The parser produces nothing else.
+        _ => unreachable!("{:?} in a parsed program", node),
+    }
+}
+
+fn encode_text(text: &str, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(text.as_bytes());
+}
+
+/// The program cached in `bytes`, `None` when it's corrupt or cached from another source.
+pub fn decode(bytes: &[u8], hash: u64) -> Option<Vec<Token>> {
+    let mut reader = Reader::new(bytes, false);
+    if reader.take(MAGIC.len())? != MAGIC || reader.u64()? != hash {
+        return None;
+    }
+    let ast = (0..reader.u32()?)
+        .map(|_| reader.node())
+        .collect::<Option<_>>()?;
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+    Some(ast)
+}
+
+/// For `disasm`: a listing of the records in the cache `bytes`, at their offsets in the file,
+/// then the program they make back as source code.
+pub fn disassemble(bytes: &[u8]) -> Result<String, String> {
+    let mut reader = Reader::new(bytes, true);
+    if reader.take(MAGIC.len()) != Some(MAGIC) {
+        return Err("not a parse cache, or one from another version".to_string());
+    }
+    reader.record_at(0, "magic MLSPC3");
+    let corrupt = |reader: &Reader| format!("corrupt at byte {}", reader.offset());
+    let hash = reader.u64().ok_or_else(|| corrupt(&reader))?;
+    reader.record_at(MAGIC.len(), &format!("source hash {:016x}", hash));
+    let count = reader.u32().ok_or_else(|| corrupt(&reader))?;
+    reader.record_at(MAGIC.len() + 8, &format!("forms {}", count));
+    let mut ast = vec![];
+    for _ in 0..count {
+        ast.push(reader.node().ok_or_else(|| corrupt(&reader))?);
+    }
+    if !reader.bytes.is_empty() {
+        return Err(format!("{} bytes after the program", reader.bytes.len()));
+    }
+    let listing = reader.listing.unwrap_or_default();
+    Ok(format!("{}\n\n{}", listing.join("\n"), pretty(&ast)))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    length: usize,
+    /// The records read so far, when disassembling.
+    listing: Option<Vec<String>>,
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], listing: bool) -> Self {
+        Reader {
+            bytes,
+            length: bytes.len(),
+            listing: if listing { Some(vec![]) } else { None },
+            depth: 0,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.length - self.bytes.len()
+    }
+
+    fn record_at(&mut self, offset: usize, text: &str) {
+        let indent = "  ".repeat(self.depth);
+        if let Some(listing) = &mut self.listing {
+            listing.push(format!("{:06x}  {}{}", offset, indent, text));
+        }
+    }
+
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < count {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn text(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).ok()
+    }
+
+    fn node(&mut self) -> Option<Token> {
+        let offset = self.offset();
+        let node = match self.take(1)?[0] {
+            b'i' => Token::Int(i32::from_le_bytes(self.take(4)?.try_into().ok()?)),
+            b'L' => Token::Long(i64::from_le_bytes(self.take(8)?.try_into().ok()?)),
+            b'f' => Token::Float(f64::from_le_bytes(self.take(8)?.try_into().ok()?)),
+            b's' => Token::Symbol(Name::new(&self.text()?)),
+            b't' => Token::Str(self.text()?),
+            b'T' => Token::True,
+            b'F' => Token::False,
+            b'l' => {
+                let count = self.u32()?;
+                self.record_at(offset, &format!("list {}", count));
+                self.depth += 1;
+                let items = (0..count).map(|_| self.node()).collect::<Option<_>>();
+                self.depth -= 1;
+                return Some(Token::List(items?));
+            }
+            _ => return None,
+        };
+        let text = match &node {
+            Token::Int(_) => format!("int {}", source(&node)),
+            Token::Long(_) => format!("long {}", source(&node)),
+            Token::Float(_) => format!("float {}", source(&node)),
+            Token::Symbol(_) => format!("symbol {}", source(&node)),
+            Token::Str(_) => format!("string {}", source(&node)),
+            _ => source(&node),
+        };
+        self.record_at(offset, &text);
+        Some(node)
+    }
+}
@@ -0,0 +1,210 @@
+//! Control-flow graphs for `--emit=cfg`: the basic blocks of each top-level function, and of
+//! the program's own top-level forms, with the edges between them as a Graphviz DOT graph.
+//!
+//! `if`, `when`, `unless`, `cond` and `while` forms in a body end a block with a branch, the
+//! other forms are steps of the block they're in, nested control flow like `(f (if a b c))`
+//! included.
+
+use crate::dot::escape;
+use crate::formatter::source;
+use crate::Token;
+
+/// A straight run of forms, entered at the top and left at the bottom.
+#[derive(Default)]
+struct Block {
+    steps: Vec<String>,
+    /// The test deciding which edge is taken, for a block ending in a branch.
+    test: Option<String>,
+    edges: Vec<(usize, &'static str)>,
+}
+
+#[derive(Default)]
+struct Graph {
+    blocks: Vec<Block>,
+}
+
+fn symbol(form: &Token) -> Option<&str> {
+    match form {
+        Token::Symbol(name) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+impl Graph {
+    fn block(&mut self) -> usize {
+        self.blocks.push(Block::default());
+        self.blocks.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &'static str) {
+        self.blocks[from].edges.push((to, label));
+    }
+
+    /// Ends `from` with a branch on `test`.
+    fn branch(&mut self, from: usize, test: &Token, taken: usize, not_taken: usize) {
+        self.blocks[from].test = Some(source(test));
+        self.edge(from, taken, "true");
+        self.edge(from, not_taken, "false");
+    }
+
+    /// Adds `forms`, evaluated in turn starting in block `current`, returning the block
+    /// control is in after them.
+    fn body(&mut self, forms: &[Token], mut current: usize) -> usize {
+        for form in forms {
+            current = self.form(form, current);
+        }
+        current
+    }
+
+    fn form(&mut self, form: &Token, current: usize) -> usize {
+        let list = match form {
+            Token::List(list) if !list.is_empty() => list,
+            _ => {
+                self.blocks[current].steps.push(source(form));
+                return current;
+            }
+        };
+        match (symbol(&list[0]), list.len()) {
+            (Some("if"), 3..=4) => {
+                let (then, otherwise, join) = (self.block(), self.block(), self.block());
+                self.branch(current, &list[1], then, otherwise);
+                let then = self.form(&list[2], then);
+                let otherwise = self.body(&list[3..], otherwise);
+                self.edge(then, join, "");
+                self.edge(otherwise, join, "");
+                join
+            }
+            (Some(head @ ("when" | "unless")), 2..) => {
+                let (body, join) = (self.block(), self.block());
+                match head {
+                    "when" => self.branch(current, &list[1], body, join),
+                    _ => self.branch(current, &list[1], join, body),
+                }
+                let body = self.body(&list[2..], body);
+                self.edge(body, join, "");
+                join
+            }
+            (Some("cond"), _) => {
+                let join = self.block();
+                let mut test = current;
+                for clause in &list[1..] {
+                    let clause = match clause {
+                        Token::List(clause) if !clause.is_empty() => clause,
+                        _ => continue,
+                    };
+                    if symbol(&clause[0]) == Some("else") {
+                        let body = self.body(&clause[1..], test);
+                        self.edge(body, join, "");
+                        return join;
+                    }
+                    let (body, next) = (self.block(), self.block());
+                    self.branch(test, &clause[0], body, next);
+                    let body = self.body(&clause[1..], body);
+                    self.edge(body, join, "");
+                    test = next;
+                }
+                self.edge(test, join, "");
+                join
+            }
+            (Some("while"), 3) => {
+                let (header, body, exit) = (self.block(), self.block(), self.block());
+                self.edge(current, header, "");
+                self.branch(header, &list[1], body, exit);
+                let body = self.form(&list[2], body);
+                self.edge(body, header, "loop");
+                exit
+            }
+            (Some("do"), _) => self.body(&list[1..], current),
+            (Some("let"), 3..) if matches!(list[1], Token::List(_)) => {
+                if let Token::List(bindings) = &list[1] {
+                    for binding in bindings {
+                        match binding {
+                            Token::List(pair) if pair.len() == 2 => {
+                                let step = format!("{} = {}", source(&pair[0]), source(&pair[1]));
+                                self.blocks[current].steps.push(step);
+                            }
+                            other => self.blocks[current].steps.push(source(other)),
+                        }
+                    }
+                }
+                self.body(&list[2..], current)
+            }
+            _ => {
+                self.blocks[current].steps.push(source(form));
+                current
+            }
+        }
+    }
+
+    /// The graph as a DOT cluster named `name`, its nodes prefixed with `prefix`.
+    fn cluster(&self, name: &str, prefix: &str) -> Vec<String> {
+        let mut lines = vec![
+            format!("  subgraph cluster_{} {{", prefix),
+            format!("    label=\"{}\";", escape(name)),
+        ];
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("b{}\\l", index);
+            for step in &block.steps {
+                label += &format!("{}\\l", escape(step));
+            }
+            if let Some(test) = &block.test {
+                label += &format!("if {}\\l", escape(test));
+            }
+            lines.push(format!("    {}_b{} [label=\"{}\"];", prefix, index, label));
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            for (to, label) in &block.edges {
+                let attributes = match *label {
+                    "" => String::new(),
+                    label => format!(" [label=\"{}\"]", label),
+                };
+                lines.push(format!(
+                    "    {}_b{} -> {}_b{}{};",
+                    prefix, index, prefix, to, attributes
+                ));
+            }
+        }
+        lines.push("  }".to_string());
+        lines
+    }
+}
+
+/// The control-flow graphs of the functions `ast` defines at the top level, then of the rest
+/// of its top-level forms, `dot -Tsvg` renders them.
+pub fn graph(ast: &[Token]) -> String {
+    let mut lines = vec![
+        "digraph cfg {".to_string(),
+        "  node [shape=box, fontname=\"monospace\"];".to_string(),
+    ];
+    let mut program = Graph::default();
+    let mut current = program.block();
+    let mut functions = 0;
+    for form in ast {
+        let function = match form {
+            Token::List(list) if list.len() > 2 && symbol(&list[0]) == Some("define") => {
+                match &list[1] {
+                    Token::List(signature) if !signature.is_empty() => {
+                        Some((source(&signature[0]), &list[2..]))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match function {
+            Some((name, body)) => {
+                let mut graph = Graph::default();
+                let entry = graph.block();
+                graph.body(body, entry);
+                functions += 1;
+                lines.extend(graph.cluster(&name, &format!("f{}", functions)));
+            }
+            None => current = program.form(form, current),
+        }
+    }
+    if program.blocks.len() > 1 || !program.blocks[0].steps.is_empty() {
+        lines.extend(program.cluster("program", "top"));
+    }
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,82 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::{error, Eval, Token, Unwind};
+
+builtins! {
+    /// Finds the complex number builtin called `name`.
+    "complex" => complex,
+    "real-part" => real_part,
+    "imag-part" => imag_part,
+    "magnitude" => magnitude,
+}
+
+/// The real and imaginary parts of a number.
+type Parts = (f64, f64);
+
+/// The parts of a number, `None` for anything else.
+fn parts(value: &Token) -> Option<Parts> {
+    match value {
+        Token::Complex(re, im) => Some((*re, *im)),
+        _ => value.as_float().map(|re| (re, 0.0)),
+    }
+}
+
+/// `op` applied to two numbers when either is complex, `None` when neither is or one isn't a
+/// number, leaving them to the other kinds of arithmetic.
+pub fn combine(lhs: &Token, rhs: &Token, op: fn(Parts, Parts) -> Parts) -> Option<Token> {
+    if !matches!(lhs, Token::Complex(..)) && !matches!(rhs, Token::Complex(..)) {
+        return None;
+    }
+    let (re, im) = op(parts(lhs)?, parts(rhs)?);
+    Some(Token::Complex(re, im))
+}
+
+pub fn add((a, b): Parts, (c, d): Parts) -> Parts {
+    (a + c, b + d)
+}
+
+pub fn sub((a, b): Parts, (c, d): Parts) -> Parts {
+    (a - c, b - d)
+}
+
+pub fn mul((a, b): Parts, (c, d): Parts) -> Parts {
+    (a * c - b * d, a * d + b * c)
+}
+
+pub fn div((a, b): Parts, (c, d): Parts) -> Parts {
+    let norm = c * c + d * d;
+    ((a * c + b * d) / norm, (b * c - a * d) / norm)
+}
+
+fn complex_arg(name: &str, args: &[Token]) -> Result<Parts, Unwind> {
+    arity(name, args, 1, 1)?;
+    match parts(&args[0]) {
+        Some(parts) => Ok(parts),
+        None => error(format!("{} expects a number", name)),
+    }
+}
+
+/// `(complex 1 2)` is 1+2i.
+fn complex(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("complex", &args, 2, 2)?;
+    match (args[0].as_float(), args[1].as_float()) {
+        (Some(re), Some(im)) => Ok(Token::Complex(re, im)),
+        _ => error("complex expects real numbers"),
+    }
+}
+
+/// `(real-part (complex 1 2))` is 1.0, real numbers are their own real part.
+fn real_part(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Float(complex_arg("real-part", &args)?.0))
+}
+
+/// `(imag-part (complex 1 2))` is 2.0, 0.0 for real numbers.
+fn imag_part(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Float(complex_arg("imag-part", &args)?.1))
+}
+
+/// `(magnitude (complex 3 4))` is 5.0, the distance from zero.
+fn magnitude(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let (re, im) = complex_arg("magnitude", &args)?;
+    Ok(Token::Float(re.hypot(im)))
+}
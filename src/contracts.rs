@@ -0,0 +1,74 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::symbols::Name;
+use crate::{error, Eval, Token};
+
+builtins! {
+    /// Finds the type checking builtin called `name`.
+    "type-of" => type_of,
+    "assert-type" => assert_type,
+}
+
+/// Whether `value` is of the type called `expected`, `number` standing for any kind of number.
+fn is(value: &Token, expected: &str) -> bool {
+    match expected {
+        "number" => value.as_float().is_some() || value.type_name() == "complex",
+        _ => value.type_name() == expected,
+    }
+}
+
+/// `(type-of 1.5)` is the symbol `float`.
+fn type_of(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("type-of", &args, 1, 1)?;
+    Ok(Token::Symbol(Name::new(args[0].type_name())))
+}
+
+/// `(assert-type n 'int "n must be an integer")` is `n` when it's an int, and an error saying
+/// what it is instead otherwise.
+fn assert_type(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("assert-type", &args, 2, 3)?;
+    let expected = match &args[1] {
+        Token::Symbol(name) => name.as_str(),
+        _ => return error("assert-type expects the name of a type"),
+    };
+    if is(&args[0], expected) {
+        return Ok(args[0].clone());
+    }
+    let problem = format!("expected {}, got {}", expected, args[0].type_name());
+    match args.get(2) {
+        Some(message) => error(format!("{}: {}", text("assert-type", message)?, problem)),
+        None => error(problem),
+    }
+}
+
+/// The parameters of the function `name` without their contracts, `n:int` becoming `n`, and the
+/// checks of their arguments to run at the start of each call.
+pub fn parameters(name: &Token, params: &[Token]) -> (Vec<Token>, Vec<Token>) {
+    let mut checks = vec![];
+    let params = params
+        .iter()
+        .map(|param| {
+            let annotated = match param {
+                Token::Symbol(symbol) => symbol.as_str().split_once(':'),
+                _ => None,
+            };
+            match annotated {
+                Some((param, expected)) if !param.is_empty() && !expected.is_empty() => {
+                    let param = Token::Symbol(Name::new(param));
+                    let expected = Token::Symbol(Name::new(expected));
+                    let message = format!("argument {} of {}", param, name);
+                    checks.push(Token::List(vec![
+                        Token::Symbol(Name::new("assert-type")),
+                        param.clone(),
+                        Token::List(vec![Token::Symbol(Name::new("quote")), expected]),
+                        Token::Str(message),
+                    ]));
+                    param
+                }
+                _ => param.clone(),
+            }
+        })
+        .collect();
+    (params, checks)
+}
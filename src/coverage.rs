@@ -0,0 +1,241 @@
+//! `--coverage`: which lines of a program ran. Each form in a place where code runs is wrapped
+//! in a `(#covered line form)`, which the interpreter counts before evaluating the form. The
+//! tag can't be written in programs, `#` starts a reader literal.
+
+use crate::highlight::escape_html;
+use crate::interpreter::Interpreter;
+use crate::symbols::Name;
+use crate::{Lexer, Token};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::iter::Peekable;
+use std::vec;
+
+struct Instrumenter<'a> {
+    /// The line each list of the program starts on, in the order they're written.
+    lines: Peekable<vec::IntoIter<usize>>,
+    interpreter: &'a Interpreter,
+    /// The macros the program defines, whose arguments are code only they know how to read.
+    macros: HashSet<Name>,
+    instrumented: BTreeSet<usize>,
+}
+
+/// `ast`, parsed from `source`, with each form that runs marking its line as run. Also the lines
+/// with such forms, out of which the ones that didn't run weren't covered.
+pub fn instrument(
+    ast: &[Token],
+    source: &str,
+    interpreter: &Interpreter,
+) -> (Vec<Token>, BTreeSet<usize>) {
+    // Each list comes from a `(`, a `'`-like prefix or a reader literal.
+    let lines: Vec<usize> = Lexer::new(source.to_string())
+        .spanned()
+        .0
+        .into_iter()
+        .filter(|(token, _)| matches!(token, Token::Open | Token::Quote(_) | Token::List(_)))
+        .map(|(_, span)| span.line)
+        .collect();
+    let macros = ast
+        .iter()
+        .filter_map(|form| match form {
+            Token::List(list) if list.len() > 1 && head(list) == Some("defmacro") => {
+                match &list[1] {
+                    Token::List(signature) => match signature.first() {
+                        Some(Token::Symbol(name)) => Some(*name),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+    let mut instrumenter = Instrumenter {
+        lines: lines.into_iter().peekable(),
+        interpreter,
+        macros,
+        instrumented: BTreeSet::new(),
+    };
+    let ast = ast.iter().map(|form| instrumenter.code(form)).collect();
+    (ast, instrumenter.instrumented)
+}
+
+fn head(list: &[Token]) -> Option<&str> {
+    match list.first() {
+        Some(Token::Symbol(name)) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+impl<'a> Instrumenter<'a> {
+    /// `node` as it is.
+    fn data(&mut self, node: &Token) -> Token {
+        self.skip(node);
+        node.clone()
+    }
+
+    /// Goes past the lines of the lists in `node`.
+    fn skip(&mut self, node: &Token) {
+        if let Token::List(items) = node {
+            self.lines.next();
+            items.iter().for_each(|item| self.skip(item));
+        }
+    }
+
+    /// `node` with `item` applied to each of its items, by position, when it's a list.
+    fn list(
+        &mut self,
+        node: &Token,
+        mut item: impl FnMut(&mut Self, usize, &Token) -> Token,
+    ) -> Token {
+        match node {
+            Token::List(items) => {
+                self.lines.next();
+                Token::List(
+                    items
+                        .iter()
+                        .enumerate()
+                        .map(|(index, node)| item(self, index, node))
+                        .collect(),
+                )
+            }
+            _ => node.clone(),
+        }
+    }
+
+    /// The code `node`, marking its line when it runs.
+    fn code(&mut self, node: &Token) -> Token {
+        let items = match node {
+            Token::List(items) if !items.is_empty() => items,
+            _ => return self.data(node),
+        };
+        let line = self.lines.peek().copied().unwrap_or(0);
+        let form = self.form(node, items);
+        self.instrumented.insert(line);
+        Token::List(vec![
+            Token::Symbol(Name::new("#covered")),
+            Token::Int(line as i32),
+            form,
+        ])
+    }
+
+    /// The list `node`, with the code among its `items` instrumented, depending on what form it
+    /// is.
+    fn form(&mut self, node: &Token, items: &[Token]) -> Token {
+        let code = |this: &mut Self, _: usize, node: &Token| this.code(node);
+        // Bindings are `(name value)` lists.
+        let binding = |this: &mut Self, _: usize, node: &Token| {
+            this.list(node, |this, index, node| match index {
+                1 => this.code(node),
+                _ => this.data(node),
+            })
+        };
+        let bindings = |this: &mut Self, _: usize, node: &Token| this.list(node, binding);
+        let named_let = matches!(items.get(1), Some(Token::Symbol(_)));
+        match head(items) {
+            Some("quote" | "quasiquote" | "defmacro" | "define-reader") => self.data(node),
+            Some(name) if self.macros.contains(&Name::new(name)) => self.data(node),
+            Some(name) if self.interpreter.is_macro(&Name::new(name)) => self.data(node),
+            Some("lambda" | "define") => self.list(node, |this, index, node| match index {
+                0 | 1 => this.data(node),
+                _ => this.code(node),
+            }),
+            Some("let") if named_let => self.list(node, |this, index, node| match index {
+                0 | 1 => this.data(node),
+                2 => bindings(this, index, node),
+                _ => this.code(node),
+            }),
+            Some("let" | "letrec") => self.list(node, |this, index, node| match index {
+                0 => this.data(node),
+                1 => bindings(this, index, node),
+                _ => this.code(node),
+            }),
+            Some("let-values") => self.list(node, |this, index, node| match index {
+                0 => this.data(node),
+                1 => binding(this, index, node),
+                _ => this.code(node),
+            }),
+            Some("cond") => self.list(node, |this, index, node| match index {
+                0 => this.data(node),
+                _ => this.list(node, code),
+            }),
+            Some("case") => self.list(node, |this, index, node| match index {
+                0 => this.data(node),
+                1 => this.code(node),
+                _ => this.list(node, |this, index, node| match index {
+                    0 => this.data(node),
+                    _ => this.code(node),
+                }),
+            }),
+            Some("try") => self.list(node, |this, index, node| match index {
+                0 => this.data(node),
+                index if index == items.len() - 1 => {
+                    this.list(node, |this, index, node| match index {
+                        0 | 1 => this.data(node),
+                        _ => this.code(node),
+                    })
+                }
+                _ => this.code(node),
+            }),
+            _ => self.list(node, code),
+        }
+    }
+}
+
+/// Each line with code, and how many times forms on it ran.
+fn hits<'a>(
+    instrumented: &'a BTreeSet<usize>,
+    covered: &'a HashMap<usize, usize>,
+) -> impl Iterator<Item = (usize, usize)> + 'a {
+    instrumented
+        .iter()
+        .map(move |line| (*line, covered.get(line).copied().unwrap_or(0)))
+}
+
+/// The report in the lcov format coverage tools read, for the program at `path`.
+pub fn lcov(path: &str, instrumented: &BTreeSet<usize>, covered: &HashMap<usize, usize>) -> String {
+    let mut report = format!("TN:\nSF:{}\n", path);
+    for (line, count) in hits(instrumented, covered) {
+        report += &format!("DA:{},{}\n", line, count);
+    }
+    let run = hits(instrumented, covered)
+        .filter(|(_, count)| *count > 0)
+        .count();
+    report + &format!("LF:{}\nLH:{}\nend_of_record\n", instrumented.len(), run)
+}
+
+/// The report as a web page of the program's `source`, with the lines that ran in green and
+/// the ones that didn't in red.
+pub fn html(
+    path: &str,
+    source: &str,
+    instrumented: &BTreeSet<usize>,
+    covered: &HashMap<usize, usize>,
+) -> String {
+    let run = hits(instrumented, covered)
+        .filter(|(_, count)| *count > 0)
+        .count();
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Coverage of {0}</title>\n\
+         <style>.run {{ background: #dfd; }} .missed {{ background: #fdd; }}</style>\n\
+         </head>\n<body>\n<h1>{0}</h1>\n<p>{1} of {2} lines run</p>\n<pre>\n",
+        escape_html(path),
+        run,
+        instrumented.len()
+    );
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let (class, count) = match covered.get(&line) {
+            _ if !instrumented.contains(&line) => ("", String::new()),
+            Some(count) => (" class=\"run\"", count.to_string()),
+            None => (" class=\"missed\"", "0".to_string()),
+        };
+        page += &format!(
+            "<span{}>{:>4} {:>6}  {}</span>\n",
+            class,
+            line,
+            count,
+            escape_html(text)
+        );
+    }
+    page + "</pre>\n</body>\n</html>\n"
+}
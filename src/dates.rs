@@ -0,0 +1,187 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::trace::Event;
+use crate::{error, Eval, Token};
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+builtins! {
+    /// Finds the date builtin called `name`. Dates are Unix timestamps in seconds, always in UTC.
+    "date-now" => date_now,
+    "date-format" => date_format,
+    "date-parse" => date_parse,
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Broken down UTC date.
+struct Date {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+impl Date {
+    fn from_timestamp(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(SECONDS_PER_DAY);
+        let seconds = timestamp.rem_euclid(SECONDS_PER_DAY);
+        // Converts days since the epoch to a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            year: year_of_era + era * 400 + if month <= 2 { 1 } else { 0 },
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds % 3600 / 60,
+            second: seconds % 60,
+        }
+    }
+
+    fn to_timestamp(&self) -> i64 {
+        // The inverse of `from_timestamp`, see
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let mp = if self.month > 2 {
+            self.month - 3
+        } else {
+            self.month + 9
+        };
+        let day_of_year = (153 * mp + 2) / 5 + self.day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        days * SECONDS_PER_DAY + self.hour * 3600 + self.minute * 60 + self.second
+    }
+}
+
+fn timestamp(value: i64) -> Eval {
+    match i32::try_from(value) {
+        Ok(timestamp) => Ok(Token::Int(timestamp)),
+        Err(_) => error("date is out of the integer range"),
+    }
+}
+
+/// `(date-now)`
+fn date_now(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("date-now", &args, 0, 0)?;
+    let event = interpreter.traced("the time", |_| {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => Ok(Event::Time(elapsed.as_secs() as i64)),
+            Err(_) => error("system clock is set before 1970"),
+        }
+    })?;
+    match event {
+        Event::Time(seconds) => timestamp(seconds),
+        _ => unreachable!("traced checks the kind of events"),
+    }
+}
+
+/// `(date-format ts "%Y-%m-%d %H:%M:%S")`, `%%` is a literal percent sign.
+fn date_format(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("date-format", &args, 2, 2)?;
+    let date = match args[0] {
+        Token::Int(timestamp) => Date::from_timestamp(timestamp as i64),
+        _ => return error("date-format expects a timestamp"),
+    };
+    let mut formatted = String::new();
+    let mut format = text("date-format", &args[1])?.chars();
+    while let Some(c) = format.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        let field = match format.next() {
+            Some('Y') => format!("{:04}", date.year),
+            Some('m') => format!("{:02}", date.month),
+            Some('d') => format!("{:02}", date.day),
+            Some('H') => format!("{:02}", date.hour),
+            Some('M') => format!("{:02}", date.minute),
+            Some('S') => format!("{:02}", date.second),
+            Some('%') => "%".to_string(),
+            other => return unknown_directive("date-format", other),
+        };
+        formatted.push_str(&field);
+    }
+    Ok(Token::Str(formatted))
+}
+
+/// `(date-parse "2021-03-04" "%Y-%m-%d")`, fields missing from the format default to the
+/// start of the epoch.
+fn date_parse(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("date-parse", &args, 2, 2)?;
+    let input = text("date-parse", &args[0])?;
+    let mut rest = input;
+    let mut date = Date::from_timestamp(0);
+    let mut format = text("date-parse", &args[1])?.chars();
+    while let Some(c) = format.next() {
+        if c != '%' {
+            rest = match rest.strip_prefix(c) {
+                Some(rest) => rest,
+                None => return mismatch(input),
+            };
+            continue;
+        }
+        let (field, width) = match format.next() {
+            Some('Y') => (&mut date.year, 4),
+            Some('m') => (&mut date.month, 2),
+            Some('d') => (&mut date.day, 2),
+            Some('H') => (&mut date.hour, 2),
+            Some('M') => (&mut date.minute, 2),
+            Some('S') => (&mut date.second, 2),
+            Some('%') => {
+                rest = match rest.strip_prefix('%') {
+                    Some(rest) => rest,
+                    None => return mismatch(input),
+                };
+                continue;
+            }
+            other => return unknown_directive("date-parse", other),
+        };
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return mismatch(input);
+        }
+        let digits = digits.min(width);
+        *field = rest[..digits].parse().unwrap();
+        rest = &rest[digits..];
+    }
+    if !rest.is_empty()
+        || !(1..=12).contains(&date.month)
+        || !(1..=31).contains(&date.day)
+        || date.hour > 23
+        || date.minute > 59
+        || date.second > 59
+    {
+        return mismatch(input);
+    }
+    timestamp(date.to_timestamp())
+}
+
+fn mismatch(input: &str) -> Eval {
+    error(format!("date-parse: {:?} doesn't match the format", input))
+}
+
+fn unknown_directive(name: &str, directive: Option<char>) -> Eval {
+    match directive {
+        Some(directive) => error(format!("{}: unknown directive %{}", name, directive)),
+        None => error(format!("{}: format ends with a lone %", name)),
+    }
+}
@@ -0,0 +1,170 @@
+//! Errors rendered the way rustc does: the message, where it happened, the line of source with
+//! carets under the problem, and a note on how to fix it. Or as JSON, one error per line, for
+//! editors and CI.
+
+use crate::json::Json;
+use crate::{Lexer, Span, SyntaxError, Token};
+use std::io::{self, IsTerminal};
+
+/// How errors are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Plain,
+    Colored,
+    Json,
+}
+
+impl Style {
+    /// The style asked for with `--color=auto|always|never` and `--error-format=human|json`,
+    /// `None` for anything else. `auto` colors errors when stderr is a terminal.
+    pub fn parse(color: &str, format: &str) -> Option<Style> {
+        let colored = match color {
+            "auto" => io::stderr().is_terminal(),
+            "always" => true,
+            "never" => false,
+            _ => return None,
+        };
+        match format {
+            "human" if colored => Some(Style::Colored),
+            "human" => Some(Style::Plain),
+            "json" => Some(Style::Json),
+            _ => None,
+        }
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// An error about part of a program.
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub note: Option<String>,
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(error: &SyntaxError) -> Self {
+        Self {
+            message: error.message.clone(),
+            span: error.span,
+            note: error.note.map(str::to_string),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// An error running a program, which is only known to be somewhere in the top-level form
+    /// spanning `form`.
+    pub fn runtime(message: String, form: Span) -> Self {
+        Self {
+            message,
+            span: form,
+            note: Some("while evaluating this form".to_string()),
+        }
+    }
+
+    /// The error with the line of `source`, the program in the file at `path`, it's about.
+    pub fn render(&self, path: &str, source: &str, colored: bool) -> String {
+        let paint = |color: &str, text: &str| {
+            if colored {
+                format!("{}{}{}", color, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+        let Span { line, column, .. } = self.span;
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        // Carets up to the end of the span or its first line, whichever comes first.
+        let rest = text.chars().count().saturating_sub(column - 1);
+        let width = (self.span.end - self.span.start).clamp(1, rest.max(1));
+        let gutter = " ".repeat(line.to_string().len());
+        let bar = paint(BLUE, &format!("{} |", gutter));
+        let mut rendered = format!(
+            "{}{}\n",
+            paint(RED, "error"),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        rendered += &format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            paint(BLUE, "-->"),
+            path,
+            line,
+            column
+        );
+        rendered += &format!("{}\n", bar);
+        rendered += &format!("{} {}\n", paint(BLUE, &format!("{} |", line)), text);
+        rendered += &format!(
+            "{} {}{}\n",
+            bar,
+            " ".repeat(column - 1),
+            paint(RED, &"^".repeat(width))
+        );
+        if let Some(note) = &self.note {
+            rendered += &format!(
+                "{} {}: {}\n",
+                paint(BLUE, &format!("{} =", gutter)),
+                paint(BOLD, "note"),
+                note
+            );
+        }
+        rendered
+    }
+
+    /// The error as a JSON object, with the 1-based line and column it starts at and its range
+    /// of character offsets.
+    pub fn json(&self, path: &str) -> Json {
+        let number = |n: usize| Json::Number(n as f64);
+        Json::object(vec![
+            ("severity", Json::Str("error".to_string())),
+            ("message", Json::Str(self.message.clone())),
+            ("file", Json::Str(path.to_string())),
+            ("line", number(self.span.line)),
+            ("column", number(self.span.column)),
+            ("start", number(self.span.start)),
+            ("end", number(self.span.end)),
+            ("note", self.note.clone().map_or(Json::Null, Json::Str)),
+        ])
+    }
+}
+
+/// `diagnostics` written in `style`, about `source`, the program in the file at `path`.
+pub fn report(diagnostics: &[Diagnostic], path: &str, source: &str, style: Style) -> String {
+    let written: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| match style {
+            Style::Json => format!("{}\n", diagnostic.json(path)),
+            _ => diagnostic.render(path, source, style == Style::Colored),
+        })
+        .collect();
+    // People get a blank line between errors, tools one per line.
+    written.join(if style == Style::Json { "" } else { "\n" })
+}
+
+/// Where each top-level form of a program that parses is.
+pub fn forms(source: &str) -> Vec<Span> {
+    let mut forms = vec![];
+    let mut depth = 0;
+    let mut start: Option<Span> = None;
+    for (token, span) in Lexer::new(source.to_string()).spanned().0 {
+        let first = *start.get_or_insert(span);
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => depth -= 1,
+            // The quoted expression is part of the form too.
+            Token::Quote(_) => continue,
+            _ => {}
+        }
+        if depth == 0 {
+            forms.push(Span {
+                end: span.end,
+                ..first
+            });
+            start = None;
+        }
+    }
+    forms
+}
@@ -0,0 +1,70 @@
+//! Markdown documentation for programs, taken from the docstrings of their `define`s: a string
+//! starting the body of a function.
+
+use crate::formatter::source;
+use crate::{parse, report, Lexer, Token};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Writes a page for each `.mlsp` file in `sources` to `out`, along with an index linking them.
+/// Returns the pages written.
+pub fn generate(sources: &Path, out: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut modules = vec![];
+    for entry in fs::read_dir(sources)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "mlsp")
+        {
+            modules.push(path);
+        }
+    }
+    modules.sort();
+    fs::create_dir_all(out)?;
+    let mut pages = vec![];
+    let mut index = String::from("# Modules\n\n");
+    for module in modules {
+        let name = module.file_stem().unwrap_or_default().to_string_lossy();
+        let ast = parse(Lexer::new(fs::read_to_string(&module)?)).map_err(|errors| {
+            let message = format!("{}: {}", module.display(), report(&errors));
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })?;
+        let page = out.join(format!("{}.md", name));
+        fs::write(&page, document(&name, &ast))?;
+        index += &format!("* [{}]({}.md)\n", name, name);
+        pages.push(page);
+    }
+    let page = out.join("index.md");
+    fs::write(&page, index)?;
+    pages.push(page);
+    Ok(pages)
+}
+
+/// The Markdown page of a module: a section for each of its top-level definitions.
+pub fn document(name: &str, ast: &[Token]) -> String {
+    let mut page = format!("# {}\n", name);
+    for node in ast {
+        let definition = match node {
+            Token::List(items) if items.len() > 2 && is_define(&items[0]) => items,
+            _ => continue,
+        };
+        let (signature, doc) = match &definition[1] {
+            Token::List(_) => match &definition[2] {
+                // A lone string is what the function returns, not its documentation.
+                Token::Str(doc) if definition.len() > 3 => (source(&definition[1]), Some(doc)),
+                _ => (source(&definition[1]), None),
+            },
+            name => (source(name), None),
+        };
+        page += &format!("\n## `{}`\n", signature);
+        if let Some(doc) = doc {
+            page += &format!("\n{}\n", doc.trim());
+        }
+    }
+    page
+}
+
+fn is_define(head: &Token) -> bool {
+    matches!(head, Token::Symbol(name) if name.as_str() == "define")
+}
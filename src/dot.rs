@@ -0,0 +1,45 @@
+//! The parse tree of a program as a Graphviz DOT graph, for `--emit=dot`: each list is a node
+//! with an edge to each of its items, in order, and atoms are labeled with their source.
+
+use crate::formatter::source;
+use crate::Token;
+
+/// `text` escaped for a double-quoted DOT string.
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The graph of `ast` under a `program` node, `dot -Tsvg` renders it.
+pub fn graph(ast: &[Token]) -> String {
+    let mut lines = vec![
+        "digraph program {".to_string(),
+        "  ordering=out;".to_string(),
+        "  node [shape=box, fontname=\"monospace\"];".to_string(),
+        "  n0 [label=\"program\", shape=ellipse];".to_string(),
+    ];
+    let mut count = 1;
+    for node in ast {
+        add(node, 0, &mut count, &mut lines);
+    }
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+}
+
+/// Adds `node` and its items, numbering them from `count`, with an edge from `parent`.
+fn add(node: &Token, parent: usize, count: &mut usize, lines: &mut Vec<String>) {
+    let id = *count;
+    *count += 1;
+    match node {
+        Token::List(items) => {
+            lines.push(format!("  n{} [label=\"()\", shape=circle];", id));
+            lines.push(format!("  n{} -> n{};", parent, id));
+            for item in items {
+                add(item, id, count, lines);
+            }
+        }
+        atom => {
+            lines.push(format!("  n{} [label=\"{}\"];", id, escape(&source(atom))));
+            lines.push(format!("  n{} -> n{};", parent, id));
+        }
+    }
+}
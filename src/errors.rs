@@ -0,0 +1,89 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::symbols::Name;
+use crate::{error, remap_bool, Eval, Token, Unwind};
+use std::rc::Rc;
+
+/// An error made by `error`, or a runtime error caught by `try`: its type to dispatch on, a
+/// message for people and any value with the details.
+#[derive(Debug, PartialEq, Hash)]
+pub struct Failure {
+    pub kind: Name,
+    pub message: String,
+    pub data: Token,
+}
+
+impl Failure {
+    /// A runtime error raised by the interpreter or a builtin, of type `error`.
+    pub fn runtime(message: String) -> Token {
+        Failure::of("error", message)
+    }
+
+    /// An error of type `kind` without details.
+    pub fn of(kind: &str, message: String) -> Token {
+        Token::Error(Rc::new(Failure {
+            kind: Name::new(kind),
+            message,
+            data: Token::Nil,
+        }))
+    }
+}
+
+builtins! {
+    /// Finds the error handling builtin called `name`.
+    "error" => make_error,
+    "throw" => throw,
+    "error?" => is_error,
+    "error-type" => error_type,
+    "error-message" => error_message,
+    "error-data" => error_data,
+}
+
+/// `(error 'not-found "missing key" key)` is an error of type `not-found`, the details after the
+/// message are optional.
+fn make_error(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("error", &args, 2, 3)?;
+    let kind = match &args[0] {
+        Token::Symbol(kind) => *kind,
+        _ => return error("error expects a symbol naming its type"),
+    };
+    Ok(Token::Error(Rc::new(Failure {
+        kind,
+        message: text("error", &args[1])?.to_string(),
+        data: args.get(2).cloned().unwrap_or(Token::Nil),
+    })))
+}
+
+/// `(throw e)` unwinds up to the innermost `try`, which passes `e` to its `catch` clause.
+fn throw(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("throw", &args, 1, 1)?;
+    Err(Unwind::Thrown(args[0].clone()))
+}
+
+fn is_error(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("error?", &args, 1, 1)?;
+    Ok(remap_bool(matches!(args[0], Token::Error(_))))
+}
+
+fn failure<'a>(name: &str, args: &'a [Token]) -> Result<&'a Failure, Unwind> {
+    arity(name, args, 1, 1)?;
+    match &args[0] {
+        Token::Error(failure) => Ok(failure),
+        _ => error(format!("{} expects an error", name)),
+    }
+}
+
+/// `(error-type e)` is the symbol the error was made with, `error` for runtime errors.
+fn error_type(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Symbol(failure("error-type", &args)?.kind))
+}
+
+fn error_message(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Str(failure("error-message", &args)?.message.clone()))
+}
+
+/// `(error-data e)` is the value with the error's details, nil when it was made without one.
+fn error_data(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(failure("error-data", &args)?.data.clone())
+}
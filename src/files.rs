@@ -0,0 +1,83 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::system::allowed;
+use crate::{error, remap_bool, Eval, Token};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+builtins! {
+    /// Finds the filesystem builtin called `name`.
+    "file-exists?" => file_exists,
+    "list-dir" => list_dir,
+    "mkdir" => mkdir,
+    "delete-file" => delete_file,
+    "file-size" => file_size,
+}
+
+/// `(file-exists? "notes.txt")` is true for existing files and directories.
+fn file_exists(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("file-exists?", &args, 1, 1)?;
+    allowed("file-exists?", interpreter)?;
+    Ok(remap_bool(
+        Path::new(text("file-exists?", &args[0])?).exists(),
+    ))
+}
+
+/// `(list-dir ".")` evaluates to the sorted names of the entries in a directory.
+fn list_dir(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("list-dir", &args, 1, 1)?;
+    allowed("list-dir", interpreter)?;
+    let path = text("list-dir", &args[0])?;
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => return error(format!("list-dir: can't read {:?}: {}", path, e)),
+    };
+    let mut names = vec![];
+    for entry in entries {
+        match entry {
+            Ok(entry) => names.push(entry.file_name().to_string_lossy().into_owned()),
+            Err(e) => return error(format!("list-dir: can't read {:?}: {}", path, e)),
+        }
+    }
+    names.sort();
+    Ok(Token::List(names.into_iter().map(Token::Str).collect()))
+}
+
+/// `(mkdir "out/reports")` creates a directory along with its missing parents.
+fn mkdir(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("mkdir", &args, 1, 1)?;
+    allowed("mkdir", interpreter)?;
+    let path = text("mkdir", &args[0])?;
+    match fs::create_dir_all(path) {
+        Ok(()) => Ok(Token::Nil),
+        Err(e) => error(format!("mkdir: can't create {:?}: {}", path, e)),
+    }
+}
+
+/// `(delete-file "notes.txt")` removes a file, not a directory.
+fn delete_file(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("delete-file", &args, 1, 1)?;
+    allowed("delete-file", interpreter)?;
+    let path = text("delete-file", &args[0])?;
+    match fs::remove_file(path) {
+        Ok(()) => Ok(Token::Nil),
+        Err(e) => error(format!("delete-file: can't delete {:?}: {}", path, e)),
+    }
+}
+
+/// `(file-size "notes.txt")` evaluates to the size of a file in bytes.
+fn file_size(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("file-size", &args, 1, 1)?;
+    allowed("file-size", interpreter)?;
+    let path = text("file-size", &args[0])?;
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return error(format!("file-size: can't read {:?}: {}", path, e)),
+    };
+    match i32::try_from(size) {
+        Ok(size) => Ok(Token::Int(size)),
+        Err(_) => error(format!("file-size: {:?} is too big", path)),
+    }
+}
@@ -0,0 +1,79 @@
+//! Prints parsed programs back as source code, laid out consistently.
+
+use crate::{prefix, Token};
+
+const WIDTH: usize = 80;
+
+/// A program's top-level forms, one per line, breaking lists that don't fit on a line with
+/// their items indented below the head.
+pub fn pretty(ast: &[Token]) -> String {
+    ast.iter()
+        .map(|node| layout(node, 0) + "\n")
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+fn layout(node: &Token, indent: usize) -> String {
+    let flat = source(node);
+    let items = match node {
+        Token::List(items) if indent + flat.chars().count() > WIDTH && items.len() > 1 => items,
+        _ => return flat,
+    };
+    let inner = indent + 2;
+    let mut text = format!("({}", layout(&items[0], indent + 1));
+    for item in &items[1..] {
+        text += &format!("\n{}{}", " ".repeat(inner), layout(item, inner));
+    }
+    text + ")"
+}
+
+/// The source code of a node on a single line.
+pub fn source(node: &Token) -> String {
+    match node {
+        Token::List(items) => match (reader(items), quoted(items)) {
+            (Some(text), _) => format!("{}{}", items[0], string(text, false)),
+            (None, Some(prefix)) => format!("{}{}", prefix, source(&items[1])),
+            (None, None) => format!(
+                "({})",
+                items.iter().map(source).collect::<Vec<_>>().join(" ")
+            ),
+        },
+        Token::Str(text) => string(text, true),
+        Token::Float(number) => format!("{:?}", number),
+        node => node.to_string(),
+    }
+}
+
+/// `text` as a string literal the lexer reads back as it is, escaping only what it unescapes.
+/// `${` starts an expression in a string literal, but not in a reader literal's.
+fn string(text: &str, interpolating: bool) -> String {
+    let mut literal = String::from("\"");
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            '$' if interpolating && chars.peek() == Some(&'{') => literal.push_str("\\$"),
+            c => literal.push(c),
+        }
+    }
+    literal + "\""
+}
+
+/// The text of a list like the `(#tag "text")` that `#tag{text}` parses into.
+fn reader(items: &[Token]) -> Option<&str> {
+    match items {
+        [Token::Symbol(tag), Token::Str(text)] if tag.as_str().starts_with('#') => Some(text),
+        _ => None,
+    }
+}
+
+/// The prefix of a list like the `(quote x)` that `'x` parses into.
+fn quoted(items: &[Token]) -> Option<&'static str> {
+    match items {
+        [Token::Symbol(name), _] => prefix(name.as_str()),
+        _ => None,
+    }
+}
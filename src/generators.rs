@@ -0,0 +1,288 @@
+//! Generators, functions run a step at a time, up to each `yield`, as `next` asks for values.
+//! A generator's function runs on a thread of its own, so its evaluation can stop halfway and
+//! pick up where it left off. It still shares everything with the rest of the program: the two
+//! threads take turns, each one waiting while the other runs. A generator dropped while its
+//! function waits at a `yield` unwinds the function from there, once no other code of the
+//! program is halfway through running.
+
+use crate::builtins::{arity, builtins};
+use crate::interpreter::{Interpreter, Session};
+use crate::{error, Eval, Token, Unwind};
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
+use std::thread::{self, JoinHandle};
+
+builtins! {
+    /// Finds the generator builtin called `name`.
+    "generator" => generator,
+    "yield" => yield_value,
+    "next" => next,
+}
+
+/// Something handed between a generator's thread and the thread running `next`.
+struct Handoff<T>(T);
+
+// Safe: the two threads take turns, one waiting on a channel while the other runs, and a
+// generator's thread drops all it holds before handing control back for the last time, so the
+// `Rc`s in what's handed over are never used by both at once. A thread left waiting at a
+// `yield` never runs again, see `abandoned`.
+unsafe impl<T> Send for Handoff<T> {}
+
+/// Why a generator's function is handed the program's session.
+enum Turn {
+    /// To run up to its next `yield`.
+    Next(Session),
+    /// To unwind from the `yield` it waits at, running its cleanup code, as the generator was
+    /// dropped.
+    Close(Session),
+}
+
+/// What a generator's function did after being resumed.
+enum Step {
+    Yielded(Token),
+    Returned,
+    Failed(Unwind),
+}
+
+/// A generator, with the function it runs waiting on a thread of its own to be resumed.
+#[derive(Clone)]
+pub struct Generator(Rc<RefCell<Resumable>>);
+
+struct Resumable {
+    /// The function's thread, `None` once it returned.
+    coroutine: Option<Coroutine>,
+    /// Whether `next` ran the function yet.
+    started: bool,
+}
+
+/// The thread a generator's function runs on, with the channels to take turns with it.
+struct Coroutine {
+    /// Hands the program's session to the function.
+    resume: Sender<Handoff<Turn>>,
+    /// Hands the session back, with what the function did with it.
+    steps: Receiver<Handoff<(Step, Session)>>,
+    thread: JoinHandle<()>,
+}
+
+impl Generator {
+    /// Whether two generators are the same one.
+    pub fn same(&self, other: &Generator) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Where the generator is in memory, the same for all copies of it.
+    pub fn address(&self) -> usize {
+        Rc::as_ptr(&self.0) as usize
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<generator>")
+    }
+}
+
+impl Coroutine {
+    /// Hands `turn` to the function, returning what it did with it, or `None` with the session
+    /// given back to `interpreter` when the thread is gone.
+    fn resume(&self, turn: Turn, interpreter: &mut Interpreter) -> Option<Step> {
+        let handoff = match self.resume.send(Handoff(turn)) {
+            Ok(()) => self.steps.recv().ok(),
+            Err(SendError(Handoff(Turn::Next(session) | Turn::Close(session)))) => {
+                interpreter.give_session(session);
+                None
+            }
+        };
+        let Handoff((step, session)) = handoff?;
+        interpreter.give_session(session);
+        Some(step)
+    }
+
+    /// Waits for the thread to end, as it may still be dropping values the rest of the program
+    /// shares. Only for a function that returned or never started, one waiting at a `yield`
+    /// is closed first.
+    fn join(self) {
+        drop(self.resume);
+        let _ = self.thread.join();
+    }
+
+    /// Unwinds the function from the `yield` it waits at, running its cleanup code with the
+    /// program's session. One yielding again instead is left waiting there for good.
+    fn close(self, interpreter: &mut Interpreter) {
+        let session = interpreter.take_session();
+        match self.resume(Turn::Close(session), interpreter) {
+            Some(Step::Yielded(_)) => {}
+            _ => self.join(),
+        }
+    }
+}
+
+impl Drop for Resumable {
+    fn drop(&mut self) {
+        let coroutine = match self.coroutine.take() {
+            Some(coroutine) => coroutine,
+            None => return,
+        };
+        if !self.started {
+            // Nothing of the function runs, its thread only drops what it was given.
+            return coroutine.join();
+        }
+        // The function's cleanup code can't run now, the program may be in the middle of
+        // changing the variables it uses. It runs once nothing is, see `close_dropped`.
+        let _ = DROPPED.try_with(|dropped| dropped.borrow_mut().push(coroutine));
+    }
+}
+
+/// The generator side of the generator running on this thread.
+struct Yielder {
+    steps: Sender<Handoff<(Step, Session)>>,
+    resume: Receiver<Handoff<Turn>>,
+}
+
+thread_local! {
+    static YIELDER: RefCell<Option<Yielder>> = const { RefCell::new(None) };
+    /// The generators dropped on this thread while their functions wait at a `yield`.
+    static DROPPED: RefCell<Vec<Coroutine>> = const { RefCell::new(vec![]) };
+}
+
+/// Closes the generators dropped while their functions wait at a `yield`, running their
+/// cleanup code. Called where no other code of the program is halfway through running:
+/// between top-level forms, and before `generator` and `next` do anything.
+pub fn close_dropped(interpreter: &mut Interpreter) {
+    loop {
+        let dropped = DROPPED.with(|dropped| mem::take(&mut *dropped.borrow_mut()));
+        if dropped.is_empty() {
+            return;
+        }
+        dropped
+            .into_iter()
+            .for_each(|coroutine| coroutine.close(interpreter));
+    }
+}
+
+/// Stops a generator's thread for good once the program stopped taking turns with it, which
+/// only happens as the thread running the program ends. Going on, even to unwind, could touch
+/// what the program shares with it while something else does.
+fn abandoned() -> ! {
+    loop {
+        thread::park();
+    }
+}
+
+/// `(generator (lambda () ... (yield x) ...))`, a generator running the function a step at a
+/// time, up to each `yield`, as `next` asks for values.
+fn generator(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("generator", &args, 1, 1)?;
+    close_dropped(interpreter);
+    let start = Handoff((args[0].clone(), interpreter.sibling()));
+    let (resume, resumed) = mpsc::channel();
+    let (stepped, steps) = mpsc::channel();
+    let thread = thread::spawn(move || run(start, resumed, stepped));
+    let resumable = Resumable {
+        coroutine: Some(Coroutine {
+            resume,
+            steps,
+            thread,
+        }),
+        started: false,
+    };
+    Ok(Token::Generator(Generator(Rc::new(RefCell::new(
+        resumable,
+    )))))
+}
+
+/// Runs a generator's function once the first `next` hands over the session, handing it back
+/// with how the function ended.
+fn run(
+    start: Handoff<(Token, Interpreter)>,
+    resumed: Receiver<Handoff<Turn>>,
+    stepped: Sender<Handoff<(Step, Session)>>,
+) {
+    let Handoff((function, mut interpreter)) = start;
+    // Nothing runs before the first `next`, or at all when it never comes.
+    let session = match resumed.recv() {
+        Ok(Handoff(Turn::Next(session))) => session,
+        Ok(Handoff(Turn::Close(session))) => {
+            drop((function, interpreter));
+            let _ = stepped.send(Handoff((Step::Returned, session)));
+            return;
+        }
+        Err(_) => return,
+    };
+    interpreter.give_session(session);
+    let yielder = Yielder {
+        steps: stepped.clone(),
+        resume: resumed,
+    };
+    YIELDER.with(|current| *current.borrow_mut() = Some(yielder));
+    let step = match interpreter.call(function, vec![]) {
+        Ok(_) => Step::Returned,
+        Err(unwind) => Step::Failed(unwind),
+    };
+    let session = interpreter.take_session();
+    drop(interpreter);
+    let _ = stepped.send(Handoff((step, session)));
+}
+
+/// `(yield x)` hands `x` to the `next` that asked for a value, and waits for the next one to
+/// ask again. It's an error once the generator was dropped instead, unwinding the function.
+fn yield_value(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("yield", &args, 1, 1)?;
+    YIELDER.with(|current| match &*current.borrow() {
+        Some(yielder) => {
+            let step = Handoff((Step::Yielded(args[0].clone()), interpreter.take_session()));
+            if yielder.steps.send(step).is_err() {
+                abandoned();
+            }
+            match yielder.resume.recv() {
+                Ok(Handoff(Turn::Next(session))) => {
+                    interpreter.give_session(session);
+                    Ok(Token::Nil)
+                }
+                Ok(Handoff(Turn::Close(session))) => {
+                    interpreter.give_session(session);
+                    error("the generator was dropped")
+                }
+                Err(_) => abandoned(),
+            }
+        }
+        None => error("yield only works inside a generator"),
+    })
+}
+
+/// `(next g)` runs the generator `g` up to its next `yield`, evaluating to the value yielded,
+/// and to nil once its function returned.
+fn next(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("next", &args, 1, 1)?;
+    close_dropped(interpreter);
+    let generator = match &args[0] {
+        Token::Generator(generator) => generator,
+        _ => return error("next expects a generator"),
+    };
+    let mut resumable = match generator.0.try_borrow_mut() {
+        Ok(resumable) => resumable,
+        Err(_) => return error("next on a generator that's already running"),
+    };
+    let coroutine = match &resumable.coroutine {
+        Some(coroutine) => coroutine,
+        None => return Ok(Token::Nil),
+    };
+    let session = interpreter.take_session();
+    let step = coroutine.resume(Turn::Next(session), interpreter);
+    resumable.started = true;
+    if !matches!(step, Some(Step::Yielded(_))) {
+        resumable.coroutine.take().unwrap().join();
+    }
+    match step {
+        Some(Step::Yielded(value)) => Ok(value),
+        Some(Step::Returned) => Ok(Token::Nil),
+        Some(Step::Failed(Unwind::Error(message))) => {
+            error(format!("error in generator: {}", message))
+        }
+        Some(Step::Failed(unwind)) => Err(unwind),
+        None => error("the generator's thread died"),
+    }
+}
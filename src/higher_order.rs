@@ -0,0 +1,159 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::lists::items;
+use crate::{error, Eval, Token, Unwind};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A function wrapped by `memoize`, with the values it returned so far keyed by arguments.
+#[derive(Debug)]
+pub struct Memo {
+    pub function: Token,
+    pub cache: RefCell<HashMap<Vec<Token>, Token>>,
+}
+
+builtins! {
+    /// Finds the higher-order builtin called `name`.
+    "map" => map,
+    "filter" => filter,
+    "reduce" => reduce,
+    "fold" => fold,
+    "for-each" => for_each,
+    "zip" => zip,
+    "any" => any,
+    "all" => all,
+    "memoize" => memoize,
+}
+
+/// Splits the arguments of `(name function list ...)` into the function and the lists' items.
+fn function_and_lists(
+    name: &str,
+    mut args: Vec<Token>,
+) -> Result<(Token, Vec<Vec<Token>>), Unwind> {
+    if args.len() < 2 {
+        return error(format!(
+            "{} expects a function and at least one list, got {} arguments",
+            name,
+            args.len()
+        ));
+    }
+    let function = args.remove(0);
+    let lists = args
+        .into_iter()
+        .map(|list| items(name, list))
+        .collect::<Result<_, _>>()?;
+    Ok((function, lists))
+}
+
+/// Groups the i-th items of all lists together, stopping at the shortest list.
+fn transpose(lists: Vec<Vec<Token>>) -> Vec<Vec<Token>> {
+    let shortest = lists.iter().map(Vec::len).min().unwrap_or(0);
+    let mut iters: Vec<_> = lists.into_iter().map(Vec::into_iter).collect();
+    (0..shortest)
+        .map(|_| iters.iter_mut().map(|iter| iter.next().unwrap()).collect())
+        .collect()
+}
+
+/// `(map f '(1 2 3))`, with several lists `f` gets one item of each.
+fn map(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    let (function, lists) = function_and_lists("map", args)?;
+    let values = transpose(lists)
+        .into_iter()
+        .map(|args| interpreter.call(function.clone(), args))
+        .collect::<Result<_, _>>()?;
+    Ok(Token::List(values))
+}
+
+/// `(for-each print '(1 2 3))` calls `f` for its side effects only.
+fn for_each(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    let (function, lists) = function_and_lists("for-each", args)?;
+    for args in transpose(lists) {
+        interpreter.call(function.clone(), args)?;
+    }
+    Ok(Token::Nil)
+}
+
+/// `(filter pred '(1 2 3))` keeps the items for which `pred` is true.
+fn filter(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("filter", &args, 2, 2)?;
+    let (predicate, mut lists) = function_and_lists("filter", args)?;
+    let mut kept = vec![];
+    for item in lists.remove(0) {
+        if interpreter
+            .call(predicate.clone(), vec![item.clone()])?
+            .truthy()
+        {
+            kept.push(item);
+        }
+    }
+    Ok(Token::List(kept))
+}
+
+/// `(fold f init '(1 2 3))` is `(f (f (f init 1) 2) 3)`.
+fn fold(mut args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("fold", &args, 3, 3)?;
+    let list = items("fold", args.pop().unwrap())?;
+    let init = args.pop().unwrap();
+    let function = args.pop().unwrap();
+    list.into_iter().try_fold(init, |acc, item| {
+        interpreter.call(function.clone(), vec![acc, item])
+    })
+}
+
+/// `(reduce f '(1 2 3))` is `(fold f 1 '(2 3))`, the list can't be empty.
+fn reduce(mut args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("reduce", &args, 2, 2)?;
+    let mut list = items("reduce", args.pop().unwrap())?;
+    if list.is_empty() {
+        return error("reduce expects a non-empty list");
+    }
+    let init = list.remove(0);
+    fold(vec![args.remove(0), init, Token::List(list)], interpreter)
+}
+
+/// `(zip '(1 2) '(a b))` is `((1 a) (2 b))`, stopping at the shortest list.
+fn zip(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let lists = args
+        .into_iter()
+        .map(|list| items("zip", list))
+        .collect::<Result<_, _>>()?;
+    Ok(Token::List(
+        transpose(lists).into_iter().map(Token::List).collect(),
+    ))
+}
+
+/// `(any pred '(1 2 3))` is true when `pred` is true for at least one item.
+fn any(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("any", &args, 2, 2)?;
+    let (predicate, mut lists) = function_and_lists("any", args)?;
+    for item in lists.remove(0) {
+        if interpreter.call(predicate.clone(), vec![item])?.truthy() {
+            return Ok(Token::True);
+        }
+    }
+    Ok(Token::False)
+}
+
+/// `(all pred '(1 2 3))` is true when `pred` is true for every item.
+fn all(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("all", &args, 2, 2)?;
+    let (predicate, mut lists) = function_and_lists("all", args)?;
+    for item in lists.remove(0) {
+        if interpreter.call(predicate.clone(), vec![item])?.truthy() {
+            continue;
+        }
+        return Ok(Token::False);
+    }
+    Ok(Token::True)
+}
+
+/// `(define fib (memoize (lambda (n) ...)))` wraps `f` to return the remembered value when
+/// called again with equal arguments, which also covers its recursive calls through `fib`.
+fn memoize(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("memoize", &args, 1, 1)?;
+    Ok(Token::Memoized(Rc::new(Memo {
+        function: args[0].clone(),
+        cache: RefCell::new(HashMap::new()),
+    })))
+}
@@ -0,0 +1,89 @@
+//! Syntax highlighting of source code for terminals and web pages.
+
+use crate::interpreter::SPECIAL_FORMS;
+use crate::{builtins, Lexer, Token};
+
+/// What highlighted code is written for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ansi,
+    Html,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "ansi" => Some(Format::Ansi),
+            "html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+}
+
+/// The class of a token, naming its color.
+fn class(token: &Token) -> &'static str {
+    match token {
+        Token::Open | Token::Close | Token::Quote(_) => "punctuation",
+        Token::Int(_) | Token::Long(_) | Token::Ratio(..) | Token::Float(_) => "number",
+        // Reader literals, `#tag"text"`, are text too.
+        Token::Str(_) | Token::List(_) => "string",
+        Token::Symbol(name) if SPECIAL_FORMS.contains(&name.as_str()) => "keyword",
+        Token::Symbol(name) if builtins::lookup(name.as_str()).is_some() => "builtin",
+        _ => "symbol",
+    }
+}
+
+fn ansi_color(class: &str) -> &'static str {
+    match class {
+        "punctuation" => "\x1b[2m",
+        "number" => "\x1b[33m",
+        "string" => "\x1b[32m",
+        "keyword" => "\x1b[1;35m",
+        "builtin" => "\x1b[36m",
+        _ => "",
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `text` with each token colored, keeping the whitespace between them as it is.
+pub fn highlight(text: &str, format: Format) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let slice = |start: usize, end: usize| chars[start..end].iter().collect::<String>();
+    let mut highlighted = String::new();
+    if format == Format::Html {
+        highlighted.push_str("<pre class=\"mlsp\">");
+    }
+    let mut end = 0;
+    for (token, span) in Lexer::new(text.to_string()).spanned().0 {
+        let gap = slice(end, span.start);
+        let source = slice(span.start, span.end);
+        end = span.end;
+        let class = class(&token);
+        match format {
+            Format::Ansi => {
+                highlighted += &gap;
+                match ansi_color(class) {
+                    "" => highlighted += &source,
+                    color => highlighted += &format!("{}{}\x1b[0m", color, source),
+                }
+            }
+            Format::Html => {
+                highlighted += &escape_html(&gap);
+                highlighted +=
+                    &format!("<span class=\"{}\">{}</span>", class, escape_html(&source));
+            }
+        }
+    }
+    let rest = slice(end, chars.len());
+    match format {
+        Format::Ansi => highlighted += &rest,
+        Format::Html => highlighted += &format!("{}</pre>\n", escape_html(&rest)),
+    }
+    highlighted
+}
@@ -0,0 +1,156 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::lists::items;
+use crate::strings::text;
+use crate::system::allowed;
+use crate::{error, Eval, Token, Unwind};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+builtins! {
+    /// Finds the HTTP builtin called `name`.
+    "http-get" => http_get,
+    "http-post" => http_post,
+}
+
+/// `(http-get "http://example.com/")` evaluates to `(status headers body)`, the headers being
+/// a list of `(name value)` pairs.
+fn http_get(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("http-get", &args, 1, 2)?;
+    allowed("http-get", interpreter)?;
+    let headers = headers("http-get", args.get(1))?;
+    request("GET", text("http-get", &args[0])?, &headers, "")
+}
+
+/// `(http-post url body '(("Content-Type" "application/json")))`, the headers are optional.
+fn http_post(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("http-post", &args, 2, 3)?;
+    allowed("http-post", interpreter)?;
+    let headers = headers("http-post", args.get(2))?;
+    request(
+        "POST",
+        text("http-post", &args[0])?,
+        &headers,
+        text("http-post", &args[1])?,
+    )
+}
+
+fn headers(name: &str, headers: Option<&Token>) -> Result<Vec<(String, String)>, Unwind> {
+    let headers = match headers {
+        Some(headers) => items(name, headers.clone())?,
+        None => return Ok(vec![]),
+    };
+    headers
+        .iter()
+        .map(|header| match header {
+            Token::List(pair) if pair.len() == 2 => {
+                Ok((text(name, &pair[0])?.to_string(), pair[1].to_string()))
+            }
+            _ => error(format!("{} expects headers as (name value) pairs", name)),
+        })
+        .collect()
+}
+
+/// Sends a request over plain HTTP/1.1 and reads the whole response.
+fn request(method: &str, url: &str, headers: &[(String, String)], body: &str) -> Eval {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if url.starts_with("https://") => return error("https isn't supported, only http"),
+        None => return error(format!("{:?} isn't an http:// URL", url)),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        authority,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let mut response = vec![];
+    let sent = TcpStream::connect(&address).and_then(|mut stream| {
+        stream.write_all(request.as_bytes())?;
+        stream.read_to_end(&mut response)
+    });
+    if let Err(e) = sent {
+        return error(format!("{} {}: {}", method, url, e));
+    }
+    parse_response(&response)
+}
+
+fn parse_response(response: &[u8]) -> Eval {
+    let response = String::from_utf8_lossy(response);
+    let (head, body) = match response.find("\r\n\r\n") {
+        Some(end) => (&response[..end], &response[end + 4..]),
+        None => return error("malformed HTTP response"),
+    };
+    let mut lines = head.split("\r\n");
+    let status = match lines.next().and_then(|line| line.split(' ').nth(1)) {
+        Some(status) => match status.parse() {
+            Ok(status) => status,
+            Err(_) => return error("malformed HTTP status"),
+        },
+        None => return error("malformed HTTP status"),
+    };
+    let mut headers = vec![];
+    let mut chunked = false;
+    for line in lines {
+        if let Some(colon) = line.find(':') {
+            let (name, value) = (line[..colon].trim(), line[colon + 1..].trim());
+            chunked |= name.eq_ignore_ascii_case("transfer-encoding") && value == "chunked";
+            headers.push(Token::List(vec![
+                Token::Str(name.to_string()),
+                Token::Str(value.to_string()),
+            ]));
+        }
+    }
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_string()
+    };
+    Ok(Token::List(vec![
+        Token::Int(status),
+        Token::List(headers),
+        Token::Str(body),
+    ]))
+}
+
+/// Joins the chunks of a `Transfer-Encoding: chunked` body.
+fn dechunk(mut body: &str) -> Result<String, Unwind> {
+    let mut joined = String::new();
+    loop {
+        let line_end = match body.find("\r\n") {
+            Some(end) => end,
+            None => return error("malformed chunked HTTP body"),
+        };
+        let size = body[..line_end].split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) => size,
+            Err(_) => return error("malformed chunked HTTP body"),
+        };
+        if size == 0 {
+            return Ok(joined);
+        }
+        let start = line_end + 2;
+        match body.get(start..start + size) {
+            Some(chunk) => joined.push_str(chunk),
+            None => return error("malformed chunked HTTP body"),
+        }
+        body = body.get(start + size + 2..).unwrap_or("");
+    }
+}
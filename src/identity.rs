@@ -0,0 +1,162 @@
+use crate::builtins::{arity, builtins};
+use crate::cache::fnv1a;
+use crate::interpreter::Interpreter;
+use crate::{remap_bool, Eval, Token};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+builtins! {
+    /// Finds the builtin comparing, hashing, copying or freezing values called `name`.
+    "identical?" => identical,
+    "equal?" => equal,
+    "hash" => hash,
+    "deep-copy" => deep_copy,
+    "freeze" => freeze,
+    "frozen?" => is_frozen,
+}
+
+/// Whether two values are the same object. Numbers, symbols, booleans and nil are the same
+/// when they're equal, functions, errors, ports and connections when they're shared. Strings,
+/// lists and the other collections are copied whenever they're passed on, so they never are.
+fn same(v1: &Token, v2: &Token) -> bool {
+    match (v1, v2) {
+        (Token::Lambda(params1, body1, scope1), Token::Lambda(params2, body2, scope2)) => {
+            Rc::ptr_eq(scope1, scope2) && params1 == params2 && body1 == body2
+        }
+        (Token::Memoized(m1), Token::Memoized(m2)) => Rc::ptr_eq(m1, m2),
+        (Token::Error(e1), Token::Error(e2)) => Rc::ptr_eq(e1, e2),
+        (Token::Port(p1), Token::Port(p2)) => Rc::ptr_eq(p1, p2),
+        (Token::Frozen(f1), Token::Frozen(f2)) => Rc::ptr_eq(f1, f2),
+        (Token::Native(n1), Token::Native(n2)) => Rc::ptr_eq(n1, n2),
+        #[cfg(feature = "net")]
+        (Token::Connection(c1), Token::Connection(c2)) => Rc::ptr_eq(c1, c2),
+        #[cfg(feature = "net")]
+        (Token::Listener(l1), Token::Listener(l2)) => Rc::ptr_eq(l1, l2),
+        #[cfg(feature = "parallel")]
+        (Token::Channel(c1), Token::Channel(c2)) => c1.same(c2),
+        #[cfg(feature = "parallel")]
+        (Token::Atom(a1), Token::Atom(a2)) => a1.same(a2),
+        (Token::Generator(g1), Token::Generator(g2)) => g1.same(g2),
+        (
+            Token::Str(_)
+            | Token::List(_)
+            | Token::Set(_)
+            | Token::Map(_)
+            | Token::Vector(_)
+            | Token::Values(_)
+            | Token::Bytes(_),
+            _,
+        ) => false,
+        _ => v1 == v2,
+    }
+}
+
+/// `(identical? f g)`, whether `f` and `g` are the same object rather than equal ones.
+fn identical(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("identical?", &args, 2, 2)?;
+    Ok(remap_bool(same(&args[0], &args[1])))
+}
+
+/// `(equal? '(1 "a") (list 1 "a"))`, whether two values have the same type and contents.
+/// Unlike `=`, `1` and `1.0` aren't equal, so equal values have equal hashes.
+fn equal(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("equal?", &args, 2, 2)?;
+    Ok(remap_bool(args[0] == args[1]))
+}
+
+/// Collects the bytes a value is hashed from, to hash them all with FNV-1a at the end.
+#[derive(Default)]
+struct Collected(Vec<u8>);
+
+impl Hasher for Collected {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        fnv1a(&self.0)
+    }
+}
+
+/// The hash of `value`, the same in every run of the program. Symbols are hashed by their
+/// names, as their numbers depend on the order they were first seen in. Ports and connections
+/// are hashed by where they are in memory, so only their hashes change between runs.
+fn stable_hash(value: &Token) -> u64 {
+    let mut hasher = Collected::default();
+    feed(value, &mut hasher);
+    hasher.finish()
+}
+
+fn feed(value: &Token, hasher: &mut Collected) {
+    if let Token::Frozen(value) = value {
+        return feed(value, hasher);
+    }
+    value.type_name().hash(hasher);
+    match value {
+        Token::Symbol(name) => name.as_str().hash(hasher),
+        Token::List(items) | Token::Values(items) => feed_all(items, hasher),
+        Token::Set(set) => {
+            // Sets have no order, so their items' hashes are combined commutatively.
+            let combined = set
+                .iter()
+                .fold(0u64, |acc, item| acc.wrapping_add(stable_hash(item)));
+            combined.hash(hasher);
+        }
+        Token::Map(map) => {
+            let combined = map.entries().iter().fold(0u64, |acc, (key, value)| {
+                let entry = stable_hash(key)
+                    .wrapping_mul(31)
+                    .wrapping_add(stable_hash(value));
+                acc.wrapping_add(entry)
+            });
+            combined.hash(hasher);
+        }
+        Token::Vector(vector) => feed_all(&vector.items(), hasher),
+        Token::Lambda(params, body, _) => {
+            feed_all(params, hasher);
+            feed_all(body, hasher);
+        }
+        Token::Error(failure) => {
+            failure.kind.as_str().hash(hasher);
+            failure.message.hash(hasher);
+            feed(&failure.data, hasher);
+        }
+        _ => value.hash(hasher),
+    }
+}
+
+fn feed_all(items: &[Token], hasher: &mut Collected) {
+    items.len().hash(hasher);
+    items.iter().for_each(|item| feed(item, hasher));
+}
+
+/// `(hash '(1 "a"))`, an integer that's the same for equal values and in every run, for tables
+/// and caches built in the language.
+fn hash(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("hash", &args, 1, 1)?;
+    Ok(Token::integer(stable_hash(&args[0]) as i64))
+}
+
+/// `(deep-copy data)`, a copy of `data` sharing nothing with it down to its innermost items, so
+/// it can be handed to code that shouldn't see later changes to `data` or make its own to it.
+/// Strings and collections are copied item by item, while functions, errors, ports and
+/// connections stay shared as they're objects rather than data.
+fn deep_copy(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("deep-copy", &args, 1, 1)?;
+    Ok(args[0].clone())
+}
+
+/// `(freeze '(1 2 3))`, the value which `push!`, `pop!` and `set-nth!` refuse to change, for
+/// constants and data shared with code that shouldn't change it. It reads like the value it's
+/// made from otherwise, and what's taken out of it, like `(nth frozen 0)`, isn't frozen.
+fn freeze(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("freeze", &args, 1, 1)?;
+    let value = args.into_iter().next().unwrap();
+    Ok(Token::Frozen(Rc::new(value)))
+}
+
+/// `(frozen? data)`, whether `data` was made by `freeze`.
+fn is_frozen(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("frozen?", &args, 1, 1)?;
+    Ok(remap_bool(matches!(args[0], Token::Frozen(_))))
+}
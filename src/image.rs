@@ -0,0 +1,139 @@
+//! Images: the state of an interpreter saved as a program bringing it back, its global
+//! variables as `define`s of code making their values and its macros as `defmacro`s.
+
+use crate::builtins::{arity, builtins};
+use crate::formatter::source;
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::symbols::Name;
+use crate::system::allowed;
+use crate::{error, sets, Eval, Token};
+use std::fs;
+
+builtins! {
+    /// Finds the image builtin called `name`.
+    "save-image" => save_image,
+}
+
+fn call(function: &str, args: Vec<Token>) -> Token {
+    let mut list = vec![Token::Symbol(Name::new(function))];
+    list.extend(args);
+    Token::List(list)
+}
+
+fn quote(value: &Token) -> Token {
+    call("quote", vec![value.clone()])
+}
+
+fn all(values: &[Token]) -> Option<Vec<Token>> {
+    values.iter().map(code).collect()
+}
+
+/// Code evaluating to `value`, `None` for values tied to this run: ports, connections, native
+/// values, channels, atoms and continuations. Functions keep their code but not the variables they
+/// captured, so they see the global ones when loaded.
+fn code(value: &Token) -> Option<Token> {
+    let code = match value {
+        Token::Int(_) | Token::Long(_) | Token::Str(_) | Token::True | Token::False => {
+            value.clone()
+        }
+        Token::Float(f) if f.is_finite() => value.clone(),
+        Token::Ratio(n, d) => call("/", vec![Token::integer(*n), Token::integer(*d)]),
+        #[cfg(feature = "complex")]
+        Token::Complex(re, im) => call("complex", vec![Token::Float(*re), Token::Float(*im)]),
+        Token::Nil => call("when", vec![Token::False, Token::False]),
+        Token::Symbol(_) => quote(value),
+        Token::List(items) => call("list", all(items)?),
+        Token::Set(items) => {
+            let items: Vec<Token> = sets::sorted(items).into_iter().cloned().collect();
+            call("set-of", all(&items)?)
+        }
+        Token::Map(map) => {
+            let mut entries = vec![];
+            for (key, value) in map.entries() {
+                entries.push(code(key)?);
+                entries.push(code(value)?);
+            }
+            call("map-of", entries)
+        }
+        Token::Vector(vector) => call("vector", all(&vector.items())?),
+        Token::Bytes(data) => {
+            let data = data.iter().map(|byte| Token::Int(*byte as i32)).collect();
+            call("bytes", data)
+        }
+        Token::Frozen(value) => call("freeze", vec![code(value)?]),
+        Token::Lambda(params, body, _) => {
+            let mut lambda = vec![Token::List(params.clone())];
+            lambda.extend(body.iter().cloned());
+            call("lambda", lambda)
+        }
+        Token::Memoized(memo) => call("memoize", vec![code(&memo.function)?]),
+        Token::Error(failure) => call(
+            "error",
+            vec![
+                quote(&Token::Symbol(failure.kind)),
+                Token::Str(failure.message.clone()),
+                code(&failure.data)?,
+            ],
+        ),
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// The program defining `globals` and `macros` again, sorted by name, and the names of the
+/// globals left out as their values can't be saved.
+pub fn program(globals: &[(Name, Token)], macros: &[(Name, Token)]) -> (String, Vec<Name>) {
+    let mut text = String::new();
+    let mut skipped = vec![];
+    let mut macros = macros.to_vec();
+    macros.sort_by_key(|(name, _)| name.as_str());
+    for (name, expander) in &macros {
+        let (params, body) = match expander {
+            Token::Lambda(params, body, _) => (params, body),
+            _ => continue,
+        };
+        let form = match name.as_str().strip_prefix('#') {
+            Some(tag) => call(
+                "define-reader",
+                vec![Token::Symbol(Name::new(tag)), code(expander).unwrap()],
+            ),
+            None => {
+                let mut signature = vec![Token::Symbol(*name)];
+                signature.extend(params.iter().cloned());
+                let mut form = vec![Token::List(signature)];
+                form.extend(body.iter().cloned());
+                call("defmacro", form)
+            }
+        };
+        text += &format!("{}\n", source(&form));
+    }
+    let mut globals = globals.to_vec();
+    globals.sort_by_key(|(name, _)| name.as_str());
+    for (name, value) in globals {
+        match code(&value) {
+            Some(code) => {
+                let form = call("define", vec![Token::Symbol(name), code]);
+                text += &format!("{}\n", source(&form));
+            }
+            None => skipped.push(name),
+        }
+    }
+    (text, skipped)
+}
+
+/// `(save-image "session.mlsp")` writes the global variables and macros defined so far to a
+/// file, which brings them back when it's run or `:load`ed. Variables whose values can't be
+/// saved, like ports, are left out, and `save-image` evaluates to their names.
+fn save_image(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("save-image", &args, 1, 1)?;
+    allowed("save-image", interpreter)?;
+    let path = text("save-image", &args[0])?;
+    let (image, skipped) = interpreter.save_state();
+    match fs::write(path, image) {
+        Ok(()) => Ok(Token::List(
+            skipped.into_iter().map(Token::Symbol).collect(),
+        )),
+        Err(e) => error(format!("save-image: can't write {:?}: {}", path, e)),
+    }
+}
@@ -0,0 +1,1380 @@
+use crate::builtins::{self, arity, Builtin};
+use crate::errors::Failure;
+use crate::interrupt::InterruptHandle;
+use crate::output::{Output, Sink, Streams};
+use crate::symbols::Name;
+use crate::trace::{Event, Trace};
+use crate::vars::{Env, Scope};
+use crate::Token::{
+    Close, Continuation, False, Float, Int, Lambda, List, Nil, Open, Quote, Set, Str, Symbol, True,
+    Values,
+};
+use crate::{
+    contracts, error, generators, image, macros, parse, report, Eval, Lexer, Token, Unwind,
+};
+use std::any::{Any, TypeId};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Turns the text of a reader literal like `#tag{text}` into the code it stands for.
+pub type Reader = fn(&str) -> Result<Token, String>;
+
+/// A function embedders registered for a type of native value, called with the value and the
+/// rest of the arguments.
+type Method = Arc<dyn Fn(&dyn Any, Vec<Token>) -> Result<Token, String> + Send + Sync>;
+
+/// The helpers defined before every program unless `--no-prelude` is given.
+pub const PRELUDE: &str = include_str!("prelude.mlsp");
+
+/// Forms handled by the interpreter itself rather than builtin functions.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    ">",
+    "<",
+    ">=",
+    "<=",
+    "=",
+    "!=",
+    "if",
+    "when",
+    "unless",
+    "and",
+    "or",
+    "case",
+    "cond",
+    "while",
+    "do",
+    "set",
+    "push!",
+    "pop!",
+    "set-nth!",
+    "inc!",
+    "dec!",
+    "update!",
+    "swap!",
+    "print",
+    "quote",
+    "quasiquote",
+    "lambda",
+    "define",
+    "defmacro",
+    "define-reader",
+    "let",
+    "letrec",
+    "call/cc",
+    "values",
+    "let-values",
+    "unwind-protect",
+    "defer",
+    "try",
+    "return",
+    "time",
+    "bench",
+    "with-output-to-string",
+];
+
+static NEXT_CONTINUATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Evaluates programs, keeping the variables in scope and where input comes from and printed
+/// output goes.
+pub struct Interpreter {
+    env: Scope,
+    input: Box<dyn Read>,
+    output: Sink,
+    /// Printed output collected by the `with-output-to-string` forms being evaluated, innermost
+    /// last.
+    captures: Vec<String>,
+    /// Functions defined by `defmacro`, turning the code of a call into the code to run.
+    macros: HashMap<Name, Token>,
+    /// Readers registered by embedders, keyed by their `#tag`.
+    readers: HashMap<Name, Reader>,
+    /// Functions registered by embedders for their native values, keyed by name and then by the
+    /// type of value they take.
+    methods: HashMap<Name, HashMap<TypeId, Method>>,
+    /// The expressions `defer`red by each function call and top-level form being evaluated,
+    /// innermost last, with the scope to run them in.
+    deferred: Vec<Vec<(Token, Scope)>>,
+    interrupt: InterruptHandle,
+    sandboxed: bool,
+    /// How many times forms on each line ran, for `--coverage`.
+    covered: HashMap<usize, usize>,
+    /// The inputs from outside the program being recorded for `--record`, or fed back in
+    /// for `--replay`.
+    trace: Option<Trace>,
+}
+
+/// What belongs to a run of a program rather than to the interpreter evaluating it: what it
+/// reads and prints, and what's recorded about it. A generator's interpreter takes it over
+/// from the one running `next` until it yields.
+pub struct Session {
+    input: Box<dyn Read>,
+    output: Sink,
+    captures: Vec<String>,
+    covered: HashMap<usize, usize>,
+    trace: Option<Trace>,
+}
+
+/// What an interpreter shares with the ones running the threads its program spawns, which
+/// can't share its variables and macros and get copies of them instead.
+#[cfg(feature = "parallel")]
+#[derive(Clone)]
+pub struct Context {
+    readers: HashMap<Name, Reader>,
+    methods: HashMap<Name, HashMap<TypeId, Method>>,
+    output: Sink,
+    interrupt: InterruptHandle,
+    sandboxed: bool,
+}
+
+impl Session {
+    /// Reading nothing and printing nowhere, left behind while the session is taken.
+    fn detached() -> Self {
+        Self {
+            input: Box::new(io::empty()),
+            output: Sink::new(Box::new(Streams {
+                out: io::sink(),
+                err: io::sink(),
+            })),
+            captures: vec![],
+            covered: HashMap::new(),
+            trace: None,
+        }
+    }
+}
+
+impl Interpreter {
+    /// An interpreter printing to stdout and stderr.
+    pub fn new() -> Self {
+        Self::with_output(Box::new(Streams::stdio()))
+    }
+
+    /// An interpreter printing to `output` instead.
+    pub fn with_output(output: Box<dyn Output + Send>) -> Self {
+        Self {
+            env: Env::global(),
+            input: Box::new(io::stdin()),
+            output: Sink::new(output),
+            captures: vec![],
+            macros: HashMap::new(),
+            readers: HashMap::new(),
+            methods: HashMap::new(),
+            deferred: vec![],
+            interrupt: InterruptHandle::default(),
+            sandboxed: false,
+            covered: HashMap::new(),
+            trace: None,
+        }
+    }
+
+    /// An interpreter sharing this one's global variables, macros, readers and methods, stopped
+    /// by the same interrupt handle. It reads and prints nothing until given a session.
+    pub fn sibling(&self) -> Self {
+        let session = Session::detached();
+        Self {
+            env: Env::outermost(&self.env),
+            input: session.input,
+            output: session.output,
+            captures: vec![],
+            macros: self.macros.clone(),
+            readers: self.readers.clone(),
+            methods: self.methods.clone(),
+            deferred: vec![],
+            interrupt: self.interrupt.clone(),
+            sandboxed: self.sandboxed,
+            covered: HashMap::new(),
+            trace: None,
+        }
+    }
+
+    /// What the interpreters of the threads the program spawns share with this one.
+    #[cfg(feature = "parallel")]
+    pub fn context(&self) -> Context {
+        Context {
+            readers: self.readers.clone(),
+            methods: self.methods.clone(),
+            output: self.output.clone(),
+            interrupt: self.interrupt.clone(),
+            sandboxed: self.sandboxed,
+        }
+    }
+
+    /// An interpreter for a thread of its own, sharing `context` with the one that spawned it.
+    #[cfg(feature = "parallel")]
+    pub fn in_context(context: &Context) -> Self {
+        Self {
+            env: Env::global(),
+            input: Box::new(io::stdin()),
+            output: context.output.clone(),
+            captures: vec![],
+            macros: HashMap::new(),
+            readers: context.readers.clone(),
+            methods: context.methods.clone(),
+            deferred: vec![],
+            interrupt: context.interrupt.clone(),
+            sandboxed: context.sandboxed,
+            covered: HashMap::new(),
+            trace: None,
+        }
+    }
+
+    /// The macros defined so far, by name.
+    pub fn macros(&self) -> impl Iterator<Item = (Name, &Token)> {
+        self.macros.iter().map(|(name, expander)| (*name, expander))
+    }
+
+    /// Defines the macro `name`, turning the code of a call into the code to run with
+    /// `expander`.
+    #[cfg(feature = "parallel")]
+    pub fn define_macro(&mut self, name: Name, expander: Token) {
+        self.macros.insert(name, expander);
+    }
+
+    /// Takes the session this interpreter runs, for another one to run it.
+    pub fn take_session(&mut self) -> Session {
+        self.give_session(Session::detached())
+    }
+
+    /// Runs `session` from now on, returning the one it ran before.
+    pub fn give_session(&mut self, session: Session) -> Session {
+        Session {
+            input: mem::replace(&mut self.input, session.input),
+            output: mem::replace(&mut self.output, session.output),
+            captures: mem::replace(&mut self.captures, session.captures),
+            covered: mem::replace(&mut self.covered, session.covered),
+            trace: mem::replace(&mut self.trace, session.trace),
+        }
+    }
+
+    /// Whether programs are denied access to the system: running commands, files, network.
+    pub fn sandboxed(&self) -> bool {
+        self.sandboxed
+    }
+
+    pub fn set_sandboxed(&mut self, sandboxed: bool) {
+        self.sandboxed = sandboxed;
+    }
+
+    /// A handle stopping the program this interpreter runs, from another thread or a signal.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// An `interrupted` error when the program was interrupted, to check between steps.
+    fn check_interrupt(&self) -> Result<(), Unwind> {
+        if self.interrupt.take() {
+            let failure = Failure::of("interrupted", "program interrupted".to_string());
+            return Err(Unwind::Thrown(failure));
+        }
+        Ok(())
+    }
+
+    /// The frame of the environment code is currently running in.
+    pub fn scope(&self) -> Scope {
+        self.env.clone()
+    }
+
+    /// Forgets every variable and macro defined so far.
+    pub fn reset(&mut self) {
+        self.env = Env::global();
+        self.macros.clear();
+    }
+
+    /// Goes back to the top level after evaluating was cut short by a panic, which left the
+    /// frame and the forms being evaluated where it was.
+    #[cfg(feature = "net")]
+    pub fn recover(&mut self) {
+        self.env = Env::outermost(&self.env);
+        self.captures.clear();
+        self.deferred.clear();
+    }
+
+    /// Makes `#tag"text"` and `#tag{text}` run as the code `reader` returns for `text`.
+    #[allow(dead_code)]
+    pub fn add_reader(&mut self, tag: &str, reader: Reader) {
+        self.readers.insert(Name::new(&format!("#{}", tag)), reader);
+    }
+
+    /// Makes `(name value args...)` call `method` with `value` when it's a native `T`, so
+    /// programs can use the Rust values embedders give them.
+    #[allow(dead_code)]
+    pub fn add_method<T: 'static>(
+        &mut self,
+        name: &str,
+        method: fn(&T, Vec<Token>) -> Result<Token, String>,
+    ) {
+        let method: Method = Arc::new(move |value, args| match value.downcast_ref() {
+            Some(value) => method(value, args),
+            None => Err("method called on the wrong type of value".to_string()),
+        });
+        let methods = self.methods.entry(Name::new(name)).or_default();
+        methods.insert(TypeId::of::<T>(), method);
+    }
+
+    /// Defines a global variable, e.g. to give programs a native value.
+    #[allow(dead_code)]
+    pub fn define_global(&mut self, name: &str, value: Token) {
+        let _ = self
+            .env
+            .borrow_mut()
+            .define(&Symbol(Name::new(name)), value);
+    }
+
+    /// The global variables and macros defined so far, as a program defining them again, and
+    /// the names of the variables left out as their values can't be saved.
+    pub fn save_state(&self) -> (String, Vec<Name>) {
+        let globals = Env::outermost(&self.env).borrow().visible();
+        let macros: Vec<(Name, Token)> = self
+            .macros
+            .iter()
+            .map(|(name, expander)| (*name, expander.clone()))
+            .collect();
+        image::program(&globals, &macros)
+    }
+
+    /// Brings back the variables and macros of an image made by `save_state`.
+    #[allow(dead_code)]
+    pub fn load_state(&mut self, image: &str) -> Result<(), String> {
+        self.run(image.to_string()).map(|_| ())
+    }
+
+    /// Sends printed output to `output` from now on, returning where it went before.
+    #[allow(dead_code)]
+    pub fn set_output(&mut self, output: Box<dyn Output + Send>) -> Box<dyn Output + Send> {
+        self.output.replace(output)
+    }
+
+    /// Reads what programs consider stdin from `input` instead.
+    #[allow(dead_code)]
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = input;
+    }
+
+    /// How many times forms on each line of an instrumented program ran.
+    pub fn covered(&self) -> &HashMap<usize, usize> {
+        &self.covered
+    }
+
+    /// Records the inputs programs get from outside from now on.
+    pub fn record(&mut self) {
+        self.trace = Some(Trace::Recording(vec![]));
+    }
+
+    /// The inputs recorded so far.
+    pub fn recorded(&self) -> &[Event] {
+        match &self.trace {
+            Some(Trace::Recording(events)) => events,
+            _ => &[],
+        }
+    }
+
+    /// Feeds programs the inputs of a recorded run instead of getting them from outside.
+    pub fn replay(&mut self, events: Vec<Event>) {
+        self.trace = Some(Trace::Replaying(events.into()));
+    }
+
+    /// An input of `kind` from outside the program, which `live` gets, or the next one of the
+    /// trace when replaying.
+    pub fn traced(
+        &mut self,
+        kind: &str,
+        live: impl FnOnce(&mut Self) -> Result<Event, Unwind>,
+    ) -> Result<Event, Unwind> {
+        if let Some(Trace::Replaying(events)) = &mut self.trace {
+            return match events.pop_front() {
+                Some(event) if event.kind() == kind => Ok(event),
+                Some(event) => error(format!(
+                    "the replayed run got {} here, not {}",
+                    event.kind(),
+                    kind
+                )),
+                None => error(format!("the replayed run got no more {}", kind)),
+            };
+        }
+        let event = live(self)?;
+        if let Some(Trace::Recording(events)) = &mut self.trace {
+            events.push(event.clone());
+        }
+        Ok(event)
+    }
+
+    /// Runs a program, returning the value of each of its top-level forms.
+    pub fn run(&mut self, text: String) -> Result<Vec<Token>, String> {
+        // Tokenize!
+        let lexer = Lexer::new(text);
+        // Parse!
+        let ast = parse(lexer).map_err(|errors| report(&errors))?;
+        // Evaluate!
+        self.run_parsed(&ast)
+    }
+
+    /// Defines the helpers of the standard prelude, which is written in the language itself.
+    pub fn load_prelude(&mut self) -> Result<(), String> {
+        self.run(PRELUDE.to_string()).map(|_| ())
+    }
+
+    /// Runs an already parsed program.
+    pub fn run_parsed(&mut self, ast: &[Token]) -> Result<Vec<Token>, String> {
+        ast.iter()
+            .map(|form| {
+                let value = self.deferring(|this| this.evaluate(form));
+                generators::close_dropped(self);
+                value
+            })
+            .collect::<Result<_, _>>()
+            .map_err(message)
+    }
+
+    /// A program with every macro call expanded, and without the `defmacro`s, which are run
+    /// instead so the code after them can use the macros.
+    pub fn expand_program(&mut self, ast: &[Token]) -> Result<Vec<Token>, String> {
+        let mut expanded = vec![];
+        for form in ast {
+            match form {
+                List(list) if matches!(list.first(), Some(Symbol(s)) if s == "defmacro") => {
+                    self.evaluate(form).map_err(message)?;
+                }
+                _ => expanded.push(self.expand(form).map_err(message)?),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Reads all of the remaining input.
+    pub fn read_all(&mut self) -> Result<String, Unwind> {
+        let event = self.traced("input", |this| {
+            let mut text = String::new();
+            match this.input.read_to_string(&mut text) {
+                Ok(_) => Ok(Event::Input(text)),
+                Err(e) => error(format!("can't read input: {}", e)),
+            }
+        })?;
+        match event {
+            Event::Input(text) => Ok(text),
+            _ => unreachable!("traced checks the kind of events"),
+        }
+    }
+
+    /// Writes printed output.
+    pub fn write(&mut self, text: String) -> Result<(), Unwind> {
+        if let Some(capture) = self.captures.last_mut() {
+            capture.push_str(&text);
+            return Ok(());
+        }
+        match self.output.out(&text) {
+            Ok(()) => Ok(()),
+            Err(e) => error(format!("can't write output: {}", e)),
+        }
+    }
+
+    /// Writes diagnostics.
+    pub fn write_err(&mut self, text: String) -> Result<(), Unwind> {
+        match self.output.err(&text) {
+            Ok(()) => Ok(()),
+            Err(e) => error(format!("can't write errors: {}", e)),
+        }
+    }
+
+    pub fn evaluate(&mut self, node: &Token) -> Eval {
+        let value = match node {
+            Open => return error("open symbol in AST makes no sense"),
+            Close => return error("close symbol in AST makes no sense"),
+            Quote(_) => return error("quote symbol in AST makes no sense"),
+            Int(number) => Int(number.to_owned()),
+            Token::Long(number) => Token::Long(*number),
+            Token::Ratio(n, d) => Token::Ratio(*n, *d),
+            Float(number) => Float(*number),
+            #[cfg(feature = "complex")]
+            Token::Complex(re, im) => Token::Complex(*re, *im),
+            Str(text) => Str(text.clone()),
+            Symbol(symbol) => {
+                match self.env.borrow().get(*symbol) {
+                    None => Symbol(*symbol), //panic!("unknown symbol"),
+                    Some(value) => value,
+                }
+            }
+            List(list) if list.is_empty() => List(vec![]),
+            List(list) => match list.first().unwrap() {
+                Symbol(symbol) => match special_form(*symbol, &list[1..])? {
+                    "+" | "-" | "*" | "/" => {
+                        let args = self.evaluate_all(&list[1..])?;
+                        arithmetic(symbol.as_str(), args)?
+                    }
+                    "<" | ">" | "<=" | ">=" | "=" | "!=" => {
+                        let args = self.evaluate_all(&list[1..])?;
+                        comparison(symbol.as_str(), &args)?
+                    }
+                    "if" => {
+                        if self.evaluate(&list[1])?.truthy() {
+                            self.evaluate(&list[2])?
+                        } else {
+                            self.evaluate(&list[3])?
+                        }
+                    }
+                    "when" => {
+                        if self.evaluate(&list[1])?.truthy() {
+                            self.evaluate_body(&list[2..])?
+                        } else {
+                            Nil
+                        }
+                    }
+                    "unless" => {
+                        if self.evaluate(&list[1])?.truthy() {
+                            Nil
+                        } else {
+                            self.evaluate_body(&list[2..])?
+                        }
+                    }
+                    // The value deciding the result, not just whether it's true: `(or x default)`.
+                    "and" => {
+                        let mut value = True;
+                        for operand in &list[1..] {
+                            value = self.evaluate(operand)?;
+                            if !value.truthy() {
+                                break;
+                            }
+                        }
+                        value
+                    }
+                    "or" => {
+                        let mut value = False;
+                        for operand in &list[1..] {
+                            value = self.evaluate(operand)?;
+                            if value.truthy() {
+                                break;
+                            }
+                        }
+                        value
+                    }
+                    // The body of the first clause with a key equal to the value, trying them in
+                    // turn: forms are copied along with the functions holding them, so there's
+                    // nowhere to keep a table of the keys between runs.
+                    "case" => {
+                        let value = self.evaluate(&list[1])?;
+                        for clause in &list[2..] {
+                            let clause = match clause {
+                                List(clause) if !clause.is_empty() => clause,
+                                _ => return error("case clause needs to be a list"),
+                            };
+                            let matched = match &clause[0] {
+                                Symbol(symbol) if symbol == "else" => true,
+                                List(keys) => keys.contains(&value),
+                                key => *key == value,
+                            };
+                            if matched {
+                                return self.evaluate_body(&clause[1..]);
+                            }
+                        }
+                        Nil
+                    }
+                    // The body of the first clause whose test is true, a clause without a body
+                    // evaluates to the test's value. Nil when there's none.
+                    "cond" => {
+                        for clause in &list[1..] {
+                            let clause = match clause {
+                                List(clause) if !clause.is_empty() => clause,
+                                _ => return error("cond clause needs to be a list"),
+                            };
+                            let test = match &clause[0] {
+                                Symbol(symbol) if symbol == "else" => True,
+                                test => self.evaluate(test)?,
+                            };
+                            if test.truthy() {
+                                return match clause.len() {
+                                    1 => Ok(test),
+                                    _ => self.evaluate_body(&clause[1..]),
+                                };
+                            }
+                        }
+                        Nil
+                    }
+                    "while" => {
+                        let mut value = False;
+                        while self.evaluate(&list[1])?.truthy() {
+                            self.check_interrupt()?;
+                            value = self.evaluate(&list[2])?;
+                        }
+                        value
+                    }
+                    "do" => List(self.evaluate_all(&list[1..])?),
+                    "set" => {
+                        let value = self.evaluate(&list[2])?;
+                        self.env.borrow_mut().set(&list[1], value.clone())?;
+                        value
+                    }
+                    "push!" => {
+                        let value = self.evaluate(&list[2])?;
+                        self.update_list(&list[1], |items| {
+                            items.push(value.clone());
+                            Ok(value)
+                        })?
+                    }
+                    "pop!" => self.update_list(&list[1], |items| match items.pop() {
+                        Some(value) => Ok(value),
+                        None => error("can't pop! from an empty list"),
+                    })?,
+                    "inc!" | "dec!" => {
+                        let step = if symbol.as_str() == "inc!" { 1 } else { -1 };
+                        self.update_variable(&list[1], |value| {
+                            *value = (value.clone() + Int(step))?;
+                            Ok(value.clone())
+                        })?
+                    }
+                    "update!" | "swap!" => self.update_with(list)?,
+                    "set-nth!" => self.set_nth(list)?,
+                    "print" => {
+                        let value = self.evaluate(&list[1])?;
+                        self.write(format!("{:?}\n", value))?;
+                        value
+                    }
+                    "quote" => list[1].clone(),
+                    "quasiquote" => self.quasiquote(&list[1])?,
+                    "unquote" | "unquote-splicing" => {
+                        return error(format!("{} only works inside a quasiquote", symbol))
+                    }
+                    "lambda" => match &list[1] {
+                        List(params) => {
+                            Lambda(params.clone(), list[2..].to_vec(), self.env.clone())
+                        }
+                        _ => return error("lambda parameters need to be a list"),
+                    },
+                    "define" => self.define(list)?,
+                    "defmacro" => match &list[1] {
+                        List(signature) if matches!(signature.first(), Some(Symbol(_))) => {
+                            let expander = Lambda(
+                                signature[1..].to_vec(),
+                                list[2..].to_vec(),
+                                self.env.clone(),
+                            );
+                            if let Symbol(name) = signature[0] {
+                                self.macros.insert(name, expander);
+                            }
+                            signature[0].clone()
+                        }
+                        _ => return error("defmacro expects (defmacro (name params...) body...)"),
+                    },
+                    "define-reader" => match (&list[1], self.evaluate(&list[2])?) {
+                        (Symbol(tag), reader @ Lambda(..)) => {
+                            let tag = Name::new(&format!("#{}", tag));
+                            self.macros.insert(tag, reader);
+                            Symbol(tag)
+                        }
+                        _ => return error("define-reader expects a tag and a function"),
+                    },
+                    "let" => self.let_form(list)?,
+                    "letrec" => self.scoped(Env::child(&self.env), |this| {
+                        for binding in bindings(&list[1])? {
+                            let value = this.evaluate(&binding[1])?;
+                            this.env.borrow_mut().define(&binding[0], value)?;
+                        }
+                        this.evaluate_body(&list[2..])
+                    })?,
+                    "call/cc" => {
+                        let id = NEXT_CONTINUATION.fetch_add(1, atomic::Ordering::Relaxed);
+                        let function = self.evaluate(&list[1])?;
+                        match self.call(function, vec![Continuation(id)]) {
+                            Err(Unwind::Escape(target, value)) if target == id => value,
+                            result => result?,
+                        }
+                    }
+                    "values" => match self.evaluate_all(&list[1..])?.as_slice() {
+                        [value] => value.clone(),
+                        values => Values(values.to_vec()),
+                    },
+                    "let-values" => self.let_values(list)?,
+                    "try" => self.try_catch(list)?,
+                    // A variable called `return` is still called, like the continuation in
+                    // `(call/cc (lambda (return) ...))`.
+                    "return" if self.env.borrow().get(*symbol).is_none() => {
+                        arity("return", &list[1..], 0, 1)?;
+                        let value = match list.get(1) {
+                            Some(value) => self.evaluate(value)?,
+                            None => Nil,
+                        };
+                        return Err(Unwind::Return(value));
+                    }
+                    "unwind-protect" => {
+                        let result = self.evaluate(&list[1]);
+                        self.evaluate_body(&list[2..])?;
+                        result?
+                    }
+                    "defer" => {
+                        let scope = self.env.clone();
+                        match self.deferred.last_mut() {
+                            Some(deferred) => deferred.push((list[1].clone(), scope)),
+                            None => return error("defer only works inside a function or form"),
+                        }
+                        Nil
+                    }
+                    "time" => {
+                        let start = Instant::now();
+                        let value = self.evaluate(&list[1])?;
+                        self.write_err(format!("time: {:?}\n", start.elapsed()))?;
+                        value
+                    }
+                    "bench" => self.bench(list)?,
+                    "with-output-to-string" => {
+                        self.captures.push(String::new());
+                        let result = self.evaluate_body(&list[1..]);
+                        let captured = self.captures.pop().unwrap_or_default();
+                        result?;
+                        Str(captured)
+                    }
+                    // What `--coverage` wraps forms in.
+                    "#covered" => {
+                        if let Int(line) = list[1] {
+                            *self.covered.entry(line as usize).or_insert(0) += 1;
+                        }
+                        self.evaluate(&list[2])?
+                    }
+                    _ if self.is_macro(symbol) => {
+                        let expansion = self.expand_once(symbol, &list[1..])?;
+                        self.evaluate(&expansion)?
+                    }
+                    _ => {
+                        let variable = self.env.borrow().get(*symbol);
+                        match variable {
+                            None => match builtins::lookup(symbol.as_str()) {
+                                Some(builtin) => {
+                                    let args = self.evaluate_all(&list[1..])?;
+                                    self.call_builtin(*symbol, builtin, args)?
+                                }
+                                None if self.methods.contains_key(symbol) => {
+                                    let args = self.evaluate_all(&list[1..])?;
+                                    self.call_method(*symbol, args)?
+                                }
+                                None => return error(format!("unknown symbol {}", symbol)),
+                            },
+                            Some(function @ Lambda(..))
+                            | Some(function @ Continuation(_))
+                            | Some(function @ Token::Memoized(_)) => {
+                                let args = self.evaluate_all(&list[1..])?;
+                                self.call(function, args)?
+                            }
+                            Some(value) => value,
+                        }
+                    }
+                },
+                head => match self.evaluate(head)? {
+                    function @ Lambda(..)
+                    | function @ Continuation(_)
+                    | function @ Token::Memoized(_) => {
+                        let args = self.evaluate_all(&list[1..])?;
+                        self.call(function, args)?
+                    }
+                    _ => return error("can't evaluate list, first item needs to be a symbol"),
+                },
+            },
+            Lambda(params, body, env) => Lambda(params.clone(), body.clone(), env.clone()),
+            Continuation(id) => Continuation(*id),
+            Token::Memoized(memo) => Token::Memoized(memo.clone()),
+            Token::Error(failure) => Token::Error(failure.clone()),
+            Token::Frozen(value) => Token::Frozen(value.clone()),
+            Token::Native(value) => Token::Native(value.clone()),
+            Values(values) => Values(values.clone()),
+            Set(set) => Set(set.clone()),
+            Token::Map(map) => Token::Map(map.clone()),
+            Token::Vector(vector) => Token::Vector(vector.clone()),
+            Token::Bytes(data) => Token::Bytes(data.clone()),
+            Token::Port(port) => Token::Port(port.clone()),
+            #[cfg(feature = "net")]
+            Token::Connection(stream) => Token::Connection(stream.clone()),
+            #[cfg(feature = "net")]
+            Token::Listener(listener) => Token::Listener(listener.clone()),
+            #[cfg(feature = "parallel")]
+            Token::Channel(channel) => Token::Channel(channel.clone()),
+            #[cfg(feature = "parallel")]
+            Token::Atom(atom) => Token::Atom(atom.clone()),
+            Token::Generator(generator) => Token::Generator(generator.clone()),
+            True => True,
+            False => False,
+            Nil => Nil,
+        };
+        Ok(value)
+    }
+
+    /// Evaluates a sequence of expressions, returning the value of the last one or `Nil` when empty.
+    pub fn evaluate_body(&mut self, body: &[Token]) -> Eval {
+        body.iter().try_fold(Nil, |_, node| self.evaluate(node))
+    }
+
+    /// Evaluates each expression in turn, collecting their values.
+    pub fn evaluate_all(&mut self, nodes: &[Token]) -> Result<Vec<Token>, Unwind> {
+        nodes.iter().map(|node| self.evaluate(node)).collect()
+    }
+
+    /// Calls a function with already evaluated arguments. A lambda's parameters are bound in a
+    /// new frame inside the one it was created in, so its body sees the variables around its
+    /// definition rather than the caller's.
+    pub fn call(&mut self, function: Token, args: Vec<Token>) -> Eval {
+        match function {
+            Lambda(params, body, env) => {
+                self.check_interrupt()?;
+                if params.len() != args.len() {
+                    return error(format!(
+                        "function expects {} arguments, got {}",
+                        params.len(),
+                        args.len()
+                    ));
+                }
+                let internal = env.borrow().is_internal();
+                let scope = Env::child(&env);
+                for (param, arg) in params.iter().zip(args) {
+                    scope.borrow_mut().define(param, arg)?;
+                }
+                self.deferring(
+                    |this| match this.scoped(scope, |this| this.evaluate_body(&body)) {
+                        Err(Unwind::Return(value)) if !internal => Ok(value),
+                        result => result,
+                    },
+                )
+            }
+            Continuation(id) => match <[Token; 1]>::try_from(args) {
+                Ok([value]) => Err(Unwind::Escape(id, value)),
+                Err(_) => error("continuation expects 1 argument"),
+            },
+            Token::Memoized(memo) => {
+                let cached = memo.cache.borrow().get(&args).cloned();
+                if let Some(value) = cached {
+                    return Ok(value);
+                }
+                let value = self.call(memo.function.clone(), args.clone())?;
+                memo.cache.borrow_mut().insert(args, value.clone());
+                Ok(value)
+            }
+            // Operators are special forms, but can be passed around like `(reduce + ...)` too.
+            Symbol(name) if matches!(name.as_str(), "+" | "-" | "*" | "/") => {
+                arithmetic(name.as_str(), args)
+            }
+            Symbol(name) if matches!(name.as_str(), "<" | ">" | "<=" | ">=" | "=" | "!=") => {
+                comparison(name.as_str(), &args)
+            }
+            Symbol(name) => match builtins::lookup(name.as_str()) {
+                Some(builtin) => self.call_builtin(name, builtin, args),
+                None => self.call_method(name, args),
+            },
+            _ => error("only functions can be called"),
+        }
+    }
+
+    /// Calls the builtin called `name`. Builtins read frozen values like any other, only
+    /// `frozen?` tells them apart.
+    fn call_builtin(&mut self, name: Name, builtin: Builtin, args: Vec<Token>) -> Eval {
+        if name.as_str() == "frozen?" {
+            return builtin(args, self);
+        }
+        builtin(args.into_iter().map(Token::thawed).collect(), self)
+    }
+
+    /// Calls the method called `name` registered for the type of native value the first
+    /// argument is.
+    fn call_method(&mut self, name: Name, mut args: Vec<Token>) -> Eval {
+        let method = match (self.methods.get(&name), args.first()) {
+            (Some(methods), Some(Token::Native(value))) => {
+                let method = methods.get(&value.as_ref().type_id()).cloned();
+                method.ok_or_else(|| {
+                    Unwind::Error(format!("{} doesn't take this type of native value", name))
+                })?
+            }
+            (Some(_), _) => return error(format!("{} expects a native value", name)),
+            (None, _) => return error(format!("{} is not a function", name)),
+        };
+        let value = match args.remove(0) {
+            Token::Native(value) => value,
+            _ => unreachable!(),
+        };
+        method(value.as_ref(), args).or_else(error)
+    }
+
+    /// The `template` of a `quasiquote` with its `unquote`d parts evaluated, and the lists its
+    /// `unquote-splicing` parts evaluate to spliced into the list around them.
+    fn quasiquote(&mut self, template: &Token) -> Eval {
+        let items = match template {
+            List(items) => items,
+            _ => return Ok(template.clone()),
+        };
+        match &items[..] {
+            [Symbol(head), value] if head == "unquote" => return self.evaluate(value),
+            [Symbol(head), _] if head == "unquote-splicing" => {
+                return error("unquote-splicing only works inside a list")
+            }
+            _ => {}
+        }
+        let mut built = vec![];
+        for item in items {
+            match item {
+                List(inner) if matches!(&inner[..], [Symbol(head), _] if head == "unquote-splicing") => {
+                    match self.evaluate(&inner[1])?.thawed() {
+                        List(spliced) => built.extend(spliced),
+                        value => return error(format!("can't splice {}, it isn't a list", value)),
+                    }
+                }
+                item => built.push(self.quasiquote(item)?),
+            }
+        }
+        Ok(List(built))
+    }
+
+    /// Expands every macro call in `form`, including the ones expansions produce, leaving quoted
+    /// code alone.
+    pub fn expand(&mut self, form: &Token) -> Eval {
+        let list = match form {
+            List(list) if !list.is_empty() => list,
+            _ => return Ok(form.clone()),
+        };
+        if let Symbol(symbol) = &list[0] {
+            if symbol == "quote" {
+                return Ok(form.clone());
+            }
+            if self.is_macro(symbol) {
+                let expansion = self.expand_once(symbol, &list[1..])?;
+                return self.expand(&expansion);
+            }
+        }
+        let items: Result<Vec<Token>, Unwind> = list.iter().map(|item| self.expand(item)).collect();
+        Ok(List(items?))
+    }
+
+    /// Whether calls to `name` are expanded into other code: macros and readers.
+    pub fn is_macro(&self, name: &Name) -> bool {
+        self.macros.contains_key(name)
+            || self.readers.contains_key(name)
+            || macros::builtin(name.as_str()).is_some()
+    }
+
+    /// Calls the macro or reader called `name` with the code of its arguments.
+    fn expand_once(&mut self, name: &Name, args: &[Token]) -> Eval {
+        if let Some(reader) = self.readers.get(name) {
+            return match args {
+                [Str(text)] => reader(text).or_else(error),
+                _ => error(format!("{} expects the text of a reader literal", name)),
+            };
+        }
+        match self.macros.get(name) {
+            Some(expander) => self.call(expander.clone(), args.to_vec()),
+            None => macros::builtin(name.as_str()).unwrap()(args),
+        }
+    }
+
+    /// `(update! x f args...)` sets `x` to `(f x args...)`. `x` can be an atom too, also one
+    /// that isn't in a variable, which is set to `f` of its value instead.
+    fn update_with(&mut self, list: &[Token]) -> Eval {
+        let function = self.evaluate(&list[2])?;
+        let old = match &list[1] {
+            Symbol(_) => self.update_variable(&list[1], |value| Ok(value.clone()))?,
+            expression => self.evaluate(expression)?,
+        };
+        #[cfg(feature = "parallel")]
+        if let Token::Atom(atom) = old {
+            let rest = self.evaluate_all(&list[3..])?;
+            return atom.update(self, |this, old| {
+                let mut args = vec![old];
+                args.extend(rest.iter().cloned());
+                this.call(function.clone(), args)
+            });
+        }
+        if !matches!(list[1], Symbol(_)) {
+            return error(format!("{} expects a variable or an atom", list[0]));
+        }
+        let mut args = vec![old];
+        args.extend(self.evaluate_all(&list[3..])?);
+        let new = self.call(function, args)?;
+        self.update_variable(&list[1], |value| {
+            *value = new.clone();
+            Ok(new)
+        })
+    }
+
+    /// `(set-nth! lst i value)` replaces an item of the list bound to `lst`.
+    fn set_nth(&mut self, list: &[Token]) -> Eval {
+        let index = self.evaluate(&list[2])?;
+        let value = self.evaluate(&list[3])?;
+        self.update_list(&list[1], |items| match index.as_integer() {
+            Some(i) if i >= 0 && (i as usize) < items.len() => {
+                items[i as usize] = value.clone();
+                Ok(value)
+            }
+            Some(i) => error(format!(
+                "index {} out of bounds for list of length {}",
+                i,
+                items.len()
+            )),
+            None => error("set-nth! expects an integer index"),
+        })
+    }
+
+    /// `(define (name params...) body...)` defines a function, `(define name value)` a variable.
+    fn define(&mut self, list: &[Token]) -> Eval {
+        match &list[1] {
+            List(signature) if signature.is_empty() => error("define expects a name to define"),
+            List(signature) => {
+                let (params, mut body) = contracts::parameters(&signature[0], &signature[1..]);
+                body.extend_from_slice(&list[2..]);
+                let function = Lambda(params, body, self.env.clone());
+                self.env
+                    .borrow_mut()
+                    .define(&signature[0], function.clone())?;
+                Ok(function)
+            }
+            name => {
+                arity("define", &list[1..], 2, 2)?;
+                let value = self.evaluate(&list[2])?;
+                self.env.borrow_mut().define(name, value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// `(let ((name value)...) body...)`, or a loop named by a symbol after `let`.
+    fn let_form(&mut self, list: &[Token]) -> Eval {
+        match &list[1] {
+            Symbol(name) => {
+                arity("let", &list[1..], 2, usize::MAX)?;
+                let pairs = bindings(&list[2])?;
+                let params: Vec<Token> = pairs.iter().map(|pair| pair[0].clone()).collect();
+                let body = &list[3..];
+                let scope = Env::internal(&self.env);
+                // What calls to the loop not in tail position call, or code it's passed to.
+                let function = Lambda(params.clone(), body.to_vec(), scope.clone());
+                scope.borrow_mut().define(&list[1], function)?;
+                let mut args: Vec<Token> = pairs
+                    .iter()
+                    .map(|pair| self.evaluate(&pair[1]))
+                    .collect::<Result<_, _>>()?;
+                loop {
+                    self.check_interrupt()?;
+                    if params.len() != args.len() {
+                        return error(format!(
+                            "function expects {} arguments, got {}",
+                            params.len(),
+                            args.len()
+                        ));
+                    }
+                    let frame = Env::child(&scope);
+                    for (param, arg) in params.iter().zip(args) {
+                        frame.borrow_mut().define(param, arg)?;
+                    }
+                    let looping = (*name, &scope);
+                    let tail = self.deferring(|this| {
+                        this.scoped(frame, |this| this.tail_body(body, looping))
+                    })?;
+                    match tail {
+                        Tail::Loop(next) => args = next,
+                        Tail::Done(value) => return Ok(value),
+                    }
+                }
+            }
+            _ => {
+                let scope = Env::child(&self.env);
+                for binding in bindings(&list[1])? {
+                    let value = self.evaluate(&binding[1])?;
+                    scope.borrow_mut().define(&binding[0], value)?;
+                }
+                self.scoped(scope, |this| this.evaluate_body(&list[2..]))
+            }
+        }
+    }
+
+    /// Evaluates the body of a named `let`, `looping` being its name and frame, going on with
+    /// the next turn of the loop for a call to it in tail position instead of calling it.
+    fn tail_body(&mut self, body: &[Token], looping: (Name, &Scope)) -> Result<Tail, Unwind> {
+        match body.split_last() {
+            Some((last, rest)) => {
+                self.evaluate_body(rest)?;
+                self.tail(last, looping)
+            }
+            None => Ok(Tail::Done(Nil)),
+        }
+    }
+
+    /// Evaluates `node`, in tail position in the body of a named `let`.
+    fn tail(&mut self, node: &Token, looping: (Name, &Scope)) -> Result<Tail, Unwind> {
+        let (name, scope) = looping;
+        let list = match node {
+            List(list) => list,
+            _ => return self.evaluate(node).map(Tail::Done),
+        };
+        let symbol = match list.first() {
+            Some(Symbol(symbol)) => *symbol,
+            _ => return self.evaluate(node).map(Tail::Done),
+        };
+        let looped = || match self.env.borrow().get(name) {
+            Some(Lambda(_, _, env)) => Rc::ptr_eq(&env, scope),
+            _ => false,
+        };
+        if symbol == name && looped() {
+            return Ok(Tail::Loop(self.evaluate_all(&list[1..])?));
+        }
+        match (symbol.as_str(), list.len()) {
+            ("if", 4) => match self.evaluate(&list[1])?.truthy() {
+                true => self.tail(&list[2], looping),
+                false => self.tail(&list[3], looping),
+            },
+            ("when", 2..) => match self.evaluate(&list[1])?.truthy() {
+                true => self.tail_body(&list[2..], looping),
+                false => Ok(Tail::Done(Nil)),
+            },
+            ("unless", 2..) => match self.evaluate(&list[1])?.truthy() {
+                true => Ok(Tail::Done(Nil)),
+                false => self.tail_body(&list[2..], looping),
+            },
+            ("cond", _) => {
+                for clause in &list[1..] {
+                    let clause = match clause {
+                        List(clause) if !clause.is_empty() => clause,
+                        _ => return error("cond clause needs to be a list"),
+                    };
+                    let test = match &clause[0] {
+                        Symbol(symbol) if symbol == "else" => True,
+                        test => self.evaluate(test)?,
+                    };
+                    if test.truthy() {
+                        return match clause.len() {
+                            1 => Ok(Tail::Done(test)),
+                            _ => self.tail_body(&clause[1..], looping),
+                        };
+                    }
+                }
+                Ok(Tail::Done(Nil))
+            }
+            ("#covered", 3) => {
+                if let Int(line) = list[1] {
+                    *self.covered.entry(line as usize).or_insert(0) += 1;
+                }
+                self.tail(&list[2], looping)
+            }
+            _ => self.evaluate(node).map(Tail::Done),
+        }
+    }
+
+    /// `(let-values ((names...) expression) body...)` binds each of the values of `expression`.
+    fn let_values(&mut self, list: &[Token]) -> Eval {
+        let (names, expression) = match &list[1] {
+            List(binding) if binding.len() == 2 => match &binding[0] {
+                List(names) => (names, &binding[1]),
+                _ => return error("let-values needs a list of names"),
+            },
+            _ => return error("let-values binding needs to be a ((names) value) pair"),
+        };
+        let values = match self.evaluate(expression)? {
+            Values(values) => values,
+            value => vec![value],
+        };
+        if names.len() != values.len() {
+            return error(format!(
+                "let-values expects {} values, got {}",
+                names.len(),
+                values.len()
+            ));
+        }
+        let scope = Env::child(&self.env);
+        for (name, value) in names.iter().zip(values) {
+            scope.borrow_mut().define(name, value)?;
+        }
+        self.scoped(scope, |this| this.evaluate_body(&list[2..]))
+    }
+
+    /// `(bench expr runs)` evaluates `expr` `runs` times, reporting how long it took to stderr.
+    fn bench(&mut self, list: &[Token]) -> Eval {
+        let runs = match self.evaluate(&list[2])?.as_integer() {
+            Some(runs) if runs > 0 => runs as u64,
+            _ => return error("bench expects a positive number of runs"),
+        };
+        let start = Instant::now();
+        let mut value = Nil;
+        for _ in 0..runs {
+            value = self.evaluate(&list[1])?;
+        }
+        let elapsed = start.elapsed();
+        self.write_err(format!(
+            "bench: {} runs in {:?}, {:?}/run\n",
+            runs,
+            elapsed,
+            Duration::from_nanos((elapsed.as_nanos() / runs as u128) as u64)
+        ))?;
+        Ok(value)
+    }
+
+    /// `(try body... (catch e handler...))` evaluates the handler with `e` bound to what was
+    /// thrown, or the error value of a runtime error.
+    fn try_catch(&mut self, list: &[Token]) -> Eval {
+        let (name, handler) = match list.last() {
+            Some(List(clause))
+                if clause.len() > 2 && matches!(&clause[0], Symbol(s) if s == "catch") =>
+            {
+                (&clause[1], &clause[2..])
+            }
+            _ => return error("try expects a (catch name handler...) clause last"),
+        };
+        let thrown = match self.evaluate_body(&list[1..list.len() - 1]) {
+            Err(Unwind::Thrown(value)) => value,
+            Err(Unwind::Error(message)) => Failure::runtime(message),
+            result => return result,
+        };
+        let scope = Env::child(&self.env);
+        scope.borrow_mut().define(name, thrown)?;
+        self.scoped(scope, |this| this.evaluate_body(handler))
+    }
+
+    /// Passes the list bound to a variable to `f`, for the forms that modify it in place.
+    fn update_list<R>(
+        &mut self,
+        name: &Token,
+        f: impl FnOnce(&mut Vec<Token>) -> Result<R, Unwind>,
+    ) -> Result<R, Unwind> {
+        self.update_variable(name, |variable| match variable {
+            List(items) => f(items),
+            Token::Frozen(_) => error(format!("{:?} is frozen, it can't be changed", name)),
+            _ => error(format!("{:?} isn't a list", name)),
+        })
+    }
+
+    /// Passes the value bound to a variable to `f`, for the forms that change it in place.
+    fn update_variable<R>(
+        &mut self,
+        name: &Token,
+        f: impl FnOnce(&mut Token) -> Result<R, Unwind>,
+    ) -> Result<R, Unwind> {
+        let updated = match name {
+            Symbol(symbol) => self.env.borrow_mut().update(*symbol, f),
+            _ => None,
+        };
+        updated.unwrap_or_else(|| error(format!("unknown variable {:?}", name)))
+    }
+
+    /// Evaluates `f`, then the expressions `defer`red while it ran, last deferred first, however
+    /// `f` ended. An error from them is only the result when `f` succeeded.
+    fn deferring<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, Unwind>,
+    ) -> Result<T, Unwind> {
+        self.deferred.push(vec![]);
+        let mut result = f(self);
+        for (expression, scope) in self.deferred.pop().unwrap_or_default().into_iter().rev() {
+            let ran = self.scoped(scope, |this| this.evaluate(&expression));
+            if result.is_ok() {
+                result = ran.and(result);
+            }
+        }
+        result
+    }
+
+    /// Evaluates `f` with `scope` as the frame code runs in, then restores the current one.
+    fn scoped<T>(
+        &mut self,
+        scope: Scope,
+        f: impl FnOnce(&mut Self) -> Result<T, Unwind>,
+    ) -> Result<T, Unwind> {
+        let outer = mem::replace(&mut self.env, scope);
+        let result = f(self);
+        self.env = outer;
+        result
+    }
+}
+
+/// `operator` applied to the values left to right, `(- 10 1 2)` is 7. `(+)` is 0 and `(*)` is 1,
+/// `(- x)` negates `x` and `/` needs at least 2 values.
+fn arithmetic(operator: &str, args: Vec<Token>) -> Eval {
+    let mut args = args.into_iter();
+    let first = match operator {
+        "+" => Int(0),
+        "*" => Int(1),
+        "-" if args.len() == 1 => Int(0),
+        _ if args.len() < 2 => {
+            return error(format!("{} expects at least 2 arguments", operator));
+        }
+        _ => args.next().unwrap(),
+    };
+    args.try_fold(first, |result, arg| match operator {
+        "+" => result + arg,
+        "-" => result - arg,
+        "*" => result * arg,
+        _ => result / arg,
+    })
+}
+
+/// Whether each value is `operator` than the next, like `(< 0 x 10)`. `=` and `!=` compare
+/// with `Token::equals`, the others numbers, strings or symbols.
+fn comparison(operator: &str, args: &[Token]) -> Eval {
+    if args.len() < 2 {
+        return error(format!("{} expects at least 2 arguments", operator));
+    }
+    for pair in args.windows(2) {
+        let holds = match operator {
+            "=" => pair[0].equals(&pair[1]),
+            "!=" => !pair[0].equals(&pair[1]),
+            _ => {
+                let ordering = pair[0].compare(&pair[1])?;
+                match operator {
+                    "<" => ordering == Ordering::Less,
+                    ">" => ordering == Ordering::Greater,
+                    "<=" => ordering != Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }
+            }
+        };
+        if !holds {
+            return Ok(False);
+        }
+    }
+    Ok(True)
+}
+
+/// The message of an error that made it to the top level.
+fn message(unwind: Unwind) -> String {
+    match unwind {
+        Unwind::Error(message) => message,
+        Unwind::Escape(..) => "continuation called after its call/cc returned".to_string(),
+        Unwind::Thrown(value) => format!("uncaught {}", value),
+        Unwind::Return(_) => "return only works inside a function".to_string(),
+    }
+}
+
+/// The name of the form `(symbol args...)`, after checking that special forms got as many
+/// arguments as they take.
+fn special_form(symbol: Name, args: &[Token]) -> Result<&'static str, Unwind> {
+    let name = symbol.as_str();
+    let (min, max) = match name {
+        "if" => (3, 3),
+        "set-nth!" => (3, 3),
+        "while" | "set" | "push!" | "define-reader" | "bench" | "#covered" => (2, 2),
+        "update!" | "swap!" => (2, usize::MAX),
+        "pop!" | "inc!" | "dec!" | "print" | "quote" | "quasiquote" | "call/cc" | "defer"
+        | "time" => (1, 1),
+        "when" | "unless" | "case" | "lambda" | "define" | "defmacro" | "let" | "letrec"
+        | "let-values" | "try" | "unwind-protect" => (1, usize::MAX),
+        _ => return Ok(name),
+    };
+    arity(name, args, min, max)?;
+    Ok(name)
+}
+
+/// What the body of a named `let` ended with: a call to the loop in tail position, with the
+/// values to go on with, or the value of the loop.
+enum Tail {
+    Loop(Vec<Token>),
+    Done(Token),
+}
+
+/// Splits a `((name value) ...)` binding list into its `(name value)` pairs.
+fn bindings(node: &Token) -> Result<Vec<&[Token]>, Unwind> {
+    match node {
+        List(bindings) => bindings
+            .iter()
+            .map(|binding| match binding {
+                List(pair) if pair.len() == 2 => Ok(pair.as_slice()),
+                _ => error("binding needs to be a (name value) pair"),
+            })
+            .collect(),
+        _ => error("bindings need to be a list"),
+    }
+}
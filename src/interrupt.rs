@@ -0,0 +1,55 @@
+//! Stopping a running program from outside of it: by the host through an `InterruptHandle`, or
+//! with Ctrl-C on the command line.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Interrupts the programs an interpreter runs, from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes the program stop at its next function call or loop iteration, with an error of
+    /// type `interrupted` that `try` can catch.
+    #[allow(dead_code)]
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the program was interrupted since the last time this was asked.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// The handle Ctrl-C interrupts.
+static CTRL_C: OnceLock<InterruptHandle> = OnceLock::new();
+
+/// Makes Ctrl-C interrupt the programs of `handle` instead of ending the process. Pressing it
+/// again before the program noticed, e.g. while it waits for input, ends the process after all.
+#[cfg(unix)]
+pub fn on_ctrl_c(handle: InterruptHandle) {
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn _exit(status: i32) -> !;
+    }
+    extern "C" fn interrupt(_signum: i32) {
+        if let Some(handle) = CTRL_C.get() {
+            if handle.0.swap(true, Ordering::SeqCst) {
+                // Safe: `_exit` is one of the few functions a signal handler may call.
+                unsafe { _exit(130) }
+            }
+        }
+    }
+    const SIGINT: i32 = 2;
+    if CTRL_C.set(handle).is_ok() {
+        // Safe: the handler only touches an atomic, or ends the process.
+        unsafe {
+            signal(SIGINT, interrupt);
+        }
+    }
+}
+
+/// Ctrl-C ends the process as usual where signals aren't available.
+#[cfg(not(unix))]
+pub fn on_ctrl_c(_handle: InterruptHandle) {}
@@ -0,0 +1,1574 @@
+//! Compiling programs to JavaScript for `compile --emit=js`, to run mlsp code in web pages
+//! without the interpreter. The output is plain JavaScript after a small runtime holding only
+//! the builtins the program uses.
+//!
+//! Lists become arrays, which are shared rather than copied when they're passed on, numbers
+//! JavaScript's, so `(/ 1 3)` is a float, and symbols `Symbol.for` symbols. Parameters' types
+//! aren't checked. Forms that need the interpreter, like `call/cc`, or builtins reaching the
+//! system are reported as errors. Macros are expanded before compiling.
+
+use crate::builtins;
+use crate::interpreter::SPECIAL_FORMS;
+use crate::symbols::Name;
+use crate::Token;
+use std::collections::{BTreeSet, HashSet};
+use std::mem;
+
+/// The builtins with a JavaScript version: their name, their code and the runtime helpers it
+/// uses. Operators passed as values, like `(fold + 0 lst)`, and the compiler's helpers, starting
+/// with `$`, are here too.
+const RUNTIME: &[(&str, &str, &[&str])] = &[
+    (
+        "$truthy",
+        "const $truthy = (x) => x !== false && x !== null && x !== undefined;",
+        &[],
+    ),
+    (
+        "$show",
+        "function $show(x) {
+  if (x === null || x === undefined) return \"nil\";
+  if (Array.isArray(x)) return \"(\" + x.map($show).join(\" \") + \")\";
+  if (typeof x === \"symbol\") return Symbol.keyFor(x);
+  if (typeof x === \"function\") return \"<lambda>\";
+  if (x instanceof Error) return `<error ${$show(x.kind ?? Symbol.for(\"error\"))}: ${x.message}>`;
+  return String(x);
+}",
+        &[],
+    ),
+    (
+        "$equal",
+        "function $equal(a, b) {
+  if (Array.isArray(a) && Array.isArray(b)) {
+    return a.length === b.length && a.every((x, i) => $equal(x, b[i]));
+  }
+  return a === b || (a == null && b == null);
+}",
+        &[],
+    ),
+    (
+        "$and",
+        "function $and(...operands) {
+  let value = true;
+  for (const operand of operands) {
+    value = operand();
+    if (!$truthy(value)) break;
+  }
+  return value;
+}",
+        &["$truthy"],
+    ),
+    (
+        "$or",
+        "function $or(...operands) {
+  let value = false;
+  for (const operand of operands) {
+    value = operand();
+    if ($truthy(value)) break;
+  }
+  return value;
+}",
+        &["$truthy"],
+    ),
+    (
+        "$push",
+        "const $push = (items, value) => (items.push(value), value);",
+        &[],
+    ),
+    (
+        "$pop",
+        "function $pop(items) {
+  if (items.length === 0) throw new Error(\"can't pop! from an empty list\");
+  return items.pop();
+}",
+        &[],
+    ),
+    (
+        "$print",
+        "const $print = (x) => (console.log(typeof x === \"string\" ? JSON.stringify(x) : $show(x)), x);",
+        &["$show"],
+    ),
+    (
+        "+",
+        "const $add = (...xs) => xs.reduce((a, b) => a + b, 0);",
+        &[],
+    ),
+    (
+        "-",
+        "const $sub = (x, ...xs) => (xs.length ? xs.reduce((a, b) => a - b, x) : -x);",
+        &[],
+    ),
+    (
+        "*",
+        "const $mul = (...xs) => xs.reduce((a, b) => a * b, 1);",
+        &[],
+    ),
+    (
+        "/",
+        "const $div = (x, ...xs) => (xs.length ? xs.reduce((a, b) => a / b, x) : 1 / x);",
+        &[],
+    ),
+    (
+        "<",
+        "const $lt = (...xs) => xs.every((x, i) => i === 0 || xs[i - 1] < x);",
+        &[],
+    ),
+    (
+        ">",
+        "const $gt = (...xs) => xs.every((x, i) => i === 0 || xs[i - 1] > x);",
+        &[],
+    ),
+    (
+        "<=",
+        "const $le = (...xs) => xs.every((x, i) => i === 0 || xs[i - 1] <= x);",
+        &[],
+    ),
+    (
+        ">=",
+        "const $ge = (...xs) => xs.every((x, i) => i === 0 || xs[i - 1] >= x);",
+        &[],
+    ),
+    (
+        "=",
+        "const $eq = (...xs) => xs.every((x, i) => i === 0 || $equal(xs[i - 1], x));",
+        &["$equal"],
+    ),
+    (
+        "!=",
+        "const $ne = (...xs) => !$eq(...xs);",
+        &["="],
+    ),
+    ("list", "const list = (...xs) => xs;", &[]),
+    ("length", "const length = (x) => [...x].length;", &[]),
+    ("append", "const append = (...lists) => [].concat(...lists);", &[]),
+    ("reverse", "const reverse = (xs) => [...xs].reverse();", &[]),
+    (
+        "nth",
+        "function nth(xs, i) {
+  if (i < 0 || i >= xs.length) throw new Error(`index ${i} out of bounds for list of length ${xs.length}`);
+  return xs[i];
+}",
+        &[],
+    ),
+    (
+        "last",
+        "const last = (xs) => (xs.length ? xs[xs.length - 1] : null);",
+        &[],
+    ),
+    (
+        "map",
+        "const map = (f, ...lists) =>
+  lists[0].slice(0, Math.min(...lists.map((l) => l.length))).map((_, i) => f(...lists.map((l) => l[i])));",
+        &[],
+    ),
+    (
+        "filter",
+        "const filter = (f, xs) => xs.filter((x) => $truthy(f(x)));",
+        &["$truthy"],
+    ),
+    (
+        "fold",
+        "const fold = (f, init, xs) => xs.reduce((acc, x) => f(acc, x), init);",
+        &[],
+    ),
+    (
+        "reduce",
+        "const reduce = (f, xs) => xs.slice(1).reduce((acc, x) => f(acc, x), xs[0]);",
+        &[],
+    ),
+    (
+        "for-each",
+        "const for_each = (f, xs) => (xs.forEach((x) => f(x)), null);",
+        &[],
+    ),
+    (
+        "zip",
+        "const zip = (a, b) => a.slice(0, Math.min(a.length, b.length)).map((x, i) => [x, b[i]]);",
+        &[],
+    ),
+    (
+        "any",
+        "const any = (f, xs) => xs.some((x) => $truthy(f(x)));",
+        &["$truthy"],
+    ),
+    (
+        "all",
+        "const all = (f, xs) => xs.every((x) => $truthy(f(x)));",
+        &["$truthy"],
+    ),
+    (
+        "str",
+        "const str = (...xs) => xs.map($show).join(\"\");",
+        &["$show"],
+    ),
+    (
+        "println",
+        "const println = (...xs) => (console.log(xs.map($show).join(\"\")), null);",
+        &["$show"],
+    ),
+    (
+        "prin",
+        // Without a newline outside node, where there's only console.log.
+        "const prin = (...xs) => (typeof process === \"object\" && process.stdout ? process.stdout.write(xs.map($show).join(\"\")) : console.log(xs.map($show).join(\"\")), null);",
+        &["$show"],
+    ),
+    (
+        "eprint",
+        "const eprint = (...xs) => (console.error(xs.map($show).join(\"\")), null);",
+        &["$show"],
+    ),
+    (
+        "substring",
+        "const substring = (s, start, end) => [...s].slice(start, end).join(\"\");",
+        &[],
+    ),
+    (
+        "split",
+        "const split = (s, sep) => (sep === undefined ? s.split(/\\s+/).filter((part) => part) : s.split(sep));",
+        &[],
+    ),
+    (
+        "join",
+        "const join = (xs, sep = \"\") => xs.map($show).join(sep);",
+        &["$show"],
+    ),
+    ("trim", "const trim = (s) => s.trim();", &[]),
+    ("upcase", "const upcase = (s) => s.toUpperCase();", &[]),
+    ("downcase", "const downcase = (s) => s.toLowerCase();", &[]),
+    (
+        "replace",
+        "const replace = (s, from, to) => s.split(from).join(to);",
+        &[],
+    ),
+    ("sqrt", "const sqrt = Math.sqrt;", &[]),
+    ("sin", "const sin = Math.sin;", &[]),
+    ("cos", "const cos = Math.cos;", &[]),
+    ("floor", "const floor = Math.floor;", &[]),
+    ("ceil", "const ceil = Math.ceil;", &[]),
+    (
+        "round",
+        "const round = (x) => Math.sign(x) * Math.round(Math.abs(x));",
+        &[],
+    ),
+    ("pow", "const pow = Math.pow;", &[]),
+    ("abs", "const abs = Math.abs;", &[]),
+    ("neg", "const neg = (x) => -x;", &[]),
+    ("inc", "const inc = (x) => x + 1;", &[]),
+    ("dec", "const dec = (x) => x - 1;", &[]),
+    (
+        "error",
+        "const error = (kind, message, data = null) => Object.assign(new Error(message), { kind, data });",
+        &[],
+    ),
+    (
+        "throw",
+        "function throw_(e) {
+  throw e;
+}",
+        &[],
+    ),
+    ("error?", "const error_p = (x) => x instanceof Error;", &[]),
+    (
+        "error-type",
+        "const error_type = (e) => e.kind ?? Symbol.for(\"error\");",
+        &[],
+    ),
+    ("error-message", "const error_message = (e) => e.message;", &[]),
+    ("error-data", "const error_data = (e) => e.data ?? null;", &[]),
+    (
+        "string->number",
+        "function string_to_number(s, radix = 10) {
+  const n = radix === 10 ? Number(s) : parseInt(s, radix);
+  return Number.isNaN(n) || s.trim() === \"\" ? false : n;
+}",
+        &[],
+    ),
+    (
+        "number->string",
+        "const number_to_string = (n, radix = 10) => n.toString(radix);",
+        &[],
+    ),
+    (
+        "symbol->string",
+        "const symbol_to_string = (s) => Symbol.keyFor(s);",
+        &[],
+    ),
+    (
+        "string->symbol",
+        "const string_to_symbol = (s) => Symbol.for(s);",
+        &[],
+    ),
+];
+
+/// The forms evaluated by the interpreter only.
+const UNSUPPORTED: &[&str] = &[
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "defmacro",
+    "define-reader",
+    "call/cc",
+    "values",
+    "let-values",
+    "unwind-protect",
+    "defer",
+    "time",
+    "bench",
+    "with-output-to-string",
+    "return",
+];
+
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// The JavaScript name of a variable: `is-empty?` is `is_empty_p`, `list->string`
+/// `list_to_string`, and reserved words get a `_` after them.
+fn mangle(name: &str) -> String {
+    let mut mangled = name.replace("->", "_to_");
+    for (from, to) in [
+        ("-", "_"),
+        ("?", "_p"),
+        ("!", "_b"),
+        ("*", "_star"),
+        ("+", "_plus"),
+        ("/", "_slash"),
+        ("<", "_lt"),
+        (">", "_gt"),
+        ("=", "_eq"),
+        ("%", "_pct"),
+        ("&", "_and"),
+    ] {
+        mangled = mangled.replace(from, to);
+    }
+    if RESERVED.contains(&mangled.as_str()) {
+        mangled.push('_');
+    }
+    mangled
+}
+
+/// The runtime name of an operator passed as a value.
+fn operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "+" => "$add",
+        "-" => "$sub",
+        "*" => "$mul",
+        "/" => "$div",
+        "<" => "$lt",
+        ">" => "$gt",
+        "<=" => "$le",
+        ">=" => "$ge",
+        "=" => "$eq",
+        "!=" => "$ne",
+        _ => return None,
+    })
+}
+
+fn symbol(form: &Token) -> Option<&str> {
+    match form {
+        Token::Symbol(name) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// Whether statements compiled from a form end by returning its value.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Discard,
+    Return,
+}
+
+/// The variables of a function or `let` block being compiled.
+#[derive(Default)]
+struct Scope {
+    declared: HashSet<String>,
+    /// Variables first set inside nested blocks or expressions, declared at the top.
+    hoisted: Vec<String>,
+    /// How many `if`, `while`, ... blocks deep the statement being compiled is.
+    nesting: usize,
+}
+
+/// The named `let` whose function's body is being compiled, which goes round a loop instead of
+/// calling itself in tail position.
+struct Loop {
+    name: String,
+    /// Its parameters, which a call in tail position assigns to.
+    params: String,
+    /// Whether the body called it in tail position.
+    looped: bool,
+}
+
+struct Compiler {
+    /// The names the program binds itself, which aren't the builtins'.
+    bound: HashSet<String>,
+    /// The runtime the compiled code uses.
+    used: BTreeSet<&'static str>,
+    scopes: Vec<Scope>,
+    depth: usize,
+    temporaries: usize,
+    looping: Option<Loop>,
+}
+
+/// `program` as JavaScript, with the functions of `prelude` it calls. The error names the first
+/// form that can't be compiled.
+pub fn compile(program: &[Token], prelude: &[Token]) -> Result<String, String> {
+    let mut forms = needed(program, prelude);
+    forms.extend_from_slice(program);
+    let mut bound = HashSet::new();
+    forms.iter().for_each(|form| binds(form, &mut bound));
+    let mut compiler = Compiler {
+        bound,
+        used: BTreeSet::new(),
+        scopes: vec![Scope::default()],
+        depth: 0,
+        temporaries: 0,
+        looping: None,
+    };
+    let mut code = compiler.body(&forms, Mode::Discard)?;
+    let scope = compiler.scopes.pop().unwrap();
+    if !scope.hoisted.is_empty() {
+        code = format!("let {};\n{}", scope.hoisted.join(", "), code);
+    }
+    Ok(format!("{}\n{}", compiler.runtime(), code))
+}
+
+/// The `define`s of `prelude` that `program` uses, directly or through other ones.
+fn needed(program: &[Token], prelude: &[Token]) -> Vec<Token> {
+    let mut referenced = HashSet::new();
+    program
+        .iter()
+        .for_each(|form| references(form, &mut referenced));
+    let mut included = vec![false; prelude.len()];
+    loop {
+        let mut added = false;
+        for (index, form) in prelude.iter().enumerate() {
+            let name = match defined(form) {
+                Some(name) => name,
+                None => continue,
+            };
+            if !included[index] && referenced.contains(name) {
+                included[index] = true;
+                added = true;
+                references(form, &mut referenced);
+            }
+        }
+        if !added {
+            break;
+        }
+    }
+    prelude
+        .iter()
+        .zip(included)
+        .filter(|(_, included)| *included)
+        .map(|(form, _)| form.clone())
+        .collect()
+}
+
+/// The name a `define` form defines.
+fn defined(form: &Token) -> Option<&str> {
+    match form {
+        Token::List(list) if list.len() > 2 && symbol(&list[0]) == Some("define") => {
+            match &list[1] {
+                Token::List(signature) => symbol(signature.first()?),
+                name => symbol(name),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn references(form: &Token, found: &mut HashSet<String>) {
+    match form {
+        Token::Symbol(name) => {
+            found.insert(name.as_str().to_string());
+        }
+        Token::List(items) => items.iter().for_each(|item| references(item, found)),
+        _ => {}
+    }
+}
+
+/// Whether `form` makes functions, with `lambda`, `define` or a named `let`.
+fn creates_functions(form: &Token) -> bool {
+    let list = match form {
+        Token::List(list) if !list.is_empty() => list,
+        _ => return false,
+    };
+    let function = match symbol(&list[0]) {
+        Some("quote") => return false,
+        Some("lambda") => true,
+        Some("define") => matches!(list.get(1), Some(Token::List(_))),
+        Some("let") => matches!(list.get(1), Some(Token::Symbol(_))),
+        _ => false,
+    };
+    function || list.iter().any(creates_functions)
+}
+
+/// Adds the names bound anywhere in `form`, by `define`, `set`, parameters, `let` or `catch`.
+fn binds(form: &Token, found: &mut HashSet<String>) {
+    let list = match form {
+        Token::List(list) if !list.is_empty() => list,
+        _ => return,
+    };
+    match symbol(&list[0]) {
+        Some("quote") => return,
+        Some("define") | Some("set") if list.len() > 1 => pattern_names(&list[1], found),
+        Some("lambda") if list.len() > 1 => pattern_names(&list[1], found),
+        Some("let") | Some("letrec") if list.len() > 2 => {
+            let bindings = match &list[1] {
+                Token::Symbol(_) => {
+                    pattern_names(&list[1], found);
+                    &list[2]
+                }
+                bindings => bindings,
+            };
+            if let Token::List(bindings) = bindings {
+                for binding in bindings {
+                    if let Token::List(binding) = binding {
+                        binding
+                            .iter()
+                            .take(1)
+                            .for_each(|name| pattern_names(name, found));
+                    }
+                }
+            }
+        }
+        Some("catch") if list.len() > 1 => pattern_names(&list[1], found),
+        _ => {}
+    }
+    list.iter().for_each(|item| binds(item, found));
+}
+
+fn pattern_names(pattern: &Token, found: &mut HashSet<String>) {
+    match pattern {
+        Token::Symbol(name) if name.as_str() != "&" => {
+            found.insert(parameter_name(name.as_str()).to_string());
+        }
+        Token::List(items) => items.iter().for_each(|item| pattern_names(item, found)),
+        _ => {}
+    }
+}
+
+/// A parameter's name without its type, `w` for `w:int`.
+fn parameter_name(name: &str) -> &str {
+    match name.find(':') {
+        Some(colon) if colon > 0 => &name[..colon],
+        _ => name,
+    }
+}
+
+/// `code` without the parentheses around all of it, where nothing binds to it more tightly: in
+/// arguments, conditions and statements. Kept around a sequence `(a, b)`, as its comma would
+/// separate arguments.
+fn bare(code: String) -> String {
+    if !code.starts_with('(') {
+        return code;
+    }
+    let (mut depth, mut quote, mut escaped) = (0, None, false);
+    for (index, c) in code.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote.is_some() => escaped = true,
+            '"' | '`' if quote == Some(c) => quote = None,
+            '"' | '`' if quote.is_none() => quote = Some(c),
+            _ if quote.is_some() => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return match index + 1 == code.len() {
+                        true => code[1..index].to_string(),
+                        false => code,
+                    };
+                }
+            }
+            ',' if depth == 1 => return code,
+            _ => {}
+        }
+    }
+    code
+}
+
+/// A string literal. Rust's escapes, `\u{1b}` included, are JavaScript's too.
+fn string(text: &str) -> String {
+    format!("{:?}", text)
+}
+
+impl Compiler {
+    fn indent(&self) -> String {
+        "  ".repeat(self.depth)
+    }
+
+    fn line(&self, text: &str) -> String {
+        format!("{}{}\n", self.indent(), text)
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporaries += 1;
+        format!("$v{}", self.temporaries)
+    }
+
+    /// Marks `name` and the runtime it needs as used.
+    fn use_runtime(&mut self, name: &'static str) {
+        if self.used.insert(name) {
+            let (_, _, needs) = RUNTIME.iter().find(|(n, _, _)| *n == name).unwrap();
+            needs.iter().for_each(|need| self.use_runtime(need));
+        }
+    }
+
+    /// The code of the runtime used so far, in the order of `RUNTIME`.
+    fn runtime(&self) -> String {
+        RUNTIME
+            .iter()
+            .filter(|(name, _, _)| self.used.contains(name))
+            .map(|(_, code, _)| format!("{}\n", code))
+            .collect()
+    }
+
+    fn declared(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.declared.contains(name))
+    }
+
+    /// Whether `define` (`local`) or `set` of `name` makes a new variable, which is then
+    /// declared in the current scope.
+    fn declare(&mut self, name: &str, local: bool) -> bool {
+        let known = match local {
+            true => self.scopes.last().unwrap().declared.contains(name),
+            false => self.declared(name),
+        };
+        if !known {
+            let scope = self.scopes.last_mut().unwrap();
+            scope.declared.insert(name.to_string());
+        }
+        !known
+    }
+
+    /// Whether the statement being compiled is directly in its function or `let` block, where
+    /// it can declare variables for the rest of the block.
+    fn unnested(&self) -> bool {
+        self.scopes.last().unwrap().nesting == 0
+    }
+
+    /// `let name = value;` for a new variable when the statement is unnested, otherwise an
+    /// assignment, declaring a new variable at the top of the block.
+    fn assign(&mut self, name: &str, value: &str, statement: bool, local: bool) -> String {
+        let value = match statement {
+            true => bare(value.to_string()),
+            false => value.to_string(),
+        };
+        if self.declare(name, local) {
+            if statement && self.unnested() {
+                return format!("let {} = {};", name, value);
+            }
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .hoisted
+                .push(name.to_string());
+        }
+        match statement {
+            true => format!("{} = {};", name, value),
+            false => format!("({} = {})", name, value),
+        }
+    }
+
+    /// Statements evaluating `forms` in turn, returning the last one's value in `Return` mode.
+    fn body(&mut self, forms: &[Token], mode: Mode) -> Result<String, String> {
+        let mut code = String::new();
+        for (index, form) in forms.iter().enumerate() {
+            let last = index + 1 == forms.len();
+            code += &self.statement(form, if last { mode } else { Mode::Discard })?;
+        }
+        if forms.is_empty() && mode == Mode::Return {
+            code += &self.line("return null;");
+        }
+        Ok(code)
+    }
+
+    /// `forms` as the statements of a block nested in the current one, one level deeper.
+    fn block(&mut self, forms: &[Token], mode: Mode) -> Result<String, String> {
+        self.depth += 1;
+        self.scopes.last_mut().unwrap().nesting += 1;
+        let code = self.body(forms, mode);
+        self.scopes.last_mut().unwrap().nesting -= 1;
+        self.depth -= 1;
+        code
+    }
+
+    /// Runs `f` in a new scope declaring `names`, with the variables it hoists declared first.
+    fn scoped(
+        &mut self,
+        names: HashSet<String>,
+        f: impl FnOnce(&mut Self) -> Result<String, String>,
+    ) -> Result<String, String> {
+        self.scopes.push(Scope {
+            declared: names,
+            ..Scope::default()
+        });
+        self.depth += 1;
+        let code = f(self);
+        self.depth -= 1;
+        let scope = self.scopes.pop().unwrap();
+        let code = code?;
+        match scope.hoisted.is_empty() {
+            true => Ok(code),
+            false => {
+                let declaration = format!("let {};", scope.hoisted.join(", "));
+                self.depth += 1;
+                let declaration = self.line(&declaration);
+                self.depth -= 1;
+                Ok(declaration + &code)
+            }
+        }
+    }
+
+    /// A function taking `params` and evaluating `body`. A single expression is the body of an
+    /// arrow function, otherwise it's a block, starting with the docstring as a comment.
+    fn function(
+        &mut self,
+        name: Option<&str>,
+        params: &[Token],
+        body: &[Token],
+    ) -> Result<String, String> {
+        let mut names = HashSet::new();
+        params
+            .iter()
+            .for_each(|param| pattern_names(param, &mut names));
+        let names: HashSet<String> = names.into_iter().map(|name| mangle(&name)).collect();
+        let params = self.parameters(params)?;
+        let outer = self.looping.take();
+        let code = self.function_body(name, &params, names, body);
+        self.looping = outer;
+        code
+    }
+
+    /// The rest of `function`, once its parameters are compiled to `params` declaring `names`.
+    fn function_body(
+        &mut self,
+        name: Option<&str>,
+        params: &str,
+        names: HashSet<String>,
+        body: &[Token],
+    ) -> Result<String, String> {
+        let (doc, body) = match body {
+            [Token::Str(doc), rest @ ..] if !rest.is_empty() => (Some(doc), rest),
+            _ => (None, body),
+        };
+        if name.is_none() && doc.is_none() {
+            if let [form] = body {
+                // Tried as an expression first, keeping it when it fits on a line.
+                let saved = self.temporaries;
+                let expression = self.scoped(names.clone(), |this| {
+                    let expression = this.expression(form)?;
+                    match this.scopes.last().unwrap().hoisted.is_empty() {
+                        true => Ok(expression),
+                        false => Ok("\n".to_string()),
+                    }
+                })?;
+                if !expression.contains('\n') {
+                    return Ok(format!("({}) => {}", params, expression));
+                }
+                self.temporaries = saved;
+            }
+        }
+        let code = self.scoped(names, |this| this.body(body, Mode::Return))?;
+        let mut header = String::new();
+        if let Some(doc) = doc {
+            for line in doc.lines() {
+                header += &format!("// {}\n{}", line.trim(), self.indent());
+            }
+        }
+        match name {
+            Some(name) => Ok(format!(
+                "{}function {}({}) {{\n{}{}}}",
+                header,
+                name,
+                params,
+                code,
+                self.indent()
+            )),
+            None => Ok(format!("({}) => {{\n{}{}}}", params, code, self.indent())),
+        }
+    }
+
+    fn parameters(&self, params: &[Token]) -> Result<String, String> {
+        let mut compiled = vec![];
+        let mut rest = false;
+        for param in params {
+            let param = match param {
+                Token::Symbol(name) if name.as_str() == "&" => {
+                    rest = true;
+                    continue;
+                }
+                Token::Symbol(name) => mangle(parameter_name(name.as_str())),
+                Token::List(items) => format!("[{}]", self.parameters(items)?),
+                other => {
+                    return Err(format!(
+                        "can't compile the parameter {} to JavaScript",
+                        other
+                    ))
+                }
+            };
+            compiled.push(if rest { format!("...{}", param) } else { param });
+        }
+        Ok(compiled.join(", "))
+    }
+
+    /// The code testing whether `form` is true: everything but `false` and `nil` is.
+    fn test(&mut self, form: &Token) -> Result<String, String> {
+        let code = self.expression(form)?;
+        match boolean(form) {
+            true => Ok(code),
+            false => {
+                self.use_runtime("$truthy");
+                Ok(format!("$truthy({})", code))
+            }
+        }
+    }
+
+    fn statement(&mut self, form: &Token, mode: Mode) -> Result<String, String> {
+        let list = match form {
+            Token::List(list) if !list.is_empty() => list,
+            _ => return self.simple(form, mode),
+        };
+        let looping = self.looping.as_ref().map(|looping| looping.name.as_str());
+        if mode == Mode::Return && symbol(&list[0]).is_some() && symbol(&list[0]) == looping {
+            return self.next_iteration(&list[1..]);
+        }
+        let head = match symbol(&list[0]) {
+            Some(head) if !self.bound.contains(head) => head,
+            _ => return self.simple(form, mode),
+        };
+        let code = match (head, list.len()) {
+            ("define", 3..) | ("set", 3) => match &list[1] {
+                Token::List(signature) if head == "define" && !signature.is_empty() => {
+                    let name = mangle(symbol(&signature[0]).unwrap_or_default());
+                    let function = self.function(Some(&name), &signature[1..], &list[2..])?;
+                    let code = match self.unnested() && self.declare(&name, true) {
+                        true => function,
+                        false => self.assign(&name, &function, true, true),
+                    };
+                    self.line(&code) + &self.returned(&name, mode)
+                }
+                Token::Symbol(name) => {
+                    let name = mangle(name.as_str());
+                    let value = self.expression(&list[2])?;
+                    let assignment = self.assign(&name, &value, true, head == "define");
+                    self.line(&assignment) + &self.returned(&name, mode)
+                }
+                other => return Err(format!("can't compile {} {} to JavaScript", head, other)),
+            },
+            ("if", 3..=4) => {
+                let test = self.test(&list[1])?;
+                let mut code = self.line(&format!("if ({}) {{", bare(test)));
+                code += &self.block(&list[2..3], mode)?;
+                if list.len() == 4 || mode == Mode::Return {
+                    code += &self.line("} else {");
+                    code += &self.block(&list[3..], mode)?;
+                }
+                code + &self.line("}")
+            }
+            ("when", 2..) | ("unless", 2..) => {
+                let mut test = self.test(&list[1])?;
+                if head == "unless" {
+                    test = format!("!{}", test);
+                }
+                let mut code = self.line(&format!("if ({}) {{", bare(test)));
+                code += &self.block(&list[2..], mode)?;
+                code += &self.line("}");
+                code + &self.returned("null", mode)
+            }
+            ("while", 3) => {
+                let test = bare(self.test(&list[1])?);
+                match mode {
+                    Mode::Discard => {
+                        let mut code = self.line(&format!("while ({}) {{", test));
+                        code += &self.block(&list[2..], Mode::Discard)?;
+                        code + &self.line("}")
+                    }
+                    Mode::Return => {
+                        let value = self.temporary();
+                        let mut code = self.line(&format!("let {} = false;", value));
+                        code += &self.line(&format!("while ({}) {{", test));
+                        self.depth += 1;
+                        let last = self.expression(&list[2])?;
+                        code += &self.line(&format!("{} = {};", value, bare(last)));
+                        self.depth -= 1;
+                        code + &self.line("}") + &self.returned(&value, mode)
+                    }
+                }
+            }
+            ("do", _) if mode == Mode::Discard => self.body(&list[1..], Mode::Discard)?,
+            ("cond", _) => self.cond(&list[1..], mode)?,
+            ("case", 2..) => self.case(&list[1], &list[2..], mode)?,
+            ("try", 3..) => self.try_catch(&list[1..], mode)?,
+            ("let", 3..) | ("letrec", 3..) if matches!(list[1], Token::List(_)) => {
+                self.let_block(head == "letrec", &list[1], &list[2..], mode)?
+            }
+            _ => return self.simple(form, mode),
+        };
+        Ok(code)
+    }
+
+    /// A form as an expression statement.
+    fn simple(&mut self, form: &Token, mode: Mode) -> Result<String, String> {
+        let code = bare(self.expression(form)?);
+        Ok(match mode {
+            Mode::Discard => self.line(&format!("{};", code)),
+            Mode::Return => self.line(&format!("return {};", code)),
+        })
+    }
+
+    fn returned(&self, value: &str, mode: Mode) -> String {
+        match mode {
+            Mode::Discard => String::new(),
+            Mode::Return => self.line(&format!("return {};", value)),
+        }
+    }
+
+    /// `cond` as a chain of `if`s.
+    fn cond(&mut self, clauses: &[Token], mode: Mode) -> Result<String, String> {
+        let mut code = String::new();
+        let mut open = false;
+        for clause in clauses {
+            let clause = match clause {
+                Token::List(clause) if !clause.is_empty() => clause,
+                _ => return Err("cond clause needs to be a list".to_string()),
+            };
+            if symbol(&clause[0]) == Some("else") {
+                if open {
+                    code += &self.line("} else {");
+                    code += &self.block(&clause[1..], mode)?;
+                    return Ok(code + &self.line("}"));
+                }
+                return Ok(code + &self.body(&clause[1..], mode)?);
+            }
+            let test = if clause.len() == 1 {
+                // A clause without a body evaluates to the test's value.
+                let value = self.temporary();
+                let expression = bare(self.expression(&clause[0])?);
+                if open {
+                    code += &self.line("}");
+                }
+                code += &self.line(&format!("const {} = {};", value, expression));
+                self.use_runtime("$truthy");
+                code += &self.line(&format!("if ($truthy({})) {{", value));
+                code += &self.block(&[], Mode::Discard)?;
+                if mode == Mode::Return {
+                    self.depth += 1;
+                    code += &self.line(&format!("return {};", value));
+                    self.depth -= 1;
+                }
+                open = true;
+                continue;
+            } else {
+                bare(self.test(&clause[0])?)
+            };
+            code += &match open {
+                true => self.line(&format!("}} else if ({}) {{", test)),
+                false => self.line(&format!("if ({}) {{", test)),
+            };
+            code += &self.block(&clause[1..], mode)?;
+            open = true;
+        }
+        if open {
+            code += &self.line("}");
+        }
+        Ok(code + &self.returned("null", mode))
+    }
+
+    /// `case` as a `switch` when every key is an integer or a symbol, which compare by identity
+    /// in JavaScript too, otherwise as a chain of `if`s comparing the value to each clause's keys.
+    fn case(&mut self, value: &Token, clauses: &[Token], mode: Mode) -> Result<String, String> {
+        let name = self.temporary();
+        let value = bare(self.expression(value)?);
+        let code = self.line(&format!("const {} = {};", name, value));
+        let mut parsed = vec![];
+        for clause in clauses {
+            let clause = match clause {
+                Token::List(clause) if !clause.is_empty() => clause,
+                _ => return Err("case clause needs to be a list".to_string()),
+            };
+            let keys = match &clause[0] {
+                Token::Symbol(symbol) if symbol.as_str() == "else" => None,
+                Token::List(keys) => Some(keys.clone()),
+                key => Some(vec![key.clone()]),
+            };
+            let last = keys.is_none();
+            parsed.push((keys, &clause[1..]));
+            // The clauses after `else` are never reached, a `switch` would still try their keys.
+            if last {
+                break;
+            }
+        }
+        let keys = parsed.iter().flat_map(|(keys, _)| keys.iter().flatten());
+        match keys
+            .clone()
+            .all(|key| matches!(key, Token::Int(_) | Token::Symbol(_)))
+        {
+            true => self.switch(code, &name, parsed, mode),
+            false => self.if_chain(code, &name, parsed, mode),
+        }
+    }
+
+    /// The `switch` statement of `case`, continuing `code` declaring `name`.
+    fn switch(
+        &mut self,
+        mut code: String,
+        name: &str,
+        clauses: Vec<(Option<Vec<Token>>, &[Token])>,
+        mode: Mode,
+    ) -> Result<String, String> {
+        code += &self.line(&format!("switch ({}) {{", name));
+        let mut default = false;
+        self.depth += 1;
+        for (keys, body) in clauses {
+            match keys {
+                Some(keys) => {
+                    for key in &keys {
+                        let key = format!("case {}:", self.literal(key)?);
+                        code += &self.line(&key);
+                    }
+                }
+                None => {
+                    code += &self.line("default:");
+                    default = true;
+                }
+            }
+            code += &self.block(body, mode)?;
+            if mode == Mode::Discard {
+                self.depth += 1;
+                code += &self.line("break;");
+                self.depth -= 1;
+            }
+        }
+        self.depth -= 1;
+        code += &self.line("}");
+        match default {
+            true => Ok(code),
+            false => Ok(code + &self.returned("null", mode)),
+        }
+    }
+
+    /// The `if`s of `case`, continuing `code` declaring `name`.
+    fn if_chain(
+        &mut self,
+        mut code: String,
+        name: &str,
+        clauses: Vec<(Option<Vec<Token>>, &[Token])>,
+        mode: Mode,
+    ) -> Result<String, String> {
+        let mut tests = vec![];
+        for (keys, body) in clauses {
+            let test = match keys {
+                None => None,
+                Some(keys) => {
+                    self.use_runtime("$equal");
+                    let compared: Result<Vec<String>, String> = keys
+                        .iter()
+                        .map(|key| Ok(format!("$equal({}, {})", name, self.literal(key)?)))
+                        .collect();
+                    Some(compared?.join(" || "))
+                }
+            };
+            tests.push((test, body));
+        }
+        let mut open = false;
+        for (test, body) in tests {
+            match test {
+                Some(test) => {
+                    code += &match open {
+                        true => self.line(&format!("}} else if ({}) {{", test)),
+                        false => self.line(&format!("if ({}) {{", test)),
+                    };
+                    code += &self.block(body, mode)?;
+                    open = true;
+                }
+                None if open => {
+                    code += &self.line("} else {");
+                    code += &self.block(body, mode)?;
+                    return Ok(code + &self.line("}"));
+                }
+                None => return Ok(code + &self.body(body, mode)?),
+            }
+        }
+        if open {
+            code += &self.line("}");
+        }
+        Ok(code + &self.returned("null", mode))
+    }
+
+    /// `(try body... (catch e handler...))` as `try { ... } catch (e) { ... }`.
+    fn try_catch(&mut self, forms: &[Token], mode: Mode) -> Result<String, String> {
+        let (name, handler) = match forms.last() {
+            Some(Token::List(clause))
+                if clause.len() > 2 && symbol(&clause[0]) == Some("catch") =>
+            {
+                match &clause[1] {
+                    Token::Symbol(name) => (mangle(name.as_str()), &clause[2..]),
+                    _ => return Err("catch expects a name for the error".to_string()),
+                }
+            }
+            _ => return Err("try expects a (catch name handler...) clause last".to_string()),
+        };
+        let mut code = self.line("try {");
+        code += &self.block(&forms[..forms.len() - 1], mode)?;
+        code += &self.line(&format!("}} catch ({}) {{", name));
+        code += &self.scoped(HashSet::from([name]), |this| this.body(handler, mode))?;
+        Ok(code + &self.line("}"))
+    }
+
+    /// `let` as a block declaring its variables, when none of the values refer to them, as
+    /// `(let ((x (+ x 1))) ...)` needs the outer `x` while the inner one is being declared.
+    /// Otherwise it's a function called with the values.
+    fn let_block(
+        &mut self,
+        recursive: bool,
+        bindings: &Token,
+        body: &[Token],
+        mode: Mode,
+    ) -> Result<String, String> {
+        let pairs = pairs(bindings)?;
+        let mut names = HashSet::new();
+        pairs
+            .iter()
+            .for_each(|(name, _)| pattern_names(name, &mut names));
+        let mut used = HashSet::new();
+        pairs
+            .iter()
+            .for_each(|(_, value)| references(value, &mut used));
+        if !recursive && names.iter().any(|name| used.contains(name)) {
+            let form = Token::List(
+                [Token::Symbol(Name::new("let")), bindings.clone()]
+                    .iter()
+                    .chain(body)
+                    .cloned()
+                    .collect(),
+            );
+            return self.simple(&form, mode);
+        }
+        let declared = &self.scopes.last().unwrap().declared;
+        let taken = names.iter().any(|name| declared.contains(&mangle(name)));
+        // At the end of a function the block's braces aren't needed, unless its names are taken.
+        if mode == Mode::Return && self.unnested() && !taken {
+            return self.declarations(&pairs, body, mode);
+        }
+        let mut code = self.line("{");
+        code += &self.scoped(HashSet::new(), |this| this.declarations(&pairs, body, mode))?;
+        Ok(code + &self.line("}"))
+    }
+
+    /// A `let` for each pair, followed by `body`.
+    fn declarations(
+        &mut self,
+        pairs: &[(Token, Token)],
+        body: &[Token],
+        mode: Mode,
+    ) -> Result<String, String> {
+        let mut code = String::new();
+        for (name, value) in pairs {
+            let value = self.expression(value)?;
+            let pattern = self.parameters(std::slice::from_ref(name))?;
+            let mut names = HashSet::new();
+            pattern_names(name, &mut names);
+            let scope = self.scopes.last_mut().unwrap();
+            scope.declared.extend(names.iter().map(|name| mangle(name)));
+            code += &self.line(&format!("let {} = {};", pattern, bare(value)));
+        }
+        Ok(code + &self.body(body, mode)?)
+    }
+
+    /// A form as an expression. Statements like `while` become functions called right away.
+    fn expression(&mut self, form: &Token) -> Result<String, String> {
+        let list = match form {
+            Token::List(list) if !list.is_empty() => list,
+            Token::List(_) => return Ok("[]".to_string()),
+            Token::Symbol(name) => return self.variable(name.as_str()),
+            atom => return self.literal(atom),
+        };
+        let head = match symbol(&list[0]) {
+            Some(head) if !self.bound.contains(head) => head,
+            _ => return self.call(list),
+        };
+        if UNSUPPORTED.contains(&head) {
+            return Err(format!("{} can't be compiled to JavaScript", head));
+        }
+        let args = &list[1..];
+        let code = match head {
+            "+" | "-" | "*" | "/" => {
+                let operands = self.expressions(args)?;
+                match (head, operands.len()) {
+                    ("+", 0) => "0".to_string(),
+                    ("*", 0) => "1".to_string(),
+                    ("-", 1) => format!("(-{})", operands[0]),
+                    ("/", 1) => format!("(1 / {})", operands[0]),
+                    (_, 1) => operands[0].clone(),
+                    (_, 0) => return Err(format!("{} expects arguments", head)),
+                    _ => format!("({})", operands.join(&format!(" {} ", head))),
+                }
+            }
+            "<" | ">" | "<=" | ">=" | "=" | "!=" if args.len() == 2 => {
+                let (a, b) = (self.expression(&args[0])?, self.expression(&args[1])?);
+                let primitive = args.iter().any(|arg| {
+                    matches!(
+                        arg,
+                        Token::Int(_) | Token::Long(_) | Token::Float(_) | Token::Str(_)
+                    )
+                });
+                match head {
+                    "=" | "!=" if !primitive => {
+                        self.use_runtime("$equal");
+                        let negation = if head == "!=" { "!" } else { "" };
+                        format!("{}$equal({}, {})", negation, a, b)
+                    }
+                    "=" => format!("({} === {})", a, b),
+                    "!=" => format!("({} !== {})", a, b),
+                    _ => format!("({} {} {})", a, head, b),
+                }
+            }
+            "if" if (3..=4).contains(&list.len()) => {
+                let test = self.test(&args[0])?;
+                let then = self.expression(&args[1])?;
+                let otherwise = match args.get(2) {
+                    Some(form) => self.expression(form)?,
+                    None => "null".to_string(),
+                };
+                format!("({} ? {} : {})", test, then, otherwise)
+            }
+            "when" | "unless" if !args.is_empty() => {
+                let test = self.test(&args[0])?;
+                let body = self.sequence(&args[1..])?;
+                match head {
+                    "when" => format!("({} ? {} : null)", test, body),
+                    _ => format!("({} ? null : {})", test, body),
+                }
+            }
+            "and" | "or" => {
+                let operands = self.expressions(args)?;
+                match (head, operands.len()) {
+                    ("and", 0) => "true".to_string(),
+                    ("or", 0) => "false".to_string(),
+                    (_, 1) => operands[0].clone(),
+                    _ if args.iter().all(boolean) => {
+                        let operator = if head == "and" { " && " } else { " || " };
+                        format!("({})", operands.join(operator))
+                    }
+                    _ => {
+                        let helper = if head == "and" { "$and" } else { "$or" };
+                        self.use_runtime(helper);
+                        let thunks: Vec<String> = operands
+                            .iter()
+                            .map(|operand| format!("() => {}", operand))
+                            .collect();
+                        format!("{}({})", helper, thunks.join(", "))
+                    }
+                }
+            }
+            "do" => {
+                let items: Vec<String> = self.expressions(args)?.into_iter().map(bare).collect();
+                format!("[{}]", items.join(", "))
+            }
+            "define" | "set" if list.len() == 3 => match &args[0] {
+                Token::Symbol(name) => {
+                    let name = mangle(name.as_str());
+                    let value = self.expression(&args[1])?;
+                    self.assign(&name, &value, false, head == "define")
+                }
+                _ if head == "define" => self.defined_function(list)?,
+                other => return Err(format!("can't compile set {} to JavaScript", other)),
+            },
+            "define" if list.len() > 3 => self.defined_function(list)?,
+            "push!" | "pop!" | "inc!" | "dec!" | "set-nth!" | "update!" | "swap!"
+                if !args.is_empty() =>
+            {
+                let target = match symbol(&args[0]) {
+                    Some(name) => mangle(name),
+                    None => return Err(format!("{} expects a variable", head)),
+                };
+                let rest = self.expressions(&args[1..])?;
+                match (head, rest.len()) {
+                    ("push!", 1) => {
+                        self.use_runtime("$push");
+                        format!("$push({}, {})", target, rest[0])
+                    }
+                    ("pop!", 0) => {
+                        self.use_runtime("$pop");
+                        format!("$pop({})", target)
+                    }
+                    ("inc!", 0) => format!("({} += 1)", target),
+                    ("dec!", 0) => format!("({} -= 1)", target),
+                    ("set-nth!", 2) => format!("({}[{}] = {})", target, rest[0], rest[1]),
+                    ("update!", 1..) | ("swap!", 1..) => {
+                        let mut call = vec![args[1].clone(), args[0].clone()];
+                        call.extend_from_slice(&args[2..]);
+                        let value = self.expression(&Token::List(call))?;
+                        format!("({} = {})", target, value)
+                    }
+                    _ => return Err(format!("wrong number of arguments to {}", head)),
+                }
+            }
+            "print" if args.len() == 1 => {
+                self.use_runtime("$print");
+                format!("$print({})", self.expression(&args[0])?)
+            }
+            "quote" if args.len() == 1 => self.literal(&args[0])?,
+            "lambda" if !args.is_empty() => match &args[0] {
+                Token::List(params) => format!("({})", self.function(None, params, &args[1..])?),
+                _ => return Err("lambda parameters need to be a list".to_string()),
+            },
+            // A named loop is a function, started with the initial values.
+            "let" if list.len() > 3 && matches!(args[0], Token::Symbol(_)) => {
+                let pairs = pairs(&args[1])?;
+                let params: Vec<Token> = pairs.iter().map(|(name, _)| name.clone()).collect();
+                let values: Vec<Token> = pairs.iter().map(|(_, value)| value.clone()).collect();
+                let values: Vec<String> =
+                    self.expressions(&values)?.into_iter().map(bare).collect();
+                let function = self.named_loop(&args[0], &params, &args[2..])?;
+                format!("({})({})", function, values.join(", "))
+            }
+            "let" if list.len() > 2 => {
+                let pairs = pairs(&args[0])?;
+                let params: Vec<Token> = pairs.iter().map(|(name, _)| name.clone()).collect();
+                let values: Vec<Token> = pairs.iter().map(|(_, value)| value.clone()).collect();
+                let values: Vec<String> =
+                    self.expressions(&values)?.into_iter().map(bare).collect();
+                let function = self.function(None, &params, &args[1..])?;
+                format!("({})({})", function, values.join(", "))
+            }
+            "while" | "cond" | "case" | "try" | "letrec" => {
+                let outer = self.looping.take();
+                let body = self.scoped(HashSet::new(), |this| this.statement(form, Mode::Return));
+                self.looping = outer;
+                let body = body?;
+                format!("(() => {{\n{}{}}})()", body, self.indent())
+            }
+            _ if SPECIAL_FORMS.contains(&head) => {
+                return Err(format!("can't compile {} to JavaScript", form))
+            }
+            _ => self.call(list)?,
+        };
+        Ok(code)
+    }
+
+    /// The function of a named `let` called `name`. Calls to it in tail position assign its
+    /// parameters and go round a `while (true)` instead, so long loops don't run out of stack.
+    /// A body creating functions is left calling itself, as the functions would see its
+    /// parameters change under them.
+    fn named_loop(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Token],
+    ) -> Result<String, String> {
+        let mangled = mangle(symbol(name).unwrap_or_default());
+        if body.iter().any(creates_functions) {
+            return self.function(Some(&mangled), params, body);
+        }
+        let mut names = HashSet::new();
+        params
+            .iter()
+            .for_each(|param| pattern_names(param, &mut names));
+        let names: HashSet<String> = names.into_iter().map(|name| mangle(&name)).collect();
+        let looping = Loop {
+            name: symbol(name).unwrap_or_default().to_string(),
+            params: self.parameters(params)?,
+            looped: false,
+        };
+        let (saved, outer) = (self.temporaries, self.looping.replace(looping));
+        self.depth += 1;
+        let code = self.scoped(names, |this| this.body(body, Mode::Return));
+        self.depth -= 1;
+        let looping = mem::replace(&mut self.looping, outer).unwrap();
+        let code = code?;
+        if !looping.looped {
+            self.temporaries = saved;
+            return self.function(Some(&mangled), params, body);
+        }
+        self.depth += 1;
+        let (open, close) = (self.line("while (true) {"), self.line("}"));
+        self.depth -= 1;
+        Ok(format!(
+            "function {}({}) {{\n{}{}{}{}}}",
+            mangled,
+            looping.params,
+            open,
+            code,
+            close,
+            self.indent()
+        ))
+    }
+
+    /// A call of the named `let` being compiled in tail position, its arguments assigned to
+    /// its parameters at once before going round its loop again.
+    fn next_iteration(&mut self, args: &[Token]) -> Result<String, String> {
+        let values: Vec<String> = self.expressions(args)?.into_iter().map(bare).collect();
+        let looping = self.looping.as_mut().unwrap();
+        looping.looped = true;
+        let params = looping.params.clone();
+        let assignment = match values.len() {
+            1 if !params.contains([',', '[', '.']) => format!("{} = {};", params, values[0]),
+            _ => format!("[{}] = [{}];", params, values.join(", ")),
+        };
+        Ok(self.line(&assignment) + &self.line("continue;"))
+    }
+
+    /// `(define (f x) ...)` inside an expression, assigning the function to `f`.
+    fn defined_function(&mut self, list: &[Token]) -> Result<String, String> {
+        let signature = match &list[1] {
+            Token::List(signature) if !signature.is_empty() => signature,
+            other => return Err(format!("can't compile define {} to JavaScript", other)),
+        };
+        let name = mangle(symbol(&signature[0]).unwrap_or_default());
+        let function = self.function(Some(&name), &signature[1..], &list[2..])?;
+        Ok(self.assign(&name, &function, false, true))
+    }
+
+    fn expressions(&mut self, forms: &[Token]) -> Result<Vec<String>, String> {
+        forms.iter().map(|form| self.expression(form)).collect()
+    }
+
+    /// Forms evaluated in turn as one expression, the value of the last.
+    fn sequence(&mut self, forms: &[Token]) -> Result<String, String> {
+        match forms {
+            [] => Ok("null".to_string()),
+            [form] => self.expression(form),
+            _ => Ok(format!("({})", self.expressions(forms)?.join(", "))),
+        }
+    }
+
+    fn call(&mut self, list: &[Token]) -> Result<String, String> {
+        let function = match &list[0] {
+            Token::Symbol(name) => self.variable(name.as_str())?,
+            other => format!("({})", self.expression(other)?),
+        };
+        let args: Vec<String> = self
+            .expressions(&list[1..])?
+            .into_iter()
+            .map(bare)
+            .collect();
+        Ok(format!("{}({})", function, args.join(", ")))
+    }
+
+    /// A variable, or the builtin of that name from the runtime.
+    fn variable(&mut self, name: &str) -> Result<String, String> {
+        if self.bound.contains(name) || self.declared(&mangle(name)) {
+            return Ok(mangle(name));
+        }
+        if name == "nil" {
+            return Ok("null".to_string());
+        }
+        if let Some(runtime) = operator(name) {
+            self.use_runtime(RUNTIME.iter().find(|(n, _, _)| *n == name).unwrap().0);
+            return Ok(runtime.to_string());
+        }
+        match RUNTIME.iter().find(|(n, _, _)| *n == name) {
+            Some((name, _, _)) => {
+                self.use_runtime(name);
+                Ok(mangle(name))
+            }
+            None if builtins::lookup(name).is_some() || SPECIAL_FORMS.contains(&name) => {
+                Err(format!("{} has no JavaScript version", name))
+            }
+            None => Ok(mangle(name)),
+        }
+    }
+
+    /// The value of a quoted form.
+    fn literal(&mut self, form: &Token) -> Result<String, String> {
+        Ok(match form {
+            Token::Int(i) => i.to_string(),
+            Token::Long(i) => i.to_string(),
+            Token::Ratio(n, d) => format!("({} / {})", n, d),
+            Token::Float(f) if f.is_finite() => format!("{:?}", f),
+            Token::Float(f) if f.is_nan() => "NaN".to_string(),
+            Token::Float(f) if *f > 0.0 => "Infinity".to_string(),
+            Token::Float(_) => "-Infinity".to_string(),
+            Token::Str(text) => string(text),
+            Token::True => "true".to_string(),
+            Token::False => "false".to_string(),
+            Token::Nil => "null".to_string(),
+            Token::Symbol(name) => format!("Symbol.for({})", string(name.as_str())),
+            Token::List(items) => {
+                let items: Result<Vec<String>, String> =
+                    items.iter().map(|item| self.literal(item)).collect();
+                format!("[{}]", items?.join(", "))
+            }
+            other => return Err(format!("can't compile the value {} to JavaScript", other)),
+        })
+    }
+}
+
+/// The `(name value)` pairs of a `let`.
+fn pairs(bindings: &Token) -> Result<Vec<(Token, Token)>, String> {
+    match bindings {
+        Token::List(bindings) => bindings
+            .iter()
+            .map(|binding| match binding {
+                Token::List(pair) if pair.len() == 2 => Ok((pair[0].clone(), pair[1].clone())),
+                _ => Err("let bindings need to be (name value) pairs".to_string()),
+            })
+            .collect(),
+        _ => Err("let bindings need to be a list".to_string()),
+    }
+}
+
+/// Whether `form` always evaluates to `true` or `false`, so it can be tested as it is.
+fn boolean(form: &Token) -> bool {
+    match form {
+        Token::True | Token::False => true,
+        Token::List(list) => match list.first().and_then(symbol) {
+            Some("<" | ">" | "<=" | ">=" | "=" | "!=") => true,
+            Some("and" | "or") => list[1..].iter().all(boolean),
+            _ => false,
+        },
+        _ => false,
+    }
+}
@@ -0,0 +1,150 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::{error, Eval, Token, Unwind};
+
+builtins! {
+    /// Finds the list builtin called `name`.
+    "list" => list,
+    "length" => length,
+    "append" => append,
+    "reverse" => reverse,
+    "nth" => nth,
+    "last" => last,
+    "assoc" => assoc,
+    "acons" => acons,
+}
+
+/// The items of a list or vector, or the `(key value)` pairs of a map.
+pub fn items(name: &str, value: Token) -> Result<Vec<Token>, Unwind> {
+    match value {
+        Token::List(items) => Ok(items),
+        Token::Vector(vector) => Ok(vector.items()),
+        Token::Map(map) => Ok(map
+            .entries()
+            .into_iter()
+            .map(|(key, value)| Token::List(vec![key.clone(), value.clone()]))
+            .collect()),
+        _ => error(format!("{} expects a list", name)),
+    }
+}
+
+/// `(list 1 2 3)`
+fn list(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::List(args))
+}
+
+/// `(length (list 1 2 3))`, also counts the characters of a string and the items of a map or
+/// vector.
+fn length(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("length", &args, 1, 1)?;
+    match &args[0] {
+        Token::List(items) => Ok(Token::Int(items.len() as i32)),
+        Token::Str(text) => Ok(Token::Int(text.chars().count() as i32)),
+        Token::Map(map) => Ok(Token::Int(map.len() as i32)),
+        Token::Vector(vector) => Ok(Token::Int(vector.len() as i32)),
+        _ => error("length expects a list, string, map or vector"),
+    }
+}
+
+/// `(append (list 1 2) (list 3) (list))`. Appending to a vector makes a vector, sharing the
+/// items of the first one.
+fn append(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    if let Some(Token::Vector(vector)) = args.first() {
+        let mut vector = vector.clone();
+        for arg in args.drain(1..) {
+            vector = items("append", arg)?
+                .into_iter()
+                .fold(vector, |vector, item| vector.push(item));
+        }
+        return Ok(Token::Vector(vector));
+    }
+    let mut result = vec![];
+    for arg in args {
+        result.extend(items("append", arg)?);
+    }
+    Ok(Token::List(result))
+}
+
+/// `(reverse (list 1 2 3))`
+fn reverse(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("reverse", &args, 1, 1)?;
+    let mut items = items("reverse", args.remove(0))?;
+    items.reverse();
+    Ok(Token::List(items))
+}
+
+/// `(nth (list 1 2 3) 0)`, indexes from zero.
+fn nth(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("nth", &args, 2, 2)?;
+    let index = match args[1].as_integer() {
+        Some(index) => index,
+        None => return error("nth expects an integer index"),
+    };
+    if let Token::Vector(vector) = &args[0] {
+        match vector.get(index as usize) {
+            Some(item) if index >= 0 => return Ok(item.clone()),
+            _ => {
+                return error(format!(
+                    "index {} out of bounds for vector of length {}",
+                    index,
+                    vector.len()
+                ))
+            }
+        }
+    }
+    let mut items = items("nth", args.remove(0))?;
+    if index < 0 || index as usize >= items.len() {
+        return error(format!(
+            "index {} out of bounds for list of length {}",
+            index,
+            items.len()
+        ));
+    }
+    Ok(items.swap_remove(index as usize))
+}
+
+/// `(last (list 1 2 3))`, evaluates to `Nil` for an empty list.
+fn last(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("last", &args, 1, 1)?;
+    if let Token::Vector(vector) = &args[0] {
+        return Ok(vector
+            .len()
+            .checked_sub(1)
+            .and_then(|i| vector.get(i))
+            .cloned()
+            .unwrap_or(Token::Nil));
+    }
+    Ok(items("last", args.remove(0))?.pop().unwrap_or(Token::Nil))
+}
+
+/// `(assoc 'b '((a 1) (b 2)))` finds the first `(key value)` pair with a matching key, or `false`.
+/// Finds the key of a map too, without going through the others.
+fn assoc(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("assoc", &args, 2, 2)?;
+    if let Token::Map(map) = &args[1] {
+        return Ok(match map.get(&args[0]) {
+            Some(value) => Token::List(vec![args[0].clone(), value.clone()]),
+            None => Token::False,
+        });
+    }
+    let alist = items("assoc", args.remove(1))?;
+    for pair in alist {
+        match &pair {
+            Token::List(items) if !items.is_empty() => {
+                if items[0] == args[0] {
+                    return Ok(pair);
+                }
+            }
+            _ => return error("assoc expects a list of (key value) pairs"),
+        }
+    }
+    Ok(Token::False)
+}
+
+/// `(acons 'a 1 alist)` adds a `(key value)` pair to the front of an association list.
+fn acons(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("acons", &args, 3, 3)?;
+    let mut alist = items("acons", args.pop().unwrap())?;
+    alist.insert(0, Token::List(args));
+    Ok(Token::List(alist))
+}
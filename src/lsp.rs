@@ -0,0 +1,295 @@
+//! A language server, so editors can show errors, documentation on hover, jump to definitions
+//! and format programs. Run with `lsp` instead of a source file, it talks over stdin/stdout.
+
+use crate::builtins;
+use crate::formatter::pretty;
+use crate::interpreter;
+use crate::json::Json;
+use crate::{parse, Lexer, Token};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// Answers the requests read from `input` until the editor asks to exit.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut documents: HashMap<String, String> = HashMap::new();
+    while let Some(message) = read_message(&mut input)? {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        let result = match method {
+            "initialize" => Json::object(vec![(
+                "capabilities",
+                Json::object(vec![
+                    ("positionEncoding", Json::Str("utf-16".to_string())),
+                    ("textDocumentSync", Json::Number(1.0)),
+                    ("hoverProvider", Json::Bool(true)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("documentFormattingProvider", Json::Bool(true)),
+                ]),
+            )]),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match params.get("textDocument").and_then(|d| d.get("text")) {
+                    Some(text) => text.as_str(),
+                    None => params
+                        .get("contentChanges")
+                        .and_then(|changes| match changes {
+                            Json::Array(changes) => changes.last(),
+                            _ => None,
+                        })
+                        .and_then(|change| change.get("text"))
+                        .and_then(Json::as_str),
+                };
+                let text = text.unwrap_or("").to_string();
+                let notification = Json::object(vec![
+                    ("jsonrpc", Json::Str("2.0".to_string())),
+                    (
+                        "method",
+                        Json::Str("textDocument/publishDiagnostics".to_string()),
+                    ),
+                    (
+                        "params",
+                        Json::object(vec![
+                            ("uri", Json::Str(uri.clone())),
+                            ("diagnostics", Json::Array(diagnostics(&text))),
+                        ]),
+                    ),
+                ]);
+                write_message(&mut output, &notification)?;
+                documents.insert(uri, text);
+                continue;
+            }
+            "textDocument/hover" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                hover(text, position(&params))
+            }
+            "textDocument/definition" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                definition(text, position(&params), &uri)
+            }
+            "textDocument/formatting" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                formatting(text)
+            }
+            "shutdown" => Json::Null,
+            "exit" => return Ok(()),
+            _ => continue,
+        };
+        // Requests carry an id to answer to, notifications don't get answers.
+        if let Some(id) = message.get("id") {
+            let response = Json::object(vec![
+                ("jsonrpc", Json::Str("2.0".to_string())),
+                ("id", id.clone()),
+                ("result", result),
+            ]);
+            write_message(&mut output, &response)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Json::parse(&String::from_utf8_lossy(&body))
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Columns of `line` counted in UTF-16 code units, the way editors count them, for `column`
+/// counted in characters.
+fn utf16(line: &str, column: usize) -> usize {
+    line.chars().take(column).map(char::len_utf16).sum()
+}
+
+/// Tokens with the 0-based line and UTF-16 column where they start.
+fn tokens(text: &str) -> Vec<(Token, usize, usize)> {
+    let lines: Vec<&str> = text.lines().collect();
+    Lexer::new(text.to_string())
+        .spanned()
+        .0
+        .into_iter()
+        .map(|(token, span)| {
+            let (line, column) = (span.line - 1, span.column - 1);
+            let column = lines.get(line).map_or(column, |text| utf16(text, column));
+            (token, line, column)
+        })
+        .collect()
+}
+
+fn diagnostics(text: &str) -> Vec<Json> {
+    let errors = match parse(Lexer::new(text.to_string())) {
+        Ok(_) => return vec![],
+        Err(errors) => errors,
+    };
+    errors
+        .into_iter()
+        .map(|error| {
+            let (line, column) = (error.span.line - 1, error.span.column - 1);
+            // Only unterminated strings span lines, marking their first line is enough there.
+            let text = text.lines().nth(line).unwrap_or("");
+            let width = text.chars().count().saturating_sub(column);
+            let end = column + (error.span.end - error.span.start).min(width);
+            Json::object(vec![
+                (
+                    "range",
+                    Json::object(vec![
+                        ("start", location(line, utf16(text, column))),
+                        ("end", location(line, utf16(text, end))),
+                    ]),
+                ),
+                ("severity", Json::Number(1.0)),
+                ("message", Json::Str(error.message)),
+            ])
+        })
+        .collect()
+}
+
+fn location(line: usize, column: usize) -> Json {
+    Json::object(vec![
+        ("line", Json::Number(line as f64)),
+        ("character", Json::Number(column as f64)),
+    ])
+}
+
+fn position(params: &Json) -> (usize, usize) {
+    let position = params.get("position");
+    let get = |key| position.and_then(|p| p.get(key)).and_then(Json::as_f64);
+    (
+        get("line").unwrap_or(0.0) as usize,
+        get("character").unwrap_or(0.0) as usize,
+    )
+}
+
+/// The symbol under the cursor, at a UTF-16 column.
+fn symbol_at(tokens: &[(Token, usize, usize)], (line, column): (usize, usize)) -> Option<String> {
+    tokens
+        .iter()
+        .find_map(|(token, start_line, start)| match token {
+            Token::Symbol(name)
+                if *start_line == line
+                    && (*start..*start + name.as_str().encode_utf16().count())
+                        .contains(&column) =>
+            {
+                Some(name.to_string())
+            }
+            _ => None,
+        })
+}
+
+/// Where symbols are introduced by `(define name ...)` or `(define (name ...) ...)`, with the
+/// text of their signature.
+fn definitions(tokens: &[(Token, usize, usize)]) -> Vec<(String, usize, usize, String)> {
+    let mut found = vec![];
+    for (i, window) in tokens.windows(2).enumerate() {
+        let is_define = matches!(&window[1].0, Token::Symbol(name) if name.as_str() == "define");
+        if !matches!(window[0].0, Token::Open) || !is_define {
+            continue;
+        }
+        let rest = &tokens[i + 2..];
+        match rest.first() {
+            Some((Token::Symbol(name), line, column)) => {
+                found.push((name.to_string(), *line, *column, name.to_string()))
+            }
+            Some((Token::Open, _, _)) => {
+                let signature: Vec<String> = rest[1..]
+                    .iter()
+                    .take_while(|(token, _, _)| !matches!(token, Token::Close | Token::Open))
+                    .map(|(token, _, _)| token.to_string())
+                    .collect();
+                if let Some((Token::Symbol(name), line, column)) = rest.get(1) {
+                    let signature = format!("({})", signature.join(" "));
+                    found.push((name.to_string(), *line, *column, signature));
+                }
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+fn hover(text: &str, at: (usize, usize)) -> Json {
+    let tokens = tokens(text);
+    let name = match symbol_at(&tokens, at) {
+        Some(name) => name,
+        None => return Json::Null,
+    };
+    let contents = if let Some((_, _, _, signature)) = definitions(&tokens)
+        .into_iter()
+        .find(|(defined, ..)| *defined == name)
+    {
+        format!("`{}`, defined in this file", signature)
+    } else if interpreter::SPECIAL_FORMS.contains(&name.as_str()) {
+        format!("`{}`, a special form", name)
+    } else if builtins::lookup(&name).is_some() {
+        format!("`{}`, a builtin function", name)
+    } else {
+        return Json::Null;
+    };
+    Json::object(vec![("contents", Json::Str(contents))])
+}
+
+fn definition(text: &str, at: (usize, usize), uri: &str) -> Json {
+    let tokens = tokens(text);
+    let name = match symbol_at(&tokens, at) {
+        Some(name) => name,
+        None => return Json::Null,
+    };
+    match definitions(&tokens)
+        .into_iter()
+        .find(|(defined, ..)| *defined == name)
+    {
+        Some((name, line, column, _)) => Json::object(vec![
+            ("uri", Json::Str(uri.to_string())),
+            (
+                "range",
+                Json::object(vec![
+                    ("start", location(line, column)),
+                    ("end", location(line, column + name.encode_utf16().count())),
+                ]),
+            ),
+        ]),
+        None => Json::Null,
+    }
+}
+
+/// Replaces the whole document with its formatted version, leaving programs that don't parse
+/// alone.
+fn formatting(text: &str) -> Json {
+    let formatted = match parse(Lexer::new(text.to_string())) {
+        Ok(ast) => pretty(&ast),
+        Err(_) => return Json::Array(vec![]),
+    };
+    let lines = text.lines().count() + 1;
+    Json::Array(vec![Json::object(vec![
+        (
+            "range",
+            Json::object(vec![("start", location(0, 0)), ("end", location(lines, 0))]),
+        ),
+        ("newText", Json::Str(formatted)),
+    ])])
+}
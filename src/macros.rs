@@ -0,0 +1,56 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::{error, Eval, Token};
+
+builtins! {
+    /// Finds the macro builtin called `name`.
+    "macroexpand" => macroexpand,
+}
+
+/// Expands the code of a call to one of the macros every program has.
+pub type Expander = fn(&[Token]) -> Eval;
+
+/// The names of the macros every program has.
+pub const BUILTIN_NAMES: &[&str] = &["->", "->>"];
+
+/// Finds the macro every program has called `name`, programs can `defmacro` their own over it.
+pub fn builtin(name: &str) -> Option<Expander> {
+    let expander: Expander = match name {
+        "->" => thread_first,
+        "->>" => thread_last,
+        _ => return None,
+    };
+    Some(expander)
+}
+
+/// `(-> x (f a) g)` is `(g (f x a))`, passing the value along as the first argument of each form.
+fn thread_first(args: &[Token]) -> Eval {
+    thread("->", args, |items, value| items.insert(1, value))
+}
+
+/// `(->> x (f a) g)` is `(g (f a x))`, passing the value along as the last argument of each form.
+fn thread_last(args: &[Token]) -> Eval {
+    thread("->>", args, |items, value| items.push(value))
+}
+
+fn thread(name: &str, args: &[Token], pass: fn(&mut Vec<Token>, Token)) -> Eval {
+    let (value, forms) = match args.split_first() {
+        Some(split) => split,
+        None => return error(format!("{} expects a value to pass along", name)),
+    };
+    Ok(forms.iter().fold(value.clone(), |value, form| match form {
+        Token::List(items) if !items.is_empty() => {
+            let mut items = items.clone();
+            pass(&mut items, value);
+            Token::List(items)
+        }
+        function => Token::List(vec![function.clone(), value]),
+    }))
+}
+
+/// `(macroexpand '(twice (print 1)))` is the code `(twice (print 1))` runs as, with every macro
+/// call in it expanded.
+fn macroexpand(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("macroexpand", &args, 1, 1)?;
+    interpreter.expand(&args[0])
+}
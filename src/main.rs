@@ -1,3 +1,6 @@
+// Values holding resources like connections hash and compare by identity, so they can be keys.
+#![allow(clippy::mutable_key_type)]
+
 /// Micro lispesque language
 ///
 /// A tiny interpreter for a language that doesn't do much.
@@ -12,163 +15,1155 @@
 /// Running the program:
 /// `cargo run -- ./examples/loop.mlsp`
 ///
+/// Without a source file, `cargo run` starts a REPL: type code to see its values, or `:help` for
+/// commands like `:env`, `:load file` and `:time expr`.
+///
+/// `--sandbox` denies programs access to the system (`sh`, `mkdir`, `tcp-connect`, `http-get`, ...).
+/// `--no-prelude` skips defining the helpers of `src/prelude.mlsp` (`min`, `max`, `range`, ...).
+///
+/// `--expand` prints the program with its macro calls expanded instead of running it.
+///
+/// `--step` pauses after each top-level form, showing its value and the variables it changed
+/// until Enter is pressed.
+///
+/// `--color=auto|always|never` colors errors, `--error-format=json` writes them as JSON lines.
+///
+/// `--highlight=ansi` or `--highlight=html` prints the program syntax-highlighted instead of
+/// running it.
+///
+/// `cargo run -- doc src/ -o docs/` writes Markdown pages documenting the `define`s of the
+/// `.mlsp` files in `src/`, from docstrings starting the functions' bodies.
+///
+/// `cargo run -- lsp` starts a language server talking to an editor over stdin/stdout.
+///
 mod arithmetic;
+mod builtins;
+mod bytes;
+mod cache;
+mod cfg;
+#[cfg(feature = "complex")]
+mod complex;
+mod contracts;
+mod coverage;
+#[cfg(feature = "datetime")]
+mod dates;
+mod diagnostics;
+mod docs;
+mod dot;
+mod errors;
+mod files;
+mod formatter;
+mod generators;
+mod higher_order;
+mod highlight;
+#[cfg(feature = "http")]
+mod http;
+mod identity;
+mod image;
+mod interpreter;
+mod interrupt;
+mod js;
+mod json;
+mod lists;
+mod lsp;
+mod macros;
+mod math;
+#[cfg(feature = "net")]
+mod net;
+mod output;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod persistent;
+mod ports;
+mod printing;
+mod reading;
+mod regexes;
+mod repl;
+mod sets;
+mod strings;
+mod symbols;
+mod system;
+mod trace;
+mod vars;
+mod visit;
+mod wasm;
 
-use crate::Token::{Close, False, Int, List, Open, Symbol, True};
-use regex::Regex;
-use std::collections::HashMap;
-use std::{env, fs};
+use crate::diagnostics::{Diagnostic, Style};
+use crate::errors::Failure;
+use crate::generators::Generator;
+use crate::higher_order::Memo;
+use crate::highlight::Format;
+use crate::interpreter::Interpreter;
+#[cfg(feature = "parallel")]
+use crate::parallel::{Atom, Channel};
+use crate::persistent::{Map, Vector};
+use crate::ports::Port;
+use crate::symbols::Name;
+use crate::vars::Scope;
+use crate::Token::{Close, False, Float, List, Open, Quote, Str, Symbol, True};
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt;
+#[cfg(feature = "net")]
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+use std::{cell::RefCell, rc::Rc};
+use std::{env, fs, io, process, slice, thread};
 
-#[derive(Hash, Eq, Debug, Clone)]
+#[derive(Debug, Clone)]
 enum Token {
     Open,
     Close,
+    /// A prefix like `'`, with the name of the form wrapping the item after it.
+    Quote(Name),
     Int(i32),
-    Symbol(String),
+    /// An integer out of the range of `Int`, which results back in range turn into again.
+    Long(i64),
+    /// An exact fraction, in lowest terms with a denominator above 1.
+    Ratio(i64, i64),
+    Float(f64),
+    /// A number with real and imaginary parts.
+    #[cfg(feature = "complex")]
+    Complex(f64, f64),
+    Symbol(Name),
+    Str(String),
     List(Vec<Token>),
+    Set(HashSet<Token>),
+    Map(Map),
+    Vector(Vector),
+    Lambda(Vec<Token>, Vec<Token>, Scope),
     True,
     False,
+    Nil,
+    Continuation(usize),
+    Memoized(Rc<Memo>),
+    Error(Rc<Failure>),
+    /// A value `freeze` made, which the forms changing variables in place refuse to change.
+    Frozen(Rc<Token>),
+    /// A Rust value an embedder gave the program, which it can only pass around and call the
+    /// methods registered for it with.
+    Native(Rc<dyn Any>),
+    Values(Vec<Token>),
+    Bytes(Vec<u8>),
+    Port(Rc<RefCell<Port>>),
+    #[cfg(feature = "net")]
+    Connection(Rc<RefCell<TcpStream>>),
+    #[cfg(feature = "net")]
+    Listener(Rc<TcpListener>),
+    #[cfg(feature = "parallel")]
+    Channel(Channel),
+    #[cfg(feature = "parallel")]
+    Atom(Atom),
+    Generator(Generator),
+}
+
+/// Prefixes standing for a form wrapping the item after them, `'x` is `(quote x)`.
+const PREFIXES: &[(&str, &str)] = &[
+    ("'", "quote"),
+    ("`", "quasiquote"),
+    (",@", "unquote-splicing"),
+    (",", "unquote"),
+];
+
+/// Characters besides letters and digits, of any script, symbols are made of, like in `is-empty?`, `set!`,
+/// `my_var` or `*debug*`, and `:` for the contracts of parameters like `n:int`. Digits can't
+/// start one, and neither can `+` or `-` followed by a digit, which start a number.
+const SYMBOL_CHARS: &str = "+-*/<>=!?_%&:";
+
+/// The prefix standing for the form called `name`, if there's one.
+fn prefix(name: &str) -> Option<&'static str> {
+    PREFIXES
+        .iter()
+        .find(|(_, form)| *form == name)
+        .map(|(prefix, _)| *prefix)
+}
+
+/// Formats values the way `println` shows them: strings without quotes, lists in parentheses.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Quote(name) => write!(f, "{}", prefix(name.as_str()).unwrap_or("'")),
+            Token::Int(i) => write!(f, "{}", i),
+            Token::Long(i) => write!(f, "{}", i),
+            Token::Ratio(n, d) => write!(f, "{}/{}", n, d),
+            Token::Float(n) => write!(f, "{:?}", n),
+            #[cfg(feature = "complex")]
+            Token::Complex(re, im) => write!(f, "{:?}{:+?}i", re, im),
+            Token::Symbol(name) => write!(f, "{}", name),
+            Token::Str(s) => write!(f, "{}", s),
+            Token::List(items) => write!(f, "({})", joined(items.iter())),
+            Token::Set(items) => write!(f, "#{{{}}}", joined(sets::sorted(items).into_iter())),
+            Token::Map(map) => {
+                let entries: Vec<String> = map
+                    .entries()
+                    .iter()
+                    .map(|(key, value)| format!("{} {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Token::Vector(vector) => write!(f, "[{}]", joined(vector.items().iter())),
+            Token::Values(items) => write!(f, "{}", joined(items.iter())),
+            Token::Bytes(data) => {
+                let data = data.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+                write!(f, "#u8({})", data.join(" "))
+            }
+            Token::Lambda(..) => write!(f, "<lambda>"),
+            Token::Continuation(_) => write!(f, "<continuation>"),
+            Token::Memoized(_) => write!(f, "<memoized>"),
+            Token::Error(failure) => write!(f, "<error {}: {}>", failure.kind, failure.message),
+            Token::Frozen(value) => write!(f, "{}", value),
+            Token::Native(_) => write!(f, "<native>"),
+            Token::True => write!(f, "true"),
+            Token::False => write!(f, "false"),
+            Token::Nil => write!(f, "nil"),
+            Token::Port(_) => write!(f, "<port>"),
+            #[cfg(feature = "net")]
+            Token::Connection(_) => write!(f, "<connection>"),
+            #[cfg(feature = "net")]
+            Token::Listener(_) => write!(f, "<listener>"),
+            #[cfg(feature = "parallel")]
+            Token::Channel(_) => write!(f, "<channel>"),
+            #[cfg(feature = "parallel")]
+            Token::Atom(_) => write!(f, "<atom>"),
+            Token::Generator(_) => write!(f, "<generator>"),
+        }
+    }
+}
+
+fn joined<'a>(items: impl Iterator<Item = &'a Token>) -> String {
+    items
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reasons for the evaluation of an expression to stop before producing a value.
+#[derive(Debug)]
+enum Unwind {
+    /// A runtime error, ends the program.
+    Error(String),
+    /// A continuation was invoked, unwinds up to the `call/cc` that created it.
+    Escape(usize, Token),
+    /// A value was thrown by `throw`, unwinds up to the innermost `try`.
+    Thrown(Token),
+    /// `return` was evaluated, unwinds up to the function call it's in.
+    Return(Token),
 }
 
+type Eval = Result<Token, Unwind>;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Invalid number of arguments. Expected 1 argument with source code file.");
+    let (flags, args): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    if args == ["lsp"] {
+        if let Err(e) = lsp::serve(io::stdin().lock(), io::stdout()) {
+            eprintln!("Language server stopped: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("doc") {
+        let (sources, out) = match &args[1..] {
+            [sources] => (sources, "docs"),
+            [sources, flag, out] if flag == "-o" => (sources, out.as_str()),
+            _ => {
+                eprintln!("Usage: doc <source directory> [-o <output directory>]");
+                return;
+            }
+        };
+        match docs::generate(Path::new(sources), Path::new(out)) {
+            Ok(pages) => pages.iter().for_each(|page| println!("{}", page.display())),
+            Err(e) => {
+                eprintln!("Can't generate documentation: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("disasm") {
+        let path = match &args[1..] {
+            [path] => path,
+            _ => {
+                eprintln!("Usage: disasm <cache file>, e.g. disasm main.mlspc");
+                return;
+            }
+        };
+        match fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| cache::disassemble(&bytes))
+        {
+            Ok(listing) => print!("{}", listing),
+            Err(e) => {
+                eprintln!("Can't disassemble {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    // `compile` runs nothing, it's `--emit=js` unless another `--emit` is given.
+    let compiling = args.first().map(String::as_str) == Some("compile");
+    let args = &args[compiling as usize..];
+    let address = match args {
+        [command, address] if command == "repl" => Some(address.clone()),
+        _ if args.len() > 1 => {
+            eprintln!(
+                "Invalid number of arguments. Expected a source code file, or none for a REPL."
+            );
+            return;
+        }
+        _ => None,
+    };
+    let mut interpreter = Interpreter::new();
+    let mut prelude = true;
+    let mut highlight = None;
+    let mut step = false;
+    let mut expand = false;
+    let mut watching = false;
+    let mut listening = false;
+    let mut covering = false;
+    let mut emit = if compiling { Some("js") } else { None };
+    let (mut record, mut replay) = (None, None);
+    let (mut color, mut error_format) = ("auto", "human");
+    for flag in &flags {
+        match flag.as_str() {
+            "--sandbox" => interpreter.set_sandboxed(true),
+            "--no-prelude" => prelude = false,
+            "--step" => step = true,
+            "--expand" => expand = true,
+            "--watch" => watching = true,
+            "--listen" => listening = true,
+            "--coverage" => covering = true,
+            "--highlight" => highlight = Some(Format::Ansi),
+            flag if flag.starts_with("--color=") => color = &flag["--color=".len()..],
+            flag if flag.starts_with("--emit=") => emit = Some(&flag["--emit=".len()..]),
+            flag if flag.starts_with("--record=") => record = Some(&flag["--record=".len()..]),
+            flag if flag.starts_with("--replay=") => replay = Some(&flag["--replay=".len()..]),
+            flag if flag.starts_with("--error-format=") => {
+                error_format = &flag["--error-format=".len()..]
+            }
+            flag if flag.starts_with("--highlight=") => {
+                match Format::parse(&flag["--highlight=".len()..]) {
+                    Some(format) => highlight = Some(format),
+                    None => {
+                        eprintln!("Unknown highlighting format in {}, use ansi or html", flag);
+                        return;
+                    }
+                }
+            }
+            _ => {
+                eprintln!(
+                    "Unknown option {}. Supported options: --sandbox, --no-prelude, --step, --expand, --watch, --listen, --coverage, --emit=js|wasm|dot|cfg, --record=<trace>, --replay=<trace>, --highlight[=ansi|html], --color=auto|always|never, --error-format=human|json",
+                    flag
+                );
+                return;
+            }
+        }
+    }
+    if record.is_some() && replay.is_some() {
+        eprintln!("Use either --record or --replay, a run can't be recorded while it's replayed");
+        return;
+    }
+    if listening != address.is_some() {
+        eprintln!("Usage: repl --listen <address>, e.g. repl --listen 127.0.0.1:7878");
+        return;
+    }
+    let style = match Style::parse(color, error_format) {
+        Some(style) => style,
+        None => {
+            eprintln!("Unknown --color or --error-format, use auto|always|never and human|json");
+            return;
+        }
+    };
+    if prelude {
+        interpreter
+            .load_prelude()
+            .expect("Something went wrong loading the prelude");
+    }
+    interrupt::on_ctrl_c(interpreter.interrupt_handle());
+    if let Some(address) = address {
+        listen(&mut interpreter, &address, prelude);
+        return;
+    }
+    if emit.is_some() && args.is_empty() {
+        eprintln!("Usage: compile [--emit=js|wasm|dot|cfg] <source file>");
+        return;
+    }
+    if args.is_empty() {
+        if let Err(e) = repl::run(&mut interpreter, io::stdin().lock(), prelude) {
+            eprintln!("Can't read input: {}", e);
+            process::exit(1);
+        }
         return;
     }
     let contents =
-        fs::read_to_string(&args[1]).expect("Something went wrong reading the source file");
+        fs::read_to_string(&args[0]).expect("Something went wrong reading the source file");
+    if let Some(format) = highlight {
+        print!("{}", highlight::highlight(&contents, format));
+        return;
+    }
+
+    let path = &args[0];
+    if watching {
+        watch(&mut interpreter, path, style);
+        return;
+    }
+    let ast = match parsed(&mut interpreter, path, &contents, style) {
+        Some(ast) => ast,
+        None => process::exit(1),
+    };
+    if let Some(target) = emit {
+        if let Err(message) = compile(&mut interpreter, &ast, path, target, prelude, expand) {
+            let _ = interpreter.write_err(format!("error: {}\n", message));
+            process::exit(1);
+        }
+        return;
+    }
+    if expand {
+        match interpreter.expand_program(&ast) {
+            Ok(expanded) => print!("{}", formatter::pretty(&expanded)),
+            Err(message) => {
+                let _ = interpreter.write_err(message + "\n");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(trace) = replay {
+        match fs::read(trace).ok().and_then(|bytes| trace::decode(&bytes)) {
+            Some(events) => interpreter.replay(events),
+            None => {
+                eprintln!("Can't replay {}, it isn't a recorded trace", trace);
+                process::exit(1);
+            }
+        }
+    }
+    if record.is_some() {
+        interpreter.record();
+    }
+    let succeeded = match covering {
+        true => run_covered(&mut interpreter, &ast, path, &contents, style, step),
+        false => run_forms(&mut interpreter, &ast, path, &contents, style, step),
+    };
+    // Failed runs are recorded too, they're the ones worth replaying.
+    if let Some(trace) = record {
+        if let Err(e) = fs::write(trace, trace::encode(interpreter.recorded())) {
+            eprintln!("Can't write the trace to {}: {}", trace, e);
+            process::exit(1);
+        }
+    }
+    if !succeeded {
+        process::exit(1);
+    }
+}
+
+/// `--emit`: prints the program compiled to `target` instead of running it, or writes it next
+/// to the source for WebAssembly. The DOT graph is of the program as it's written, unless
+/// `expand` asks for it after expanding macros.
+fn compile(
+    interpreter: &mut Interpreter,
+    ast: &[Token],
+    path: &str,
+    target: &str,
+    prelude: bool,
+    expand: bool,
+) -> Result<(), String> {
+    let ast = match expand || target != "dot" {
+        true => interpreter.expand_program(ast)?,
+        false => ast.to_vec(),
+    };
+    match target {
+        "dot" => print!("{}", dot::graph(&ast)),
+        "cfg" => print!("{}", cfg::graph(&ast)),
+        "js" => {
+            let prelude = match prelude {
+                true => parse(Lexer::new(interpreter::PRELUDE.to_string()))
+                    .map_err(|errors| report(&errors))?,
+                false => vec![],
+            };
+            print!("{}", js::compile(&ast, &prelude)?);
+        }
+        "wasm" => {
+            let (module, skipped) = wasm::compile(&ast)?;
+            for (name, reason) in skipped {
+                let _ = interpreter.write_err(format!("left out {}: {}\n", name, reason));
+            }
+            let out = Path::new(path).with_extension("wasm");
+            fs::write(&out, module).map_err(|e| format!("can't write {}: {}", out.display(), e))?;
+            println!("{}", out.display());
+        }
+        _ => {
+            return Err(format!(
+                "unknown --emit target {}, use js, wasm, dot or cfg",
+                target
+            ))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "net")]
+fn listen(interpreter: &mut Interpreter, address: &str, prelude: bool) {
+    if let Err(e) = repl::listen(interpreter, address, prelude) {
+        eprintln!("Can't listen on {}: {}", address, e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "net"))]
+fn listen(_interpreter: &mut Interpreter, _address: &str, _prelude: bool) {
+    eprintln!("The remote REPL needs the net feature");
+    process::exit(1);
+}
+
+/// The tree of the program `contents` in the file at `path`, `None` after reporting its syntax
+/// errors.
+fn parsed(
+    interpreter: &mut Interpreter,
+    path: &str,
+    contents: &str,
+    style: Style,
+) -> Option<Vec<Token>> {
+    match cache::parsed(Path::new(path), contents.to_string()) {
+        Ok(ast) => Some(ast),
+        Err(errors) => {
+            let errors: Vec<Diagnostic> = errors.iter().map(Diagnostic::from).collect();
+            let _ = interpreter.write_err(diagnostics::report(&errors, path, contents, style));
+            None
+        }
+    }
+}
+
+/// Runs the top-level forms of a program, reporting the error stopping it. `step` pauses after
+/// each one. Whether it ran to the end.
+fn run_forms(
+    interpreter: &mut Interpreter,
+    ast: &[Token],
+    path: &str,
+    contents: &str,
+    style: Style,
+    step: bool,
+) -> bool {
+    // Forms run one at a time, so an error can point at the one it happened in.
+    for (index, form) in ast.iter().enumerate() {
+        let result = if step {
+            repl::step(interpreter, form, &mut io::stdin().lock())
+        } else {
+            interpreter.run_parsed(slice::from_ref(form)).map(drop)
+        };
+        if let Err(message) = result {
+            let rendered = match diagnostics::forms(contents).get(index) {
+                Some(span) => {
+                    let error = Diagnostic::runtime(message, *span);
+                    diagnostics::report(&[error], path, contents, style)
+                }
+                None => message + "\n",
+            };
+            let _ = interpreter.write_err(rendered);
+            return false;
+        }
+    }
+    true
+}
 
-    run(contents);
+/// `--coverage`: runs the program like `run_forms`, then writes which of its lines ran next to
+/// it, as `.lcov` and `.coverage.html` reports.
+fn run_covered(
+    interpreter: &mut Interpreter,
+    ast: &[Token],
+    path: &str,
+    contents: &str,
+    style: Style,
+    step: bool,
+) -> bool {
+    let (ast, instrumented) = coverage::instrument(ast, contents, interpreter);
+    let succeeded = run_forms(interpreter, &ast, path, contents, style, step);
+    let covered = interpreter.covered();
+    let lcov = Path::new(path).with_extension("lcov");
+    let html = Path::new(path).with_extension("coverage.html");
+    let written = fs::write(&lcov, coverage::lcov(path, &instrumented, covered)).and_then(|_| {
+        fs::write(
+            &html,
+            coverage::html(path, contents, &instrumented, covered),
+        )
+    });
+    let run = instrumented
+        .iter()
+        .filter(|line| covered.contains_key(line))
+        .count();
+    let summary = match written {
+        Ok(()) => format!(
+            "coverage: {} of {} lines run, see {} and {}\n",
+            run,
+            instrumented.len(),
+            lcov.display(),
+            html.display()
+        ),
+        Err(e) => format!("Can't write the coverage reports: {}\n", e),
+    };
+    let _ = interpreter.write_err(summary);
+    succeeded
+}
+
+/// `--watch`: runs the program at `path` again whenever it changes, in the same interpreter so
+/// the variables of the last run are still there, until Ctrl-C.
+fn watch(interpreter: &mut Interpreter, path: &str, style: Style) {
+    let mut seen = None;
+    loop {
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_some() && modified != seen {
+            seen = modified;
+            match fs::read_to_string(path) {
+                Ok(contents) => {
+                    if let Some(ast) = parsed(interpreter, path, &contents, style) {
+                        run_forms(interpreter, &ast, path, &contents, style, false);
+                    }
+                }
+                Err(e) => eprintln!("Can't read {}: {}", path, e),
+            }
+            eprintln!("[watching {} for changes, Ctrl-C to stop]", path);
+        }
+        // Ctrl-C between runs is for the watching itself.
+        if interpreter.interrupt_handle().take() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
 }
 
-fn run(text: String) -> Vec<Token> {
-    // Tokenize!
-    let lexer = Lexer::new(text);
-    // Parse!
-    let ast = parse(lexer);
-    // Evaluate!
-    ast.into_iter().fold(vec![], |mut acc, node| {
-        acc.push(evaluate(&node, &mut HashMap::new()));
-        acc
-    })
+/// Builds the tree of a program. A program that doesn't parse is read again to report every
+/// error at once: after an error, the rest of the top-level form is skipped up to the next `(`
+/// starting a line.
+fn parse(lexer: Lexer) -> Result<Vec<Token>, Vec<SyntaxError>> {
+    let (tokens, lexing) = lexer.spanned();
+    let (ast, errors) = build(&tokens, false);
+    if errors.is_empty() && lexing.is_empty() {
+        return Ok(ast);
+    }
+    let (_, mut located) = build(&tokens, true);
+    if located.is_empty() {
+        located = errors;
+    }
+    located.extend(lexing);
+    located.sort_by_key(|error| error.span.start);
+    Err(located)
 }
 
-fn parse(lexer: Lexer) -> Vec<Token> {
+/// The tree of the program `tokens` make up, and its errors. `resync` takes a `(` starting a
+/// line for the start of the next top-level form, closing whatever came before.
+fn build(tokens: &[(Token, Span)], resync: bool) -> (Vec<Token>, Vec<SyntaxError>) {
+    let mut errors = vec![];
     let mut list_stack: Vec<Vec<Token>> = vec![vec![]];
     let mut curr_list = 0;
+    // Where each list being built starts, and whether a `'` opened it.
+    let mut opens: Vec<(Span, bool)> = vec![];
+    // Lists opened by a `'`, they close by themselves after one item.
+    let mut quotes: Vec<usize> = vec![];
+    let mut skipping = false;
 
-    for token in lexer {
-        match token {
+    for (token, span) in tokens.iter().cloned() {
+        if resync && matches!(token, Open) && span.column == 1 {
+            // Most likely the next top-level form, whatever came before.
+            if let (Some(open), false) = (opens.first(), skipping) {
+                errors.push(unclosed(*open));
+            }
+            list_stack.truncate(1);
+            curr_list = 0;
+            opens.clear();
+            quotes.clear();
+            skipping = false;
+        }
+        if skipping {
+            continue;
+        }
+        let mut node = match token {
             Open => {
                 list_stack.push(vec![]);
-                curr_list = curr_list + 1;
+                curr_list += 1;
+                opens.push((span, false));
+                continue;
+            }
+            Quote(name) => {
+                list_stack.push(vec![Symbol(name)]);
+                curr_list += 1;
+                quotes.push(curr_list);
+                opens.push((span, true));
+                continue;
+            }
+            Close if curr_list == 0 => {
+                errors.push(SyntaxError::new("unexpected closing parenthesis", span));
+                continue;
             }
             Close => {
+                if quotes.last() == Some(&curr_list) {
+                    errors.push(
+                        SyntaxError::new("nothing to quote before closing parenthesis", span)
+                            .with_note(QUOTE_NOTE),
+                    );
+                    skipping = true;
+                    continue;
+                }
                 let last = list_stack.pop().unwrap();
-                curr_list = curr_list - 1;
-                list_stack[curr_list].push(List(last));
-            }
-            Token::Int(_) | Symbol(_) | List(_) => {
-                list_stack[curr_list].push(token);
+                curr_list -= 1;
+                opens.pop();
+                List(last)
             }
+            Token::Int(_)
+            | Token::Long(_)
+            | Float(_)
+            | Symbol(_)
+            | Str(_)
+            | List(_)
+            | True
+            | False => token,
             _ => panic!("unrecognized token in parsing"),
+        };
+        while quotes.last() == Some(&curr_list) {
+            quotes.pop();
+            let mut quote = list_stack.pop().unwrap();
+            curr_list -= 1;
+            opens.pop();
+            quote.push(node);
+            node = List(quote);
         }
+        list_stack[curr_list].push(node);
     }
 
-    if list_stack.len() != 1 {
-        panic!("unmatched parenthesis");
+    if let (Some(open), false) = (opens.first(), skipping) {
+        errors.push(unclosed(*open));
     }
-
-    list_stack.into_iter().flatten().collect()
+    (list_stack.into_iter().flatten().collect(), errors)
 }
 
-fn evaluate(node: &Token, vars: &mut HashMap<Token, Token>) -> Token {
-    match node {
-        Open => panic!("open symbol in AST makes no sense"),
-        Close => panic!("open symbol in AST makes no sense"),
-        Int(number) => Int(number.to_owned()),
-        Symbol(symbol) => {
-            match vars.get(&Symbol(symbol.to_string())) {
-                None => Symbol(symbol.to_string()), //panic!("unknown symbol"),
-                Some(value) => value.clone(),
-            }
-        }
-        List(list) => match list.first().unwrap() {
-            Symbol(symbol) => match symbol.as_str() {
-                "+" => evaluate(&list[1], vars) + evaluate(&list[2], vars),
-                "-" => evaluate(&list[1], vars) - evaluate(&list[2], vars),
-                "*" => evaluate(&list[1], vars) * evaluate(&list[2], vars),
-                ">" => remap_bool(evaluate(&list[1], vars) > evaluate(&list[2], vars)),
-                "<" => remap_bool(evaluate(&list[1], vars) < evaluate(&list[2], vars)),
-                "=" => remap_bool(evaluate(&list[1], vars) == evaluate(&list[2], vars)),
-                "if" => {
-                    if let True = evaluate(&list[1], vars) {
-                        evaluate(&list[2], vars)
-                    } else {
-                        evaluate(&list[3], vars)
-                    }
-                }
-                "while" => {
-                    let mut value = False;
-                    while let True = evaluate(&list[1], vars) {
-                        value = evaluate(&list[2], vars);
-                    }
-                    value
-                }
-                "do" => {
-                    List(list[1..].iter().fold(vec![], |mut acc, node| {
-                        acc.push(evaluate(&node, vars));
-                        acc
-                    }))
-                }
-                "set" => {
-                    let value = evaluate(&list[2], vars);
-                    vars.insert(list[1].clone(), value.clone());
-                    value
-                }
-                "print" => {
-                    let value = evaluate(&list[1], vars);
-                    println!("{:?}", value.clone());
-                    value
-                }
-                _ => match vars.get(&Symbol(symbol.to_string())) {
-                    None => panic!("unknown symbol"),
-                    Some(value) => value.clone(),
-                },
-            },
-            _ => {
-                eprintln!("LIST {:?}", list);
-                panic!("can't evaluate list, first item needs to be a symbol");
-            }
-        },
-        True => True,
-        False => False,
+const QUOTE_NOTE: &str = "a `'` quotes the expression right after it";
+
+/// The error for a list that's still open at the end of its form.
+fn unclosed((span, quoted): (Span, bool)) -> SyntaxError {
+    if quoted {
+        return SyntaxError::new("nothing to quote", span).with_note(QUOTE_NOTE);
     }
+    SyntaxError::new("unclosed parenthesis", span).with_note(
+        "a `(` starting a line begins the next form, so everything before it has to be closed",
+    )
+}
+
+/// The errors of a program that doesn't parse, one per line.
+fn report(errors: &[SyntaxError]) -> String {
+    let lines: Vec<String> = errors.iter().map(SyntaxError::to_string).collect();
+    lines.join("\n")
 }
 
 fn remap_bool(value: bool) -> Token {
     if value {
         return True;
     }
-    return False;
+    False
+}
+
+fn error<T>(message: impl ToString) -> Result<T, Unwind> {
+    Err(Unwind::Error(message.to_string()))
+}
+
+/// Where a token is in the source: the line and column it starts at, counted from 1, and its
+/// range of character offsets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Span {
+    line: usize,
+    column: usize,
+    start: usize,
+    end: usize,
+}
+
+/// A mistake in the source of a program, found while lexing or parsing it.
+#[derive(Debug, Clone, PartialEq)]
+struct SyntaxError {
+    message: String,
+    span: Span,
+    /// A hint on how to fix it.
+    note: Option<&'static str>,
+}
+
+impl SyntaxError {
+    fn new(message: impl ToString, span: Span) -> Self {
+        Self {
+            message: message.to_string(),
+            span,
+            note: None,
+        }
+    }
+
+    fn with_note(mut self, note: &'static str) -> Self {
+        self.note = Some(note);
+        self
+    }
 }
 
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.span.line, self.span.column
+        )
+    }
+}
+
+/// Splits source code into tokens, scanning it one character at a time. Characters it can't
+/// make sense of are skipped, leaving an error behind.
 struct Lexer {
-    text: String,
+    chars: Vec<char>,
+    pos: usize,
     line: usize,
-    current_pos: usize,
-    token_matcher: TokenMatcher,
+    column: usize,
+    errors: Vec<SyntaxError>,
+    /// How many lists are open, and the depths at which quoted data (`true`) or code unquoted
+    /// in it (`false`) begins.
+    depth: usize,
+    quoted: Vec<(usize, bool)>,
+    /// Whether the last token was a prefix quoting (`'`) or unquoting (`,`) what follows.
+    quoting: Option<bool>,
+    /// Whether the next token is the operator of a list of code, where `-5` is `-` applied to 5
+    /// like in `(-5 3)` rather than a negative number.
+    operator: bool,
 }
 
 impl Lexer {
     pub fn new(text: String) -> Self {
         Self {
-            text,
-            line: 0,
-            current_pos: 0,
-            token_matcher: TokenMatcher::new(),
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+            errors: vec![],
+            depth: 0,
+            quoted: vec![],
+            quoting: None,
+            operator: false,
+        }
+    }
+
+    /// The tokens with where each of them is in the source, and the errors found between them.
+    pub fn spanned(mut self) -> (Vec<(Token, Span)>, Vec<SyntaxError>) {
+        let mut tokens = vec![];
+        loop {
+            self.consume_while(char::is_whitespace);
+            let (line, column, start) = (self.line, self.column, self.pos);
+            match self.next() {
+                Some(token) => {
+                    let span = Span {
+                        line,
+                        column,
+                        start,
+                        end: self.pos,
+                    };
+                    tokens.push((token, span));
+                }
+                None => return (tokens, self.errors),
+            }
+        }
+    }
+
+    /// The span from where a token started, at `line`, `column` and offset `start`, up to here.
+    fn span_from(&self, (line, column, start): (usize, usize, usize)) -> Span {
+        Span {
+            line,
+            column,
+            start,
+            end: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    /// The character `offset` characters after the current one.
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Consumes characters while they satisfy `accept`, returning them.
+    fn consume_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek().filter(|c| accept(*c)) {
+            taken.push(c);
+            self.advance();
+        }
+        taken
+    }
+
+    fn number(&mut self) -> Result<Token, SyntaxError> {
+        let from = (self.line, self.column, self.pos);
+        let mut literal = String::new();
+        if let Some(sign @ '+') | Some(sign @ '-') = self.peek() {
+            literal.push(sign);
+            self.advance();
+        }
+        literal += &self.consume_while(|c| c.is_ascii_digit());
+        let mut float = false;
+        let fraction = self.peek_at(1).filter(char::is_ascii_digit);
+        if self.peek() == Some('.') && fraction.is_some() {
+            self.advance();
+            literal.push('.');
+            literal += &self.consume_while(|c| c.is_ascii_digit());
+            float = true;
+        }
+        // An exponent, `1e9` or `2.5e-3`, when digits follow the `e` and its sign.
+        let sign = matches!(self.peek_at(1), Some('+') | Some('-')) as usize;
+        let exponent = self.peek_at(1 + sign).filter(char::is_ascii_digit);
+        if matches!(self.peek(), Some('e') | Some('E')) && exponent.is_some() {
+            for _ in 0..=sign {
+                literal.extend(self.advance());
+            }
+            literal += &self.consume_while(|c| c.is_ascii_digit());
+            float = true;
+        }
+        // Letters right after the digits, `1e` or `12abc`, aren't a number and a symbol.
+        if self.peek().is_some_and(|c| c.is_alphabetic() || c == '_') {
+            literal += &self.consume_while(|c| c.is_alphanumeric() || SYMBOL_CHARS.contains(c));
+            return Err(SyntaxError::new(
+                format!("malformed number `{}`", literal),
+                self.span_from(from),
+            ));
+        }
+        if float {
+            return Ok(Token::Float(literal.parse().unwrap()));
+        }
+        match (literal.parse(), literal.parse()) {
+            (Ok(number), _) => Ok(Token::Int(number)),
+            (_, Ok(number)) => Ok(Token::Long(number)),
+            _ => Err(SyntaxError::new(
+                format!("number {} is out of range", literal),
+                self.span_from(from),
+            )
+            .with_note("integers go from -9223372036854775808 to 9223372036854775807")),
+        }
+    }
+
+    /// A string literal, or when it interpolates expressions with `${...}`, the call to `str`
+    /// joining its text and their values.
+    fn string(&mut self) -> Result<Token, SyntaxError> {
+        let from = (self.line, self.column, self.pos);
+        let parts = self.literal(true)?;
+        if let [(text, false)] = &parts[..] {
+            return Ok(Token::Str(unescape(text)));
+        }
+        let mut call = vec![Symbol(Name::new("str"))];
+        for (text, expression) in parts {
+            if !expression {
+                if !text.is_empty() {
+                    call.push(Token::Str(unescape(&text)));
+                }
+                continue;
+            }
+            let message = match parse(Lexer::new(text.clone())) {
+                Ok(forms) if forms.len() == 1 => {
+                    call.extend(forms);
+                    continue;
+                }
+                Ok(forms) => format!("expected one expression in ${{}}, found {}", forms.len()),
+                Err(errors) => format!("{} in ${{{}}}", errors[0].message, text),
+            };
+            return Err(SyntaxError::new(message, self.span_from(from)));
+        }
+        Ok(List(call))
+    }
+
+    /// The parts of a string literal: text with its escapes still in and, when `interpolate`,
+    /// the source of the `${...}` expressions between, flagged `true`. `\${` is a plain `${`.
+    fn literal(&mut self, interpolate: bool) -> Result<Vec<(String, bool)>, SyntaxError> {
+        let from = (self.line, self.column, self.pos);
+        self.advance();
+        let mut parts = vec![(String::new(), false)];
+        // Inside an expression: how many of its braces are open, and whether it's in a string.
+        let (mut depth, mut quoted) = (0, false);
+        loop {
+            let c = match self.advance() {
+                Some(c) => c,
+                None => {
+                    return Err(
+                        SyntaxError::new("unterminated string", self.span_from(from))
+                            .with_note("a `\\\"` inside a string doesn't end it"),
+                    )
+                }
+            };
+            let starts_expression = interpolate && self.peek() == Some('{');
+            let escapes_expression =
+                interpolate && self.peek() == Some('$') && self.peek_at(1) == Some('{');
+            let last = parts.len() - 1;
+            let part = &mut parts[last].0;
+            match c {
+                '\\' if depth == 0 && escapes_expression => {
+                    part.push_str("${");
+                    self.advance();
+                    self.advance();
+                }
+                '\\' => {
+                    part.push(c);
+                    if let Some(c) = self.advance() {
+                        part.push(c);
+                    }
+                }
+                '"' if depth == 0 => return Ok(parts),
+                '$' if depth == 0 && starts_expression => {
+                    self.advance();
+                    depth = 1;
+                    parts.push((String::new(), true));
+                }
+                '"' => {
+                    quoted = !quoted;
+                    part.push(c);
+                }
+                '{' if depth > 0 && !quoted => {
+                    depth += 1;
+                    part.push(c);
+                }
+                '}' if depth > 0 && !quoted => {
+                    depth -= 1;
+                    if depth == 0 {
+                        parts.push((String::new(), false));
+                    } else {
+                        part.push(c);
+                    }
+                }
+                c => part.push(c),
+            }
+        }
+    }
+
+    /// A reader literal, `#tag"text"` or `#tag{text}`, as the form `(#tag "text")` which calls
+    /// the reader registered for the tag.
+    fn reader(&mut self) -> Result<Token, SyntaxError> {
+        let from = (self.line, self.column, self.pos);
+        self.advance();
+        let tag = self.consume_while(|c| c.is_alphanumeric() || c == '-');
+        if tag.is_empty() {
+            let message = "unrecognized character '#'";
+            return Err(SyntaxError::new(message, self.span_from(from)));
+        }
+        let text = match self.peek() {
+            Some('"') => unescape(&self.literal(false)?.remove(0).0),
+            Some('{') => {
+                self.advance();
+                let mut text = String::new();
+                let mut depth = 0;
+                loop {
+                    match self.advance() {
+                        Some('}') if depth == 0 => break text,
+                        Some(c) => {
+                            depth += (c == '{') as i32 - (c == '}') as i32;
+                            text.push(c);
+                        }
+                        None => {
+                            let message = format!("unterminated #{}{{", tag);
+                            return Err(SyntaxError::new(message, self.span_from(from)));
+                        }
+                    }
+                }
+            }
+            _ => {
+                let message = format!("expected a string or {{...}} after #{}", tag);
+                return Err(SyntaxError::new(message, self.span_from(from)));
+            }
+        };
+        let tag = Symbol(Name::new(&format!("#{}", tag)));
+        Ok(List(vec![tag, Str(text)]))
+    }
+
+    /// The next token, `None` at the end of the source.
+    fn scan(&mut self) -> Option<Result<Token, SyntaxError>> {
+        let scanned = self.token();
+        if let Some(Ok(token)) = &scanned {
+            self.track(token);
+        }
+        scanned
+    }
+
+    /// Keeps track of whether the token after `token` is in operator position.
+    fn track(&mut self, token: &Token) {
+        let quoting = self.quoting.take();
+        match token {
+            Token::Open => {
+                self.depth += 1;
+                if let Some(data) = quoting {
+                    self.quoted.push((self.depth, data));
+                }
+            }
+            Token::Close => {
+                if self.quoted.last().map(|(depth, _)| *depth) == Some(self.depth) {
+                    self.quoted.pop();
+                }
+                self.depth = self.depth.saturating_sub(1);
+            }
+            Token::Quote(name) => {
+                self.quoting = Some(matches!(name.as_str(), "quote" | "quasiquote"));
+            }
+            _ => {}
         }
+        let data = self.quoted.last().is_some_and(|(_, data)| *data);
+        self.operator = matches!(token, Token::Open) && !data;
+    }
+
+    fn token(&mut self) -> Option<Result<Token, SyntaxError>> {
+        self.consume_while(char::is_whitespace);
+        let c = self.peek()?;
+        let starts_number = |c: Option<char>| c.filter(char::is_ascii_digit).is_some();
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '\'' | '`' | ',' => {
+                let rest: String = self.chars[self.pos..].iter().take(2).collect();
+                let (prefix, name) = PREFIXES
+                    .iter()
+                    .find(|(prefix, _)| rest.starts_with(prefix))
+                    .unwrap();
+                for _ in 0..prefix.len() {
+                    self.advance();
+                }
+                return Some(Ok(Token::Quote(Name::new(name))));
+            }
+            '"' => return Some(self.string()),
+            '#' => return Some(self.reader()),
+            '+' | '-' if starts_number(self.peek_at(1)) && !self.operator => {
+                return Some(self.number())
+            }
+            '+' | '-' if starts_number(self.peek_at(1)) => Token::Symbol(Name::new(&c.to_string())),
+            c if c.is_ascii_digit() => return Some(self.number()),
+            c if c.is_alphabetic() || SYMBOL_CHARS.contains(c) => {
+                self.advance();
+                let rest = self.consume_while(|c| c.is_alphanumeric() || SYMBOL_CHARS.contains(c));
+                let token = match format!("{}{}", c, rest).as_str() {
+                    "true" => True,
+                    "false" => False,
+                    name => Token::Symbol(Name::new(name)),
+                };
+                return Some(Ok(token));
+            }
+            c => {
+                let from = (self.line, self.column, self.pos);
+                self.advance();
+                let message = format!("unrecognized character {:?}", c);
+                return Some(Err(SyntaxError::new(message, self.span_from(from))));
+            }
+        };
+        self.advance();
+        Some(Ok(token))
     }
 }
 
@@ -176,95 +1171,2647 @@ impl Iterator for Lexer {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let slice = &self.text[self.current_pos..];
-        if slice.is_empty() {
-            return None;
-        }
-        if self.token_matcher.open.is_match(slice) {
-            self.current_pos = self.current_pos + 1;
-            return Some(Token::Open);
-        } else if self.token_matcher.close.is_match(slice) {
-            self.current_pos = self.current_pos + 1;
-            return Some(Token::Close);
-        } else if let Some(m) = self.token_matcher.int.find(slice) {
-            self.current_pos = self.current_pos + m.end();
-            let number_str = &slice[0..m.end()];
-            let number = number_str.parse::<i32>().unwrap();
-            return Some(Token::Int(number));
-        } else if let Some(m) = self.token_matcher.symbol.find(slice) {
-            self.current_pos = self.current_pos + m.end();
-            return Some(Token::Symbol(slice[0..m.end()].to_string()));
-        } else if let Some(m) = self.token_matcher.newline.find(slice) {
-            self.current_pos = self.current_pos + m.end();
-            self.line = self.line + 1;
-            return self.next();
-        } else if let Some(m) = self.token_matcher.whitespace.find(slice) {
-            self.current_pos = self.current_pos + m.end();
-            return self.next();
-        }
-        panic!(
-            "unrecognized symbol at line {} position {}",
-            self.line, self.current_pos
-        );
-    }
-}
-
-struct TokenMatcher {
-    open: Regex,
-    close: Regex,
-    int: Regex,
-    symbol: Regex,
-    newline: Regex,
-    whitespace: Regex,
-}
-
-impl TokenMatcher {
-    pub fn new() -> Self {
-        Self {
-            open: Regex::new(r"^\(").unwrap(),
-            close: Regex::new(r"^\)").unwrap(),
-            int: Regex::new(r"^[\+\-]?[0-9]+").unwrap(),
-            symbol: Regex::new(r"^[+\-\*><=a-zA-Z][a-zA-Z0-9]*").unwrap(),
-            newline: Regex::new(r"^\n").unwrap(),
-            whitespace: Regex::new(r"^\s+").unwrap(),
+        loop {
+            match self.scan()? {
+                Ok(token) => return Some(token),
+                Err(error) => self.errors.push(error),
+            }
+        }
+    }
+}
+
+/// Replaces the escape sequences of a string literal with the characters they stand for.
+fn unescape(literal: &str) -> String {
+    let mut text = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some(c @ '"') | Some(c @ '\\') => text.push(c),
+            // Unknown escapes are kept, so regex patterns like "\d+" read naturally.
+            Some(other) => {
+                text.push('\\');
+                text.push(other);
+            }
+            None => {}
         }
     }
+    text
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::diagnostics::{Diagnostic, Style};
+    use crate::json::Json;
+    use crate::output::Streams;
+    use std::cell::Cell;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A writer to capture output, still readable after it's boxed into an interpreter.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(text: String) -> Result<Vec<Token>, String> {
+        Interpreter::new().run(text)
+    }
 
     #[test]
     fn arith() {
         let text = "(+ (- 10 5) (* 2 2))";
-        let res = run(text.to_string());
+        let res = run(text.to_string()).unwrap();
         assert!(matches!(res[0], Token::Int(9)));
 
         let text = "(+ (- 10 5) (* -2 10))";
-        let res = run(text.to_string());
+        let res = run(text.to_string()).unwrap();
         assert!(matches!(res[0], Token::Int(-15)));
     }
 
     #[test]
     fn branching() {
         let text = "(if (> 10 (* 3 3)) 1 2)";
-        let res = run(text.to_string());
+        let res = run(text.to_string()).unwrap();
         assert!(matches!(res[0], Token::Int(1)));
 
         let text = "(if (< 10 (* 3 3)) 1 2)";
-        let res = run(text.to_string());
+        let res = run(text.to_string()).unwrap();
         assert!(matches!(res[0], Token::Int(2)));
     }
 
     #[test]
-    fn iteration() {
-        let text = r#"
-            (do
-                (set i 5)
-                (while (> i 0) (do (print i) (set i (- i 1)))))
-            "#;
-        let res = run(text.to_string());
-        assert!(matches!(res[0], Token::List(_)));
-    }
-}
\ No newline at end of file
+    fn when_unless() {
+        let text = "(when (> 10 5) (set x 1) (+ x 1))";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(2)));
+
+        let text = "(when (< 10 5) 1)";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Nil));
+
+        let text = "(unless (< 10 5) 1 2 3)";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(3)));
+
+        let text = "(unless (> 10 5) 1)";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Nil));
+    }
+
+    #[test]
+    fn case() {
+        let text = "(case (* 2 1) (1 10) (2 20) (else 30))";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(20)));
+
+        let text = "(case 7 ((1 2 3) 10) ((4 5 6) 20) (else 30))";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(30)));
+
+        let text = "(do (set x b) (case x (a 1) (b 2)))";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(&res[0], Token::List(l) if l[1] == Token::Int(2)));
+
+        let text = "(case 7 (1 10))";
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Nil));
+    }
+
+    #[test]
+    fn functions() {
+        let text = r#"
+            (define (square x) (* x x))
+            (square 7)
+            ((lambda (a b) (- a b)) 10 3)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(49)));
+        assert!(matches!(res[2], Token::Int(7)));
+    }
+
+    #[test]
+    fn letrec() {
+        let text = r#"
+            (define (fact n)
+                (letrec ((go (lambda (n acc) (if (= n 0) acc (go (- n 1) (* acc n))))))
+                    (go n 1)))
+            (fact 5)
+            (letrec ((iseven (lambda (n) (if (= n 0) 1 (isodd (- n 1)))))
+                     (isodd (lambda (n) (if (= n 0) 0 (iseven (- n 1))))))
+                (iseven 10))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(120)));
+        assert!(matches!(res[2], Token::Int(1)));
+    }
+
+    #[test]
+    fn let_forms() {
+        let text = r#"
+            (set x 1)
+            (let ((x 10) (y x)) (+ x y))
+            (let loop ((i 0) (acc 0))
+                (if (> i 10) acc (loop (+ i 1) (+ acc i))))
+            x
+            (let loop ((i 0)) (if (< i 10000) (loop (+ i 1)) i))
+            (let loop ((i 0)) (cond ((= i 5) (list i)) (else (loop (+ i 1)))))
+            (let loop ((i 0)) (if (< i 3) (+ 1 (loop (+ i 1))) 0))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(11)));
+        assert!(matches!(res[2], Token::Int(55)));
+        assert!(matches!(res[3], Token::Int(1)));
+        assert_eq!(res[4], Token::Int(10000));
+        assert_eq!(res[5].to_string(), "(5)");
+        assert_eq!(res[6], Token::Int(3));
+    }
+
+    #[test]
+    fn special_form_arity() {
+        let res = run("(if false 1)".to_string());
+        assert_eq!(res.unwrap_err(), "if expects 3 arguments, got 2");
+        for text in [
+            "(quote)",
+            "(set x)",
+            "(define x)",
+            "(define ())",
+            "(let loop)",
+            "(while)",
+        ] {
+            assert!(run(text.to_string()).is_err(), "{}", text);
+        }
+        assert!(run("(return 1 2)".to_string()).is_err());
+    }
+
+    #[test]
+    fn call_cc() {
+        let text = r#"
+            (call/cc (lambda (k) (+ 1 (k 42))))
+            (+ 1 (call/cc (lambda (k) 2)))
+            (define (first-over limit)
+                (call/cc (lambda (return)
+                    (let loop ((i 0))
+                        (when (> i limit) (return i))
+                        (loop (+ i 1))))))
+            (first-over 5)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(42)));
+        assert!(matches!(res[1], Token::Int(3)));
+        assert!(matches!(res[3], Token::Int(6)));
+
+        let text = "(do (set saved (call/cc (lambda (k) k))) (saved 1))";
+        assert!(run(text.to_string()).is_err());
+    }
+
+    #[test]
+    fn early_return() {
+        let text = r#"
+            (define (index-of x lst)
+                (let ((i 0))
+                    (while (< i (length lst))
+                        (do (when (= (nth lst i) x) (return i))
+                            (inc! i))))
+                -1)
+            (index-of 3 '(1 2 3 4))
+            (index-of 9 '(1 2 3 4))
+            (define (classify n)
+                (when (< n 0) (return 'negative))
+                (unless (= (type-of n) 'int) (return))
+                'natural)
+            (list (classify -1) (classify 1.5) (classify 2))
+            (set cleaned false)
+            (define (cleanup) (defer (set cleaned true)) (return 1) 2)
+            (list (cleanup) cleaned)
+            (define (caught) (try (return 1) (catch e 2)) 3)
+            (caught)
+            (define (f) (let loop ((i 0)) (if (= i 3) (return 'early) (loop (+ i 1)))) 'after)
+            (f)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1], Token::Int(2));
+        assert_eq!(res[2], Token::Int(-1));
+        let classes = vec![Token::Symbol(Name::new("negative")), Token::Nil];
+        let classes = [classes, vec![Token::Symbol(Name::new("natural"))]].concat();
+        assert_eq!(res[4], Token::List(classes));
+        assert_eq!(res[7], Token::List(vec![Token::Int(1), Token::True]));
+        assert_eq!(res[9], Token::Int(1));
+        assert_eq!(res[11], Token::Symbol(Name::new("early")));
+        let res = run("(return 1)".to_string());
+        assert_eq!(res.unwrap_err(), "return only works inside a function");
+    }
+
+    #[test]
+    fn unwind_protect() {
+        let text = r#"
+            (do (unwind-protect (set x 1) (set y 2)) (+ x y))
+            (call/cc (lambda (k) (unwind-protect (k 1) (k 2))))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(&res[0], Token::List(l) if l[1] == Token::Int(3)));
+        assert!(matches!(res[1], Token::Int(2)));
+
+        let text = "(unwind-protect (+ 1 true) (* 2 true))";
+        let res = run(text.to_string());
+        assert_eq!(res.unwrap_err(), "you can multiply only numbers");
+    }
+
+    #[test]
+    fn multiple_values() {
+        let text = r#"
+            (define (split n) (values (- n 1) (+ n 1)))
+            (let-values ((lo hi) (split 10)) (* lo hi))
+            (let-values ((x) (values 5)) x)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(99)));
+        assert!(matches!(res[2], Token::Int(5)));
+
+        let text = "(let-values ((a b c) (values 1 2)) a)";
+        assert!(run(text.to_string()).is_err());
+    }
+
+    #[test]
+    fn number_string_conversion() {
+        let text = r#"
+            (+ (string->number "42") 1)
+            (string->number "ff" 16)
+            (string->number "4x2")
+            (number->string -42)
+            (number->string 255 2)
+            "a \"quoted\" word"
+            (number->string 9000000000)
+            (string->number "9000000000")
+            (number->string -9223372036854775808 16)
+            (list (string->number "1/2") (string->number "-3/6") (string->number "1/0"))
+            (list (string->number "2.5") (string->number "-1e3") (string->number "inf"))
+            (list (number->string 2.5) (number->string (/ -10 3) 2) (string->number "a/b" 16))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(43)));
+        assert!(matches!(res[1], Token::Int(255)));
+        assert!(matches!(res[2], Token::False));
+        assert!(matches!(&res[3], Token::Str(s) if s == "-42"));
+        assert!(matches!(&res[4], Token::Str(s) if s == "11111111"));
+        assert!(matches!(&res[5], Token::Str(s) if s == "a \"quoted\" word"));
+        assert!(matches!(&res[6], Token::Str(s) if s == "9000000000"));
+        assert!(matches!(res[7], Token::Long(9000000000)));
+        assert!(matches!(&res[8], Token::Str(s) if s == "-8000000000000000"));
+        assert_eq!(res[9].to_string(), "(1/2 -1/2 false)");
+        assert_eq!(res[10].to_string(), "(2.5 -1000.0 false)");
+        assert_eq!(res[11].to_string(), "(2.5 -1010/11 10/11)");
+        let error = run("(number->string 1.5 2)".to_string()).unwrap_err();
+        assert!(error.contains("number->string writes floats only in radix 10"));
+        let error = run(r#"(string->number "1.5" 16)"#.to_string()).unwrap_err();
+        assert!(error.contains("string->number reads floats only in radix 10"));
+    }
+
+    #[test]
+    fn symbol_string_conversion() {
+        let text = r#"
+            (symbol->string (quote abc))
+            (set name (string->symbol "counter"))
+            (= name (quote counter))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(&res[0], Token::Str(s) if s == "abc"));
+        assert!(matches!(&res[1], Token::Symbol(s) if s.as_str() == "counter"));
+        assert!(matches!(res[2], Token::True));
+
+        assert!(run("(symbol->string 1)".to_string()).is_err());
+    }
+
+    #[test]
+    fn list_library() {
+        let text = r#"
+            (length (list 1 2 3))
+            (append '(1 2) (list) (list 3))
+            (reverse '(1 2 3))
+            (nth '(a b c) 1)
+            (last '(1 2 3))
+            (last '())
+            '(1 'x)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let ints = |values: Vec<i32>| Token::List(values.into_iter().map(Token::Int).collect());
+        assert!(matches!(res[0], Token::Int(3)));
+        assert_eq!(res[1], ints(vec![1, 2, 3]));
+        assert_eq!(res[2], ints(vec![3, 2, 1]));
+        assert!(matches!(&res[3], Token::Symbol(s) if s.as_str() == "b"));
+        assert!(matches!(res[4], Token::Int(3)));
+        assert!(matches!(res[5], Token::Nil));
+        assert!(matches!(&res[6], Token::List(l) if l.len() == 2));
+
+        assert!(run("(nth '(1 2) 2)".to_string()).is_err());
+        let text = "(nth '(1 2) (* 65536 65536))";
+        assert!(run(text.to_string()).unwrap_err().contains("out of bounds"));
+    }
+
+    #[test]
+    fn association_lists() {
+        let text = r#"
+            (set ages (acons 'bob 42 (acons 'alice 37 '())))
+            (nth (assoc 'alice ages) 1)
+            (assoc 'carol ages)
+            (nth (assoc 'bob (acons 'bob 43 ages)) 1)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(37)));
+        assert!(matches!(res[2], Token::False));
+        assert!(matches!(res[3], Token::Int(43)));
+    }
+
+    #[test]
+    fn higher_order_functions() {
+        let text = r#"
+            (define (double x) (* x 2))
+            (map double '(1 2 3))
+            (filter (lambda (x) (> x 1)) '(1 2 3))
+            (reduce (lambda (acc x) (+ acc x)) '(1 2 3 4))
+            (fold (lambda (acc x) (- acc x)) 10 '(1 2 3))
+            (zip '(1 2 3) '(a b))
+            (any (lambda (x) (> x 2)) '(1 2 3))
+            (all (lambda (x) (> x 2)) '(1 2 3))
+            (for-each double '(1 2))
+            (map list '(1 2) '(3 4))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let ints = |values: Vec<i32>| Token::List(values.into_iter().map(Token::Int).collect());
+        assert_eq!(res[1], ints(vec![2, 4, 6]));
+        assert_eq!(res[2], ints(vec![2, 3]));
+        assert!(matches!(res[3], Token::Int(10)));
+        assert!(matches!(res[4], Token::Int(4)));
+        assert!(matches!(&res[5], Token::List(l) if l.len() == 2));
+        assert!(matches!(res[6], Token::True));
+        assert!(matches!(res[7], Token::False));
+        assert!(matches!(res[8], Token::Nil));
+        assert_eq!(
+            res[9],
+            Token::List(vec![ints(vec![1, 3]), ints(vec![2, 4])])
+        );
+
+        assert!(run("(reduce double '())".to_string()).is_err());
+        assert!(run("(map double)".to_string()).is_err());
+    }
+
+    #[test]
+    fn math() {
+        let text = r#"
+            (+ 1 2.5)
+            (sqrt 16)
+            (pow 2 10)
+            (pow 2 -1)
+            (floor -2.5)
+            (round 2.5)
+            (ceil 3)
+            (< (sin 0) (cos 0))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Float(f) if f == 3.5));
+        assert!(matches!(res[1], Token::Float(f) if f == 4.0));
+        assert!(matches!(res[2], Token::Int(1024)));
+        assert!(matches!(res[3], Token::Float(f) if f == 0.5));
+        assert!(matches!(res[4], Token::Float(f) if f == -3.0));
+        assert!(matches!(res[5], Token::Float(f) if f == 3.0));
+        assert!(matches!(res[6], Token::Int(3)));
+        assert!(matches!(res[7], Token::True));
+
+        assert!(run("(sqrt 'x)".to_string()).is_err());
+    }
+
+    #[test]
+    fn bitwise() {
+        let text = r#"
+            (bit-and 12 10)
+            (bit-or 12 10 1)
+            (bit-xor 12 10)
+            (bit-not 0)
+            (shl 1 4)
+            (shr -16 2)
+            (shl 1 31)
+            (bit-and 4294967295 (shl 255 24))
+            (shr (shl 1 40) 39)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(8)));
+        assert!(matches!(res[1], Token::Int(15)));
+        assert!(matches!(res[2], Token::Int(6)));
+        assert!(matches!(res[3], Token::Int(-1)));
+        assert!(matches!(res[4], Token::Int(16)));
+        assert!(matches!(res[5], Token::Int(-4)));
+        assert_eq!(res[6], Token::Long(2147483648));
+        assert_eq!(res[7], Token::Long(4278190080));
+        assert!(matches!(res[8], Token::Int(2)));
+
+        assert!(run("(shl 1 64)".to_string()).is_err());
+        assert!(run("(shl 1 63)".to_string()).is_err());
+        assert!(run("(bit-and 1 2.0)".to_string()).is_err());
+    }
+
+    #[test]
+    fn string_library() {
+        let text = r#"
+            (substring "hello" 1 3)
+            (substring "héllo" 1)
+            (split "a,b,,c" ",")
+            (split "  a b  ")
+            (join (list "a" "b" "c") ", ")
+            (trim "  hi  ")
+            (upcase "hi")
+            (downcase "HI")
+            (replace "a-b-c" "-" "+")
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let strs = |values: Vec<&str>| {
+            Token::List(
+                values
+                    .into_iter()
+                    .map(|s| Token::Str(s.to_string()))
+                    .collect(),
+            )
+        };
+        assert_eq!(res[0], Token::Str("el".to_string()));
+        assert_eq!(res[1], Token::Str("éllo".to_string()));
+        assert_eq!(res[2], strs(vec!["a", "b", "", "c"]));
+        assert_eq!(res[3], strs(vec!["a", "b"]));
+        assert_eq!(res[4], Token::Str("a, b, c".to_string()));
+        assert_eq!(res[5], Token::Str("hi".to_string()));
+        assert_eq!(res[6], Token::Str("HI".to_string()));
+        assert_eq!(res[7], Token::Str("hi".to_string()));
+        assert_eq!(res[8], Token::Str("a+b+c".to_string()));
+
+        assert!(run(r#"(substring "abc" 2 1)"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn regexes() {
+        let text = r#"
+            (re-match "(\\w+)@(\\w+)" "mail me@host now")
+            (re-match "[0-9]" "none")
+            (re-find-all "[0-9]+" "1 22 333")
+            (re-replace "(\\w+)@" "me@host" "$1 at ")
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let strs = |values: Vec<&str>| {
+            Token::List(
+                values
+                    .into_iter()
+                    .map(|s| Token::Str(s.to_string()))
+                    .collect(),
+            )
+        };
+        assert_eq!(res[0], strs(vec!["me@host", "me", "host"]));
+        assert!(matches!(res[1], Token::False));
+        assert_eq!(res[2], strs(vec!["1", "22", "333"]));
+        assert_eq!(res[3], Token::Str("me at host".to_string()));
+
+        assert!(run(r#"(re-match "(" "x")"#.to_string()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "datetime")]
+    fn dates() {
+        let text = r#"
+            (date-format 0 "%Y-%m-%d %H:%M:%S")
+            (date-format 1614865384 "%d/%m/%Y %%")
+            (date-parse "2021-03-04 13:43:04" "%Y-%m-%d %H:%M:%S")
+            (date-parse "2000-02-29" "%Y-%m-%d")
+            (> (date-now) 1600000000)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Str("1970-01-01 00:00:00".to_string()));
+        assert_eq!(res[1], Token::Str("04/03/2021 %".to_string()));
+        assert!(matches!(res[2], Token::Int(1614865384)));
+        assert!(matches!(res[3], Token::Int(951782400)));
+        assert!(matches!(res[4], Token::True));
+
+        assert!(run(r#"(date-parse "2021-13-01" "%Y-%m-%d")"#.to_string()).is_err());
+        assert!(run(r#"(date-format 0 "%Q")"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn sets() {
+        let text = r#"
+            (set a (set-of 1 2 3 3))
+            (set b (set-of 2 3 4))
+            (union a b)
+            (intersection a b)
+            (difference a b)
+            (member? 2 a)
+            (member? 4 a)
+            (length (set->list a))
+            (= (set-of 1 2) (set-of 2 1))
+            (set->list (union b a))
+            (set-of "y" 'a 2.5 '(1) 1 "x" 10)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let set = |values: Vec<i32>| Token::Set(values.into_iter().map(Token::Int).collect());
+        assert_eq!(res[0], set(vec![1, 2, 3]));
+        assert_eq!(res[2], set(vec![1, 2, 3, 4]));
+        assert_eq!(res[3], set(vec![2, 3]));
+        assert_eq!(res[4], set(vec![1]));
+        assert!(matches!(res[5], Token::True));
+        assert!(matches!(res[6], Token::False));
+        assert!(matches!(res[7], Token::Int(3)));
+        assert!(matches!(res[8], Token::True));
+        assert_eq!(res[9].to_string(), "(1 2 3 4)");
+        assert_eq!(res[10].to_string(), "#{1 2.5 10 a x y (1)}");
+        assert!(run("(set->list a b)".to_string()).is_err());
+    }
+
+    #[test]
+    fn mutable_lists() {
+        let text = r#"
+            (set stack '(1 2))
+            (push! stack 3)
+            (pop! stack)
+            (set-nth! stack 0 10)
+            stack
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(3)));
+        assert!(matches!(res[2], Token::Int(3)));
+        assert_eq!(res[4], Token::List(vec![Token::Int(10), Token::Int(2)]));
+
+        assert!(run("(do (set empty '()) (pop! empty))".to_string()).is_err());
+        assert!(run("(do (set l '(1)) (set-nth! l 1 0))".to_string()).is_err());
+        let text = "(do (set l '(1)) (set-nth! l (* 65536 65536) 0))";
+        assert!(run(text.to_string()).unwrap_err().contains("out of bounds"));
+    }
+
+    #[test]
+    fn printing() {
+        let (out, err) = (Captured::default(), Captured::default());
+        let text = r#"
+            (println "x is " 1.5 " and " (list 1 "two" 'three (= 1 1)))
+            (prin "no newline")
+            (eprint "warning: " 'low)
+            (print "debug")
+            (println)
+            "#;
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: out.clone(),
+            err: err.clone(),
+        }));
+        let res = interpreter.run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Nil));
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "x is 1.5 and (1 two three true)\nno newlineStr(\"debug\")\n\n"
+        );
+        assert_eq!(
+            String::from_utf8(err.0.lock().unwrap().clone()).unwrap(),
+            "warning: low\n"
+        );
+        // A list that can't be called is an error, without the list written out first.
+        let message = interpreter.run("((list 1) 2)".to_string()).unwrap_err();
+        assert!(message.contains("can't evaluate list, first item needs to be a symbol"));
+        assert_eq!(err.0.lock().unwrap().len(), "warning: low\n".len());
+    }
+
+    #[test]
+    fn output_to_string() {
+        let text = r#"
+            (with-output-to-string
+              (println "outer " 1)
+              (set inner (with-output-to-string (prin "inner")))
+              (prin "done"))
+            inner
+            (with-output-to-string 42)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Str("outer 1\ndone".to_string()));
+        assert_eq!(res[1], Token::Str("inner".to_string()));
+        assert_eq!(res[2], Token::Str(String::new()));
+    }
+
+    #[test]
+    fn ports() {
+        let path = env::temp_dir().join(format!("micro-lisp-ports-{}", process::id()));
+        let path = path.to_str().unwrap();
+        let text = format!(
+            r#"
+            (set out (open-output-file "{path}"))
+            (write-string out "hé")
+            (write-string out "\nsecond line\n")
+            (close-port out)
+            (set in (open-input-file "{path}"))
+            (list (read-char in) (read-char in) (read-char in))
+            (read-line in)
+            (read-line in)
+            (close-port in)
+            "#,
+            path = path
+        );
+        let res = run(text).unwrap();
+        let chars = vec!["h", "é", "\n"];
+        let chars = chars.into_iter().map(|c| Token::Str(c.to_string()));
+        assert_eq!(res[5], Token::List(chars.collect()));
+        assert_eq!(res[6], Token::Str("second line".to_string()));
+        assert!(matches!(res[7], Token::Nil));
+        let closed = format!(
+            r#"(set in (open-input-file "{}")) (close-port in) (read-char in)"#,
+            path
+        );
+        assert!(run(closed).is_err());
+        assert!(run(format!(r#"(read-line (open-output-file "{}"))"#, path)).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn timing() {
+        let err = Captured::default();
+        let text = r#"
+            (time (+ 1 2))
+            (set calls 0)
+            (bench (set calls (+ calls 1)) 5)
+            "#;
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: Captured::default(),
+            err: err.clone(),
+        }));
+        let res = interpreter.run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::Int(3)));
+        assert!(matches!(res[2], Token::Int(5)));
+        let report = String::from_utf8(err.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[0].starts_with("time: "));
+        assert!(lines[1].starts_with("bench: 5 runs in "));
+        assert!(lines[1].ends_with("/run"));
+        assert!(run("(bench 1 0)".to_string()).is_err());
+    }
+
+    #[test]
+    fn reading_stdin() {
+        let text = r#"
+            (map string->number (read-stdin-lines))
+            (read-all-stdin)
+            "#;
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(Box::new("1\n2\r\n3".as_bytes()));
+        let res = interpreter.run(text.to_string()).unwrap();
+        let ints = |values: Vec<i32>| Token::List(values.into_iter().map(Token::Int).collect());
+        assert_eq!(res[0], ints(vec![1, 2, 3]));
+        assert_eq!(res[1], Token::Str(String::new()));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(Box::new("all of it\n".as_bytes()));
+        let res = interpreter.run("(read-all-stdin)".to_string()).unwrap();
+        assert_eq!(res[0], Token::Str("all of it\n".to_string()));
+    }
+
+    #[test]
+    fn recording_and_replaying() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(Box::new("1\n2".as_bytes()));
+        interpreter.record();
+        let recorded = interpreter.run("(read-all-stdin)".to_string()).unwrap();
+        let bytes = trace::encode(interpreter.recorded());
+        let events = trace::decode(&bytes).unwrap();
+        assert_eq!(events, vec![trace::Event::Input("1\n2".to_string())]);
+        assert_eq!(trace::decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(trace::decode(b"(read-all-stdin)"), None);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(Box::new("something else".as_bytes()));
+        interpreter.replay(events);
+        let replayed = interpreter.run("(read-all-stdin)".to_string()).unwrap();
+        assert_eq!(replayed, recorded);
+        let res = interpreter.run("(read-all-stdin)".to_string());
+        assert_eq!(res.unwrap_err(), "the replayed run got no more input");
+    }
+
+    #[test]
+    fn shell_commands() {
+        let text = r#"
+            (sh "echo hello; echo oops >&2; exit 3")
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let expected = vec![
+            Token::Int(3),
+            Token::Str("hello\n".to_string()),
+            Token::Str("oops\n".to_string()),
+        ];
+        assert_eq!(res[0], Token::List(expected));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_sandboxed(true);
+        assert!(interpreter.run(text.to_string()).is_err());
+    }
+
+    #[test]
+    fn files() {
+        let dir = env::temp_dir().join(format!("micro-lisp-files-{}", process::id()));
+        let dir = dir.to_str().unwrap();
+        let text = format!(
+            r#"
+            (mkdir "{dir}/nested")
+            (sh "printf hello > {dir}/greeting.txt")
+            (file-exists? "{dir}/greeting.txt")
+            (file-size "{dir}/greeting.txt")
+            (list-dir "{dir}")
+            (delete-file "{dir}/greeting.txt")
+            (file-exists? "{dir}/greeting.txt")
+            "#,
+            dir = dir
+        );
+        let res = run(text).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(res[2], Token::True));
+        assert!(matches!(res[3], Token::Int(5)));
+        let names = vec![
+            Token::Str("greeting.txt".to_string()),
+            Token::Str("nested".to_string()),
+        ];
+        assert_eq!(res[4], Token::List(names));
+        assert!(matches!(res[6], Token::False));
+        assert!(run(format!(r#"(file-size "{}/missing")"#, dir)).is_err());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_sandboxed(true);
+        assert!(interpreter
+            .run(r#"(file-exists? ".")"#.to_string())
+            .is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        let path = env::temp_dir().join(format!("micro-lisp-bytes-{}", process::id()));
+        let path = path.to_str().unwrap();
+        let text = format!(
+            r#"
+            (write-bytes "{path}" (bytes 0 255 104 105))
+            (set data (read-bytes "{path}"))
+            (bytes-len data)
+            (byte-ref data 1)
+            (= data (bytes 0 255 104 105))
+            (println data)
+            (bytes->string (string->bytes "héllo"))
+            "#,
+            path = path
+        );
+        let res = run(text).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(res[1], Token::Bytes(vec![0, 255, 104, 105]));
+        assert!(matches!(res[2], Token::Int(4)));
+        assert!(matches!(res[3], Token::Int(255)));
+        assert!(matches!(res[4], Token::True));
+        assert_eq!(res[1].to_string(), "#u8(0 255 104 105)");
+        assert_eq!(res[6], Token::Str("héllo".to_string()));
+        assert!(run("(bytes 256)".to_string()).is_err());
+        assert!(run("(byte-ref (bytes 1) 1)".to_string()).is_err());
+        assert!(run("(bytes->string (bytes 255))".to_string()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn tcp() {
+        use std::io::Read;
+
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let echo = std::thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut buffer = [0; 5];
+            stream.read_exact(&mut buffer).unwrap();
+            stream.write_all(&buffer).unwrap();
+        });
+        let text = format!(
+            r#"
+            (set conn (tcp-connect "127.0.0.1" {}))
+            (tcp-send conn "hello")
+            (tcp-recv conn 5)
+            (tcp-recv conn 5)
+            "#,
+            port
+        );
+        let res = run(text).unwrap();
+        echo.join().unwrap();
+        assert!(matches!(res[1], Token::Int(5)));
+        assert_eq!(res[2], Token::Str("hello".to_string()));
+        assert_eq!(res[3], Token::Str(String::new()));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_sandboxed(true);
+        assert!(interpreter.run("(tcp-listen 0)".to_string()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn http() {
+        use std::io::Read;
+
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let responder = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for response in &[
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n",
+                "HTTP/1.1 201 Created\r\nX-Id: 7\r\n\r\ndone",
+            ] {
+                let (mut stream, _) = server.accept().unwrap();
+                let mut request = vec![0; 1024];
+                let read = stream.read(&mut request).unwrap();
+                requests.push(String::from_utf8_lossy(&request[..read]).into_owned());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        let text = format!(
+            r#"
+            (http-get "http://127.0.0.1:{port}/greeting")
+            (http-post "http://127.0.0.1:{port}/items" "{{}}" '(("Content-Type" "application/json")))
+            "#,
+            port = port
+        );
+        let res = run(text).unwrap();
+        let requests = responder.join().unwrap();
+        let header = |name: &str, value: &str| {
+            Token::List(vec![
+                Token::Str(name.to_string()),
+                Token::Str(value.to_string()),
+            ])
+        };
+        assert_eq!(
+            res[0],
+            Token::List(vec![
+                Token::Int(200),
+                Token::List(vec![header("Transfer-Encoding", "chunked")]),
+                Token::Str("hello".to_string()),
+            ])
+        );
+        assert_eq!(
+            res[1],
+            Token::List(vec![
+                Token::Int(201),
+                Token::List(vec![header("X-Id", "7")]),
+                Token::Str("done".to_string()),
+            ])
+        );
+        assert!(requests[0].starts_with("GET /greeting HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with("POST /items HTTP/1.1\r\n"));
+        assert!(requests[1].contains("Content-Type: application/json\r\n"));
+        assert!(requests[1].ends_with("\r\n\r\n{}"));
+
+        assert!(run(r#"(http-get "https://example.com")"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn lexing() {
+        let text = "(+ -2 3.5 -x\n  'a \"b \\\" c\" even?)";
+        let tokens: Vec<Token> = Lexer::new(text.to_string()).collect();
+        let expected = vec![
+            Token::Open,
+            Token::Symbol(Name::new("+")),
+            Token::Int(-2),
+            Token::Float(3.5),
+            Token::Symbol(Name::new("-x")),
+            Token::Quote(Name::new("quote")),
+            Token::Symbol(Name::new("a")),
+            Token::Str("b \" c".to_string()),
+            Token::Symbol(Name::new("even?")),
+            Token::Close,
+        ];
+        assert_eq!(format!("{:?}", tokens), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn lexing_error_position() {
+        let (tokens, errors) = Lexer::new("(a\n  (#))".to_string()).spanned();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["unrecognized character '#' at line 2 column 4"]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let text = r#"(define (f x)
+  (* x 2)
+(println "fine")
+(f 1))
+(f '))
+(g 99999999999999999999 "open
+"#;
+        let errors = parse(Lexer::new(text.to_string())).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "unclosed parenthesis at line 1 column 1",
+                "unexpected closing parenthesis at line 4 column 6",
+                "nothing to quote before closing parenthesis at line 5 column 5",
+                "unclosed parenthesis at line 6 column 1",
+                "number 99999999999999999999 is out of range at line 6 column 4",
+                "unterminated string at line 6 column 25",
+            ]
+        );
+        assert_eq!(errors[0].span.end, 1);
+        // A `(` starting a line only ends the form before it in a program that doesn't parse.
+        let ast = parse(Lexer::new("(define (f x)\n(+ x 1))\n(f 2)".to_string())).unwrap();
+        assert_eq!(ast.len(), 2);
+        let message = run("(+ 1 2))\n(- 1 #)".to_string()).unwrap_err();
+        assert_eq!(
+            message,
+            "unexpected closing parenthesis at line 1 column 8\nunrecognized character '#' at line 2 column 6"
+        );
+    }
+
+    #[test]
+    fn rendering_errors() {
+        let text = "(define s\n  \"open)\n";
+        let errors = parse(Lexer::new(text.to_string())).unwrap_err();
+        assert_eq!(
+            Diagnostic::from(&errors[1]).render("s.mlsp", text, false),
+            "error: unterminated string\n --> s.mlsp:2:3\n  |\n2 |   \"open)\n  |   ^^^^^^\n  = note: a `\\\"` inside a string doesn't end it\n"
+        );
+
+        let text = "1 'a '(b c)\n(f\n  x) \"s\"";
+        let forms = diagnostics::forms(text);
+        let starts: Vec<(usize, usize)> = forms.iter().map(|s| (s.line, s.column)).collect();
+        assert_eq!(starts, vec![(1, 1), (1, 3), (1, 6), (2, 1), (3, 6)]);
+        assert_eq!(
+            forms.len(),
+            parse(Lexer::new(text.to_string())).unwrap().len()
+        );
+        let error = Diagnostic::runtime("unknown symbol f".to_string(), forms[3]);
+        assert_eq!(
+            error.render("f.mlsp", text, false),
+            "error: unknown symbol f\n --> f.mlsp:2:1\n  |\n2 | (f\n  | ^^\n  = note: while evaluating this form\n"
+        );
+
+        let errors: Vec<Diagnostic> = parse(Lexer::new(") #".to_string()))
+            .unwrap_err()
+            .iter()
+            .map(Diagnostic::from)
+            .collect();
+        assert_eq!(
+            diagnostics::report(&errors, "e.mlsp", ") #", Style::Json),
+            concat!(
+                r#"{"severity":"error","message":"unexpected closing parenthesis","file":"e.mlsp","line":1,"column":1,"start":0,"end":1,"note":null}"#,
+                "\n",
+                r#"{"severity":"error","message":"unrecognized character '#'","file":"e.mlsp","line":1,"column":3,"start":2,"end":3,"note":null}"#,
+                "\n"
+            )
+        );
+        let colored = diagnostics::report(&errors[..1], "e.mlsp", ") #", Style::Colored);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: unexpected"));
+        assert!(colored.contains("\x1b[1;31m^\x1b[0m"));
+        assert_eq!(Style::parse("never", "human"), Some(Style::Plain));
+        assert_eq!(Style::parse("always", "human"), Some(Style::Colored));
+        assert_eq!(Style::parse("always", "json"), Some(Style::Json));
+        assert_eq!(Style::parse("sometimes", "human"), None);
+    }
+
+    #[test]
+    fn parse_cache() {
+        let text = r#"(define (f x) (* x 2.5)) (f -3) '(a "b\n")"#;
+        let ast = parse(Lexer::new(text.to_string())).unwrap();
+        let hash = cache::fnv1a(text.as_bytes());
+        let bytes = cache::encode(&ast, hash);
+        assert_eq!(cache::decode(&bytes, hash), Some(ast));
+        assert_eq!(cache::decode(&bytes, hash + 1), None);
+        assert_eq!(cache::decode(&bytes[..bytes.len() - 1], hash), None);
+
+        let path = env::temp_dir().join(format!("micro-lisp-cache-{}.lisp", process::id()));
+        let cached = path.with_extension("mlspc");
+        let first = cache::parsed(&path, "(+ 1 2)".to_string());
+        assert!(cached.exists());
+        assert_eq!(cache::parsed(&path, "(+ 1 2)".to_string()), first);
+        let changed = cache::parsed(&path, "(+ 1 3)".to_string()).unwrap();
+        assert_eq!(changed, parse(Lexer::new("(+ 1 3)".to_string())).unwrap());
+        fs::remove_file(&cached).unwrap();
+        assert!(cache::parsed(&path, "(+ 1".to_string()).is_err());
+        assert!(!cached.exists());
+    }
+
+    #[test]
+    fn line_coverage() {
+        let text = "(define (sign n)\n  (if (< n 0)\n      '(-1)\n      (* 1 1)))\n(sign 2)\n";
+        let ast = parse(Lexer::new(text.to_string())).unwrap();
+        let mut interpreter = Interpreter::new();
+        let (instrumented, lines) = coverage::instrument(&ast, text, &interpreter);
+        let res = interpreter.run_parsed(&instrumented).unwrap();
+        assert_eq!(res[1], Token::Int(1));
+        assert_eq!(
+            coverage::lcov("sign.mlsp", &lines, interpreter.covered()),
+            "TN:\nSF:sign.mlsp\nDA:1,1\nDA:2,2\nDA:3,0\nDA:4,1\nDA:5,1\nLF:5\nLH:4\nend_of_record\n"
+        );
+        let page = coverage::html("sign.mlsp", text, &lines, interpreter.covered());
+        assert!(page.contains("<p>4 of 5 lines run</p>"));
+        assert!(page.contains("<span class=\"missed\">   3      0        '(-1)</span>"));
+    }
+
+    #[test]
+    fn disassembly() {
+        let ast = parse(Lexer::new("(f -3) \"x\"".to_string())).unwrap();
+        let bytes = cache::encode(&ast, 0xbeef);
+        assert_eq!(
+            cache::disassemble(&bytes).unwrap(),
+            "000000  magic MLSPC3\n000007  source hash 000000000000beef\n00000f  forms 2\n\
+             000013  list 2\n000018    symbol f\n00001e    int -3\n000023  string \"x\"\n\n\
+             (f -3)\n\"x\"\n"
+        );
+        assert_eq!(
+            cache::disassemble(&bytes[..bytes.len() - 1]),
+            Err("corrupt at byte 40".to_string())
+        );
+        assert!(cache::disassemble(b"(f -3)").is_err());
+    }
+
+    #[test]
+    fn interned_symbols() {
+        assert_eq!(Name::new("counter"), Name::new("counter"));
+        assert_ne!(Name::new("counter"), Name::new("count"));
+        assert_eq!(Name::new("counter").as_str(), "counter");
+        let res = run("(symbol->string (string->symbol \"fresh\"))".to_string()).unwrap();
+        assert_eq!(res[0], Token::Str("fresh".to_string()));
+    }
+
+    #[test]
+    fn variable_storage() {
+        let text = r#"
+            (set late-variable 1)
+            (define (shadow late-variable) (+ late-variable 10))
+            (shadow 5)
+            late-variable
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[2], Token::Int(15)));
+        assert!(matches!(res[3], Token::Int(1)));
+        // Frames binding many variables index them, redefining one replaces it.
+        let names: Vec<String> = (0..40).map(|i| format!("v{}", i)).collect();
+        let mut text = format!("(define (many {}) (list v0 v39))", names.join(" "));
+        text += &format!(
+            " (many {}) (set v3 1) (set v3 2) v3",
+            (0..40).map(|i| i.to_string()).collect::<Vec<_>>().join(" ")
+        );
+        let res = run(text).unwrap();
+        assert_eq!(res[1].to_string(), "(0 39)");
+        assert_eq!(res[4], Token::Int(2));
+        assert!(run("(set 5 1)".to_string()).is_err());
+        assert!(run("(let ((\"x\" 1)) x)".to_string()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_map() {
+        let text = r#"
+            (define (square x) (* x x))
+            (set offset 100)
+            (pmap (lambda (x) (+ (square x) offset)) '(1 2 3 4 5 6 7 8 9))
+            (pmap sqrt '(4 9))
+            (pmap square '())
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let expected = (1..10).map(|x| Token::Int(x * x + 100)).collect();
+        assert_eq!(res[2], Token::List(expected));
+        assert_eq!(
+            res[3],
+            Token::List(vec![Token::Float(2.0), Token::Float(3.0)])
+        );
+        assert_eq!(res[4], Token::List(vec![]));
+        assert!(run("(pmap (lambda (x) (car x)) '(1 2))".to_string()).is_err());
+        assert!(run("(pmap (lambda (x) (+ x 1)) '(1 \"two\"))".to_string()).is_err());
+        let out = Captured::default();
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: out.clone(),
+            err: io::sink(),
+        }));
+        let text = r#"
+            (defmacro (twice e) (list '* 2 e))
+            (pmap (lambda (x) (prin x) (twice x)) '(1))
+            "#;
+        let res = interpreter.run(text.to_string()).unwrap();
+        assert_eq!(res[1].to_string(), "(2)");
+        assert_eq!(out.0.lock().unwrap().as_slice(), b"1");
+    }
+
+    #[test]
+    fn memoization() {
+        let text = r#"
+            (define fib
+              (memoize (lambda (n)
+                (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))
+            (fib 20)
+            (define count (memoize (lambda (xs) (prin "called ") (length xs))))
+            (with-output-to-string (count '(1 2)) (count '(1 2)) (count '(1 2 3)))
+            (list (count '(1 2)) (count '(1 2 3)))
+            (map (memoize sqrt) '(4 4))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[1], Token::Int(6765)));
+        assert_eq!(res[3], Token::Str("called called ".to_string()));
+        assert_eq!(res[4], Token::List(vec![Token::Int(2), Token::Int(3)]));
+        let roots = vec![Token::Float(2.0), Token::Float(2.0)];
+        assert_eq!(res[5], Token::List(roots));
+    }
+
+    #[test]
+    fn closures() {
+        let text = r#"
+            (define (make-counter)
+              (let ((count 0))
+                (lambda () (set count (+ count 1)))))
+            (set counter (make-counter))
+            (counter)
+            (counter)
+            ((make-counter))
+            (define (adder n) (lambda (x) (+ x n)))
+            ((adder 3) 4)
+            (set total 0)
+            (define (add! n) (set total (+ total n)))
+            (add! 5)
+            total
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[3], Token::Int(2)));
+        assert!(matches!(res[4], Token::Int(1)));
+        assert!(matches!(res[6], Token::Int(7)));
+        assert!(matches!(res[10], Token::Int(5)));
+    }
+
+    #[test]
+    fn prelude() {
+        let text = r#"
+            (list (min 3 1) (max 3 1) (abs -4) (abs 2.5))
+            (range 2 6)
+            (sum (range 1 5))
+            (list (first '(7 8)) (empty? '()) (empty? '(1)))
+            "#;
+        let mut interpreter = Interpreter::new();
+        interpreter.load_prelude().unwrap();
+        let res = interpreter.run(text.to_string()).unwrap();
+        let ints = |values: Vec<i32>| Token::List(values.into_iter().map(Token::Int).collect());
+        let numbers = vec![
+            Token::Int(1),
+            Token::Int(3),
+            Token::Int(4),
+            Token::Float(2.5),
+        ];
+        assert_eq!(res[0], Token::List(numbers));
+        assert_eq!(res[1], ints(vec![2, 3, 4, 5]));
+        assert!(matches!(res[2], Token::Int(10)));
+        let checks = vec![Token::Int(7), Token::True, Token::False];
+        assert_eq!(res[3], Token::List(checks));
+    }
+
+    #[test]
+    fn json() {
+        let text = r#"{"a": [1, 2.5, -3e2], "b": "x\"\né😀", "c": {"d": null, "e": true}}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.get("b").and_then(Json::as_str), Some("x\"\né😀"));
+        assert_eq!(
+            value.to_string(),
+            r#"{"a":[1,2.5,-300],"b":"x\"\né😀","c":{"d":null,"e":true}}"#
+        );
+        assert_eq!(Json::parse(&value.to_string()), Ok(value));
+        assert!(Json::parse("[1,").is_err());
+    }
+
+    #[test]
+    fn formatting() {
+        let text = r#"(define (f x) (* x 2.0)) '(a "b\n")
+            (define (long-function-name first-argument) (println "a long string to print" first-argument))"#;
+        let ast = parse(Lexer::new(text.to_string())).unwrap();
+        assert_eq!(
+            formatter::pretty(&ast),
+            r#"(define (f x) (* x 2.0))
+'(a "b\n")
+(define
+  (long-function-name first-argument)
+  (println "a long string to print" first-argument))
+"#
+        );
+
+        // Strings print back as literals reading as the same text.
+        let text = r#""cost: \${x}" "a\"b\\c\td\r \u{1F600}$" #tag"${x}\d""#;
+        let ast = parse(Lexer::new(text.to_string())).unwrap();
+        assert_eq!(ast[0], Token::Str("cost: ${x}".to_string()));
+        let printed = formatter::pretty(&ast);
+        let expected = r#""cost: \${x}"
+"a\"b\\c\td\\r \\u{1F600}$"
+#tag"${x}\\d"
+"#;
+        assert_eq!(printed, expected);
+        assert_eq!(parse(Lexer::new(printed)).unwrap(), ast);
+    }
+
+    #[test]
+    fn language_server() {
+        let messages = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.mlsp","text":"(define (square x) (* x x))\n(square (sqrt 4))"}}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.mlsp"},"position":{"line":1,"character":9}}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.mlsp"},"position":{"line":1,"character":2}}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.mlsp"},"contentChanges":[{"text":"(a\n  #)"}]}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///b.mlsp","text":"\"😀é\" (sqrt 4) #"}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///b.mlsp"},"position":{"line":0,"character":10}}}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ];
+        let input: String = messages
+            .iter()
+            .map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m))
+            .collect();
+        let mut output = vec![];
+        lsp::serve(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<Json> = output
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|reply| Json::parse(reply.split("\r\n\r\n").nth(1).unwrap()).unwrap())
+            .collect();
+        assert_eq!(replies.len(), 7);
+        let result = |i: usize| replies[i].get("result").unwrap().to_string();
+        assert!(result(0).contains(r#""hoverProvider":true"#));
+        assert_eq!(
+            replies[1].get("params").unwrap().get("diagnostics"),
+            Some(&Json::Array(vec![]))
+        );
+        assert_eq!(result(2), r#"{"contents":"`sqrt`, a builtin function"}"#);
+        assert!(result(3).contains(r#""start":{"line":0,"character":9}"#));
+        let diagnostic = replies[4].get("params").unwrap().to_string();
+        assert!(diagnostic.contains(r#""start":{"line":1,"character":2}"#));
+        assert!(diagnostic.contains("unrecognized character '#'"));
+        // Columns count UTF-16 code units, two for the emoji.
+        let diagnostic = replies[5].get("params").unwrap().to_string();
+        assert!(diagnostic.contains(r#""start":{"line":0,"character":15}"#));
+        assert_eq!(result(6), r#"{"contents":"`sqrt`, a builtin function"}"#);
+    }
+
+    #[test]
+    fn highlighting() {
+        let text = "(if (< x 1.5)\n  (println \"a<b\") x)";
+        assert_eq!(
+            highlight::highlight(text, Format::Html),
+            concat!(
+                r#"<pre class="mlsp"><span class="punctuation">(</span><span class="keyword">if</span> "#,
+                r#"<span class="punctuation">(</span><span class="keyword">&lt;</span> "#,
+                r#"<span class="symbol">x</span> <span class="number">1.5</span>"#,
+                r#"<span class="punctuation">)</span>"#,
+                "\n  ",
+                r#"<span class="punctuation">(</span><span class="builtin">println</span> "#,
+                r#"<span class="string">&quot;a&lt;b&quot;</span><span class="punctuation">)</span> "#,
+                r#"<span class="symbol">x</span><span class="punctuation">)</span></pre>"#,
+                "\n"
+            )
+        );
+        assert_eq!(
+            highlight::highlight("(sqrt 2)", Format::Ansi),
+            "\x1b[2m(\x1b[0m\x1b[36msqrt\x1b[0m \x1b[33m2\x1b[0m\x1b[2m)\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn documentation() {
+        let text = r#"
+            (define (area w h)
+              "The area of a rectangle
+               `w` wide and `h` high."
+              (* w h))
+            (define (greeting) "hello")
+            (define limit 10)
+            (println "not a definition")
+            "#;
+        let ast = parse(Lexer::new(text.to_string())).unwrap();
+        assert_eq!(
+            docs::document("shapes", &ast),
+            "# shapes\n\n## `(area w h)`\n\nThe area of a rectangle\n               `w` wide and `h` high.\n\n## `(greeting)`\n\n## `limit`\n"
+        );
+        let res = run(format!("{} (area 2 3) (greeting)", text)).unwrap();
+        assert!(matches!(res[4], Token::Int(6)));
+        assert_eq!(res[5], Token::Str("hello".to_string()));
+
+        let out = env::temp_dir().join(format!("micro-lisp-docs-{}", process::id()));
+        let pages = docs::generate(Path::new("src"), &out).unwrap();
+        assert_eq!(pages, vec![out.join("prelude.md"), out.join("index.md")]);
+        let prelude = fs::read_to_string(out.join("prelude.md")).unwrap();
+        assert!(prelude.contains("## `(min a b)`\n\nThe smaller of two numbers.\n"));
+        fs::remove_dir_all(out).unwrap();
+    }
+
+    #[test]
+    fn visiting() {
+        use crate::visit::{visit_all, Visitor};
+
+        // Counts calls to each function, the deepest nesting, and skips quoted lists.
+        #[derive(Default)]
+        struct Calls {
+            calls: Vec<String>,
+            depth: usize,
+            deepest: usize,
+            atoms: usize,
+        }
+        impl Visitor for Calls {
+            fn enter(&mut self, items: &[Token]) -> bool {
+                if let Some(Token::Symbol(name)) = items.first() {
+                    if name.as_str() == "quote" {
+                        return false;
+                    }
+                    self.calls.push(name.to_string());
+                }
+                self.depth += 1;
+                self.deepest = self.deepest.max(self.depth);
+                true
+            }
+            fn leave(&mut self, items: &[Token]) {
+                if !matches!(items.first(), Some(Token::Symbol(name)) if name.as_str() == "quote") {
+                    self.depth -= 1;
+                }
+            }
+            fn atom(&mut self, _atom: &Token) {
+                self.atoms += 1;
+            }
+        }
+
+        let ast = parse(Lexer::new(
+            "(define (f x) (+ x (* 2 x))) (println (f '(1 2 3))) \"done\"".to_string(),
+        ))
+        .unwrap();
+        let mut calls = Calls::default();
+        visit_all(&ast, &mut calls);
+        assert_eq!(calls.calls, vec!["define", "f", "+", "*", "println", "f"]);
+        assert_eq!(calls.deepest, 3);
+        assert_eq!(calls.depth, 0);
+        assert_eq!(calls.atoms, 11);
+    }
+
+    #[test]
+    fn repl() {
+        let (out, err) = (Captured::default(), Captured::default());
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: out.clone(),
+            err: err.clone(),
+        }));
+        let path = env::temp_dir().join(format!("micro-lisp-repl-{}.mlsp", process::id()));
+        fs::write(&path, "(define loaded 7)").unwrap();
+        let session = format!(
+            "(define x 2) (+ x 1)\n(define (f y)\n  (* y x))\n:type (f 3)\n:type \"s\"\n:env\n:load {}\nloaded\n:time x\n(undefined)\n:nope\n:reset\n:env\n:quit\n(+ 1 1)\n",
+            path.display()
+        );
+        repl::run(&mut interpreter, session.as_bytes(), false).unwrap();
+        fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let out: Vec<&str> = out.split("> ").collect();
+        assert_eq!(out[1], "2\n3\n");
+        assert_eq!(out[2], "  <lambda>\n");
+        assert_eq!(out[3..5], ["int\n", "string\n"]);
+        assert_eq!(out[5], "f = <lambda>\nx = 2\n");
+        assert!(out[6].starts_with("loaded ") && out[7] == "7\n");
+        assert!(out[8].starts_with("2\ntime: "));
+        assert_eq!(out[11..], ["", "", ""]);
+        let err = String::from_utf8(err.0.lock().unwrap().clone()).unwrap();
+        assert!(err.starts_with("error: "));
+        assert!(err.ends_with("error: unknown command :nope, see :help\n"));
+    }
+
+    #[test]
+    fn repl_completion() {
+        let out = Captured::default();
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: out.clone(),
+            err: Captured::default(),
+        }));
+        let session = "(define filtered 1)\n(defmacro (filtering x) x)\n:complete (map (filt\n:complete (let\n:complete ->\n";
+        repl::run(&mut interpreter, session.as_bytes(), false).unwrap();
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let out: Vec<&str> = out.split("> ").collect();
+        assert_eq!(out[3], "filter\nfiltered\nfiltering\n");
+        assert_eq!(out[4], "let\nlet-values\nletrec\n");
+        assert_eq!(out[5], "->\n->>\n");
+    }
+
+    #[test]
+    fn builtin_names() {
+        let names: Vec<&str> = builtins::names().collect();
+        let unique: HashSet<&str> = names.iter().copied().collect();
+        assert_eq!(names.len(), unique.len());
+        assert!(names.iter().all(|name| builtins::lookup(name).is_some()));
+        assert!(names.contains(&"string->number") && names.contains(&"set->list"));
+        assert!(builtins::lookup("no-such-builtin").is_none());
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn remote_repl() {
+        use std::io::Read;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"(define x 2)\n(println (* x 21))\n(car)\n(if x 1)\n:quit\n")
+                .unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        let out = Captured::default();
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: out.clone(),
+            err: out.clone(),
+        }));
+        let (stream, _) = listener.accept().unwrap();
+        repl::serve(&mut interpreter, stream, false).unwrap();
+        let received = client.join().unwrap();
+        let expected = "> 2\n> 42\nnil\n> error: unknown symbol car\n> error: if expects 3 arguments, got 2\n> ";
+        assert_eq!(received, expected);
+        // The client had the output while connected, and it's back where it was after.
+        assert!(out.0.lock().unwrap().is_empty());
+        interpreter.run("(println x)".to_string()).unwrap();
+        assert_eq!(out.0.lock().unwrap().as_slice(), b"2\n");
+    }
+
+    #[test]
+    fn stepping() {
+        let out = Captured::default();
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: out.clone(),
+            err: Captured::default(),
+        }));
+        let ast = parse(Lexer::new(
+            "(define x 1) (define y '(a)) (set x (+ x 1)) (println x)".to_string(),
+        ))
+        .unwrap();
+        let mut enter = "\n\n\n\n".as_bytes();
+        for form in &ast {
+            repl::step(&mut interpreter, form, &mut enter).unwrap();
+        }
+        assert!(enter.is_empty());
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let steps: Vec<&str> = out.split("[Enter to continue]").collect();
+        assert_eq!(
+            steps,
+            vec![
+                "(define x 1)\n=> 1\n  + x = 1\n",
+                "(define y '(a))\n=> (a)\n  + y = (a)\n",
+                "(set x (+ x 1))\n=> 2\n  ~ x = 2 (was 1)\n",
+                "(println x)\n2\n=> nil\n",
+                "",
+            ]
+        );
+        let mut enter = "\n".as_bytes();
+        let error = parse(Lexer::new("(undefined)".to_string())).unwrap();
+        assert!(repl::step(&mut interpreter, &error[0], &mut enter).is_err());
+    }
+
+    #[test]
+    fn macros() {
+        let text = r#"
+            (define n 0)
+            (defmacro (twice e) (list 'do e e))
+            (twice (set n (+ n 1)))
+            n
+            (macroexpand '(twice (twice x)))
+            (macroexpand ''(twice x))
+            (macroexpand 5)
+            (define (twice x) "not the macro")
+            (twice 1)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1], Token::Symbol(Name::new("twice")));
+        assert_eq!(res[2], Token::List(vec![Token::Int(1), Token::Int(2)]));
+        assert_eq!(res[3], Token::Int(2));
+        assert_eq!(res[4].to_string(), "(do (do x x) (do x x))");
+        assert_eq!(res[5].to_string(), "(quote (twice x))");
+        assert_eq!(res[6], Token::Int(5));
+        assert_eq!(res[8], Token::List(vec![Token::Int(1), Token::Int(1)]));
+        assert!(run("(defmacro twice 1)".to_string()).is_err());
+
+        let text = r#"
+            (define xs '(1 2))
+            `(0 ,@xs ,(+ 1 2) (xs ,xs) 'q ,@'())
+            `x
+            (defmacro (block forms) `(do ,@forms))
+            (block ((set a 1) (set b (+ a 1))))
+            (macroexpand '(block ((println 1) (println 2))))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1].to_string(), "(0 1 2 3 (xs (1 2)) (quote q))");
+        assert_eq!(res[2], Token::Symbol(Name::new("x")));
+        assert_eq!(res[4].to_string(), "(1 2)");
+        assert_eq!(formatter::source(&res[5]), "(do (println 1) (println 2))");
+        assert!(run("`(1 ,@2)".to_string()).is_err());
+        assert!(run("`,@'(1)".to_string()).is_err());
+        assert!(run("(unquote 1)".to_string()).is_err());
+        let ast = parse(Lexer::new("`(a ,b ,@c 'd)".to_string())).unwrap();
+        assert_eq!(formatter::pretty(&ast), "`(a ,b ,@c 'd)\n");
+
+        let ast = parse(Lexer::new(
+            "(defmacro (unless2 c e) (list 'if c 0 e)) (println (unless2 (< 1 2) 'x))".to_string(),
+        ))
+        .unwrap();
+        let expanded = Interpreter::new().expand_program(&ast).unwrap();
+        assert_eq!(
+            formatter::pretty(&expanded),
+            "(println (if (< 1 2) 0 'x))\n"
+        );
+    }
+
+    #[test]
+    fn readers() {
+        fn hex(text: &str) -> Result<Token, String> {
+            match i32::from_str_radix(text.trim(), 16) {
+                Ok(number) => Ok(Token::Int(number)),
+                Err(e) => Err(format!("bad hex {}: {}", text, e)),
+            }
+        }
+        let mut interpreter = Interpreter::new();
+        interpreter.add_reader("hex", hex);
+        let text = r#"
+            (+ #hex{ff} #hex"10")
+            (define-reader twice (lambda (text) (list 'list text text)))
+            #twice{a {b} c}
+            (macroexpand '(f #twice"x\"y"))
+            "#;
+        let res = interpreter.run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Int(271));
+        assert_eq!(res[1], Token::Symbol(Name::new("#twice")));
+        assert_eq!(res[2].to_string(), "(a {b} c a {b} c)");
+        assert_eq!(formatter::source(&res[3]), r#"(f (list "x\"y" "x\"y"))"#);
+        assert!(interpreter.run("#hex{zz}".to_string()).is_err());
+        assert!(run("#nope{1}".to_string()).is_err());
+
+        let ast = parse(Lexer::new(r#"(f #t{1 2} #d"2024")"#.to_string())).unwrap();
+        assert_eq!(formatter::pretty(&ast), "(f #t\"1 2\" #d\"2024\")\n");
+        let errors = parse(Lexer::new("# #x #y{open".to_string())).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unrecognized character '#'",
+                "expected a string or {...} after #x",
+                "unterminated #y{",
+            ]
+        );
+    }
+
+    #[test]
+    fn interpolation() {
+        let text = r#"
+            (define x 2)
+            (define name "world")
+            "x is ${x} and the sum is ${(+ x 3)}, ${(upcase "hi {")} ${name}!"
+            "no interpolation: $x { } \${x}"
+            "${'(1 2)}${x}"
+            (str "a" 1 '(b))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(
+            res[2],
+            Token::Str("x is 2 and the sum is 5, HI { world!".to_string())
+        );
+        assert_eq!(
+            res[3],
+            Token::Str("no interpolation: $x { } ${x}".to_string())
+        );
+        assert_eq!(res[4], Token::Str("(1 2)2".to_string()));
+        assert_eq!(res[5], Token::Str("a1(b)".to_string()));
+
+        let ast = parse(Lexer::new(r#""a${b}c""#.to_string())).unwrap();
+        assert_eq!(formatter::pretty(&ast), "(str \"a\" b \"c\")\n");
+        let errors = parse(Lexer::new(r#""${}" "${1 2}" "${(}""#.to_string())).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "expected one expression in ${}, found 0",
+                "expected one expression in ${}, found 2",
+                "unclosed parenthesis in ${(}",
+            ]
+        );
+    }
+
+    #[test]
+    fn equality() {
+        let text = r#"
+            (= (list 1 '(2 "three")) (list 1 '(2 "three")))
+            (= (list 1 2) (list 1 2 3))
+            (= "abc" "abc")
+            (= 1 1.0)
+            (= '(1 (2.0)) '(1.0 (2)))
+            (= 'a "a")
+            (= (= 1 1) (= 2 2))
+            (= (lambda (x) x) 1)
+            (= (set-of 0.0) (set-of -0.0))
+            (< "a" "b")
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let expected = [
+            true, false, true, true, true, false, true, false, true, true,
+        ];
+        for (value, expected) in res.iter().zip(expected.iter()) {
+            assert_eq!(*value, remap_bool(*expected));
+        }
+        assert!(run("(< '(1) '(2))".to_string()).is_err());
+        assert!(run("(> 1 \"a\")".to_string()).is_err());
+        assert_ne!(Token::Close, Token::Open);
+    }
+
+    #[test]
+    fn comparisons() {
+        let text = r#"
+            (define x 5)
+            (< 1 x 10)
+            (< 1 x 3)
+            (<= 5 x 5.0)
+            (>= 10 x x 1)
+            (> 3 2 2)
+            (!= x 6)
+            (!= x 5.0)
+            (= 1 1 1.0)
+            (<= "a" "b")
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let expected = [true, false, true, true, false, true, false, true, true];
+        for (value, expected) in res[1..].iter().zip(expected.iter()) {
+            assert_eq!(*value, remap_bool(*expected));
+        }
+        assert_eq!(res.len(), 10);
+        assert!(run("(< 1)".to_string()).is_err());
+        assert!(run("(<= 1 '(2))".to_string()).is_err());
+        let tokens: Vec<Token> = Lexer::new("<= >= != =>".to_string()).collect();
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn booleans() {
+        let text = r#"
+            (if true 1 2)
+            (if false 1 2)
+            (if (when false 1) 1 2)
+            (if 0 1 2)
+            (if "" 1 2)
+            (filter (lambda (x) x) (list 1 false (when false 1) true))
+            '(true false)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(
+            &res[..4],
+            &[Token::Int(1), Token::Int(2), Token::Int(2), Token::Int(1)]
+        );
+        assert_eq!(res[4], Token::Int(1));
+        assert_eq!(res[5], Token::List(vec![Token::Int(1), Token::True]));
+        assert_eq!(res[6], Token::List(vec![Token::True, Token::False]));
+    }
+
+    #[test]
+    fn ratios() {
+        let text = r#"
+            (/ 1 3)
+            (/ 6 3)
+            (+ (/ 1 3) (/ 1 6))
+            (* (/ 2 3) 3)
+            (- (/ 1 4) 1)
+            (/ (/ 1 2) 0.5)
+            (/ 4 -6)
+            (< (/ 1 3) (/ 1 2) 1)
+            (= (/ 1 2) 0.5)
+            (floor (/ 7 2))
+            (str (/ 1 3))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Ratio(1, 3));
+        assert_eq!(res[1], Token::Int(2));
+        assert_eq!(res[2], Token::Ratio(1, 2));
+        assert_eq!(res[3], Token::Int(2));
+        assert_eq!(res[4], Token::Ratio(-3, 4));
+        assert_eq!(res[5], Token::Float(1.0));
+        assert_eq!(res[6], Token::Ratio(-2, 3));
+        assert_eq!(&res[7..9], &[Token::True, Token::True]);
+        assert_eq!(res[9], Token::Int(3));
+        assert_eq!(res[10], Token::Str("1/3".to_string()));
+
+        let res = run("(/ 1 0)".to_string());
+        assert_eq!(res.unwrap_err(), "division by zero");
+    }
+
+    #[test]
+    fn overflow() {
+        let text = r#"
+            (+ 2147483647 1)
+            (* 65536 65536)
+            (- (+ 2147483647 1) 1)
+            (- -2147483648 1)
+            (pow 2 40)
+            (/ (* 65536 65536) 3)
+            (< 2147483647 (+ 2147483647 1) 5000000000.0)
+            9000000000
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Long(2147483648));
+        assert_eq!(res[1], Token::Long(4294967296));
+        assert_eq!(res[2], Token::Int(2147483647));
+        assert_eq!(res[3], Token::Long(-2147483649));
+        assert_eq!(res[4], Token::Long(1 << 40));
+        assert_eq!(res[5], Token::Ratio(4294967296, 3));
+        assert_eq!(res[6], Token::True);
+        assert_eq!(res[7], Token::Long(9000000000));
+
+        let res = run("(* 9000000000 9000000000)".to_string());
+        assert_eq!(res.unwrap_err(), "integer overflow");
+    }
+
+    #[test]
+    #[cfg(feature = "complex")]
+    fn complex_numbers() {
+        let text = r#"
+            (+ (complex 1 2) (complex 3 -1))
+            (* (complex 0 1) (complex 0 1))
+            (/ (complex 1 1) (complex 0 1))
+            (- 1 (complex 1 1))
+            (real-part (complex 1.5 2))
+            (imag-part 3)
+            (magnitude (complex 3 4))
+            (str (complex 1 -2))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Complex(4.0, 1.0));
+        assert_eq!(res[1], Token::Complex(-1.0, 0.0));
+        assert_eq!(res[2], Token::Complex(1.0, -1.0));
+        assert_eq!(res[3], Token::Complex(0.0, -1.0));
+        assert_eq!(res[4], Token::Float(1.5));
+        assert_eq!(res[5], Token::Float(0.0));
+        assert_eq!(res[6], Token::Float(5.0));
+        assert_eq!(res[7], Token::Str("1.0-2.0i".to_string()));
+        assert!(run("(< (complex 1 1) 2)".to_string()).is_err());
+    }
+
+    #[test]
+    fn symbol_names() {
+        let text = r#"
+            (define (is-empty? l) (= (length l) 0))
+            (define my_var 1)
+            (define *scale* 10)
+            (define _ 2)
+            (define count! 0)
+            (set count! (+ count! 1))
+            (list (is-empty? '()) my_var *scale* _ count!)
+            (list 'a+b 'x%y 'p&q '->list '<=>)
+            (list (- 5 3) -5 (+ 1 -2))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(
+            res[6],
+            Token::List(vec![
+                Token::True,
+                Token::Int(1),
+                Token::Int(10),
+                Token::Int(2),
+                Token::Int(1)
+            ])
+        );
+        let names: Vec<String> = match &res[7] {
+            Token::List(items) => items.iter().map(|item| item.to_string()).collect(),
+            _ => panic!("not a list"),
+        };
+        assert_eq!(names, vec!["a+b", "x%y", "p&q", "->list", "<=>"]);
+        assert_eq!(
+            res[8],
+            Token::List(vec![Token::Int(2), Token::Int(-5), Token::Int(-1)])
+        );
+    }
+
+    #[test]
+    fn unicode_symbols() {
+        let text = "(define (größe α) (* α 2))\n(define 名前 \"ünï\")\n(list (größe 21) 名前 'λx)";
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[2].to_string(), "(42 ünï λx)");
+        let (tokens, errors) = Lexer::new("(f ü) → x".to_string()).spanned();
+        assert_eq!(tokens[2].1.start, 3);
+        assert_eq!(
+            errors[0].to_string(),
+            "unrecognized character '→' at line 1 column 7"
+        );
+    }
+
+    #[test]
+    fn minus() {
+        let text = r#"
+            (- 5 3)
+            (-5 3)
+            (+2 3)
+            (list -5 +3 (- -5 -3))
+            '(-1 2)
+            `(-1 ,(-4 1))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(&res[..3], &[Token::Int(2), Token::Int(2), Token::Int(5)]);
+        let ints = |items: &[i32]| Token::List(items.iter().map(|i| Token::Int(*i)).collect());
+        assert_eq!(res[3], ints(&[-5, 3, -2]));
+        assert_eq!(res[4], ints(&[-1, 2]));
+        assert_eq!(res[5], ints(&[-1, 3]));
+    }
+
+    #[test]
+    fn scientific_notation() {
+        let text = "(list 1e9 2.5e-3 -1E+2 3e0)";
+        let res = run(text.to_string()).unwrap();
+        let floats = [1e9, 2.5e-3, -100.0, 3.0].iter().map(|f| Token::Float(*f));
+        assert_eq!(res[0], Token::List(floats.collect()));
+        for (text, number) in [
+            ("(list 1e e)", "1e"),
+            ("(+ 12abc 1)", "12abc"),
+            ("2.5e+x", "2.5e+x"),
+        ] {
+            let errors = parse(Lexer::new(text.to_string())).unwrap_err();
+            assert_eq!(errors[0].message, format!("malformed number `{}`", number));
+        }
+    }
+
+    #[test]
+    fn variadic_arithmetic() {
+        let text = r#"
+            (+ 1 2 3 4)
+            (- 10 1 2)
+            (* 2 3 4)
+            (/ 60 2 3)
+            (+)
+            (*)
+            (+ 5)
+            (+ 1 2.5 (/ 1 2))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        let ints: Vec<Token> = [10, 7, 24, 10, 0, 1, 5]
+            .iter()
+            .map(|i| Token::Int(*i))
+            .collect();
+        assert_eq!(&res[..7], &ints[..]);
+        assert_eq!(res[7], Token::Float(4.0));
+        assert_eq!(
+            run("(/ 1)".to_string()).unwrap_err(),
+            "/ expects at least 2 arguments"
+        );
+        assert_eq!(
+            run("(+ 1 2 \"x\")".to_string()).unwrap_err(),
+            "you can add only numbers"
+        );
+    }
+
+    #[test]
+    fn negation() {
+        let text = r#"
+            (define x 5)
+            (- x)
+            (- -2.5)
+            (- (/ 1 3))
+            (neg x)
+            (list (abs -4) (abs 4) (abs -2.5) (abs (/ -1 2)))
+            (- -2147483648)
+            (- 10 4)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1], Token::Int(-5));
+        assert_eq!(res[2], Token::Float(2.5));
+        assert_eq!(res[3], Token::Ratio(-1, 3));
+        assert_eq!(res[4], Token::Int(-5));
+        assert_eq!(res[5].to_string(), "(4 4 2.5 1/2)");
+        assert_eq!(res[6], Token::Long(2147483648));
+        assert_eq!(res[7], Token::Int(6));
+        assert!(run("(abs \"x\")".to_string()).is_err());
+    }
+
+    #[test]
+    fn increments() {
+        let text = r#"
+            (define i 0)
+            (list (inc 5) (dec 5) (inc 1.5) i)
+            (while (< i 3) (inc! i))
+            (dec! i)
+            i
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1].to_string(), "(6 4 2.5 0)");
+        assert_eq!(res[3], Token::Int(2));
+        assert_eq!(res[4], Token::Int(2));
+        assert!(run("(inc! nope)".to_string()).is_err());
+        assert!(run("(define s \"a\") (inc! s)".to_string()).is_err());
+    }
+
+    #[test]
+    fn update_variables() {
+        let text = r#"
+            (define counter 1)
+            (define step 5)
+            (update! counter (lambda (c) (+ c step)))
+            (swap! counter * 2)
+            (define names '("b"))
+            (update! names append '("c"))
+            (list counter names)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[2], Token::Int(6));
+        assert_eq!(res[3], Token::Int(12));
+        assert_eq!(res[6].to_string(), "(12 (b c))");
+        assert!(run("(update! nope inc)".to_string()).is_err());
+        let res = run("(fold + 0 '(1 2 3))\n(filter (lambda (x) (< 1 x)) '(1 2 3))".to_string());
+        assert_eq!(res.unwrap()[0], Token::Int(6));
+    }
+
+    #[test]
+    fn destructuring() {
+        let text = r#"
+            (let (((a b) '(1 2)) (c 3)) (list a b c))
+            (define (dot (x1 y1) (x2 y2)) (+ (* x1 x2) (* y1 y2)))
+            (dot '(1 2) '(3 4))
+            (let (((head & tail) '(1 2 3))) (list head tail))
+            (let (((a (b c)) '(1 (2 3)))) (+ a b c))
+            (map (lambda ((k v)) v) '((a 1) (b 2)))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0].to_string(), "(1 2 3)");
+        assert_eq!(res[2], Token::Int(11));
+        assert_eq!(res[3].to_string(), "(1 (2 3))");
+        assert_eq!(res[4], Token::Int(6));
+        assert_eq!(res[5].to_string(), "(1 2)");
+
+        let res = run("(let (((a b) '(1 2 3))) a)".to_string());
+        assert_eq!(res.unwrap_err(), "can't destructure (1 2 3) into (a b)");
+        assert!(run("(let (((a b) 5)) a)".to_string()).is_err());
+    }
+
+    #[test]
+    fn threading() {
+        let text = r#"
+            (-> 5 (- 1) (* 2) inc)
+            (->> '(1 2 3 4) (filter (lambda (x) (< 1 x))) (map inc) (fold + 0))
+            (macroexpand '(-> x (f a) g))
+            (macroexpand '(->> x (f a) g))
+            (-> 7)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Int(9));
+        assert_eq!(res[1], Token::Int(12));
+        assert_eq!(res[2].to_string(), "(g (f x a))");
+        assert_eq!(res[3].to_string(), "(g (f a x))");
+        assert_eq!(res[4], Token::Int(7));
+    }
+
+    #[test]
+    fn and_or() {
+        let text = r#"
+            (define name (when false "x"))
+            (or name "default")
+            (and 1 2 3)
+            (and 1 false (print "not evaluated"))
+            (or false (when false 1))
+            (list (and) (or))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1], Token::Str("default".to_string()));
+        assert_eq!(res[2], Token::Int(3));
+        assert_eq!(res[3], Token::False);
+        assert_eq!(res[4], Token::Nil);
+        assert_eq!(res[5], Token::List(vec![Token::True, Token::False]));
+    }
+
+    #[test]
+    fn cond() {
+        let text = r#"
+            (define (sign x) (cond ((< x 0) 'negative) ((= x 0) 'zero) (else 'positive)))
+            (list (sign -3) (sign 0) (sign 8))
+            (cond (false 1) ((< 2 1) 2))
+            (cond ((+ 1 1)) (else 3))
+            (cond (true (set a 1) (+ a 1)))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1].to_string(), "(negative zero positive)");
+        assert_eq!(res[2], Token::Nil);
+        assert_eq!(res[3], Token::Int(2));
+        assert_eq!(res[4], Token::Int(2));
+        assert!(run("(cond 1)".to_string()).is_err());
+    }
+
+    #[test]
+    fn contracts() {
+        let text = r#"
+            (assert-type 5 'int)
+            (assert-type (/ 1 2) 'number "n must be a number")
+            (list (type-of 1) (type-of 1.5) (type-of "a") (type-of '(1)) (type-of inc))
+            (define (area w:int h:number) (* w h))
+            (area 2 1.5)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[0], Token::Int(5));
+        assert_eq!(res[1], Token::Ratio(1, 2));
+        assert_eq!(res[2].to_string(), "(int float string list symbol)");
+        assert_eq!(res[4], Token::Float(3.0));
+
+        let res = run(r#"(assert-type "5" 'int "n must be an integer")"#.to_string());
+        assert_eq!(
+            res.unwrap_err(),
+            "n must be an integer: expected int, got string"
+        );
+        let res = run("(define (area w:int h) (* w h))\n(area 1.5 2)".to_string());
+        assert_eq!(
+            res.unwrap_err(),
+            "argument w of area: expected int, got float"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let text = r#"
+            (define (lookup key)
+              (throw (error 'not-found "missing key" key)))
+            (try (lookup 'color)
+              (catch e (list (error-type e) (error-message e) (error-data e))))
+            (try (+ 1 "a") (catch e (list (error-type e) (error-message e))))
+            (try 1 2 (catch e 3))
+            (try (throw 42) (catch e (+ e 1)))
+            (list (error? (error 'oops "")) (error? 1))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1].to_string(), "(not-found missing key color)");
+        assert_eq!(res[2].to_string(), "(error you can add only numbers)");
+        assert_eq!(res[3], Token::Int(2));
+        assert_eq!(res[4], Token::Int(43));
+        assert_eq!(res[5], Token::List(vec![Token::True, Token::False]));
+
+        let res = run(r#"(throw (error 'bad "it broke"))"#.to_string());
+        assert_eq!(res.unwrap_err(), "uncaught <error bad: it broke>");
+        assert!(run("(try 1)".to_string()).is_err());
+    }
+
+    #[test]
+    fn defer() {
+        let text = r#"
+            (define log '())
+            (define (work fail)
+              (defer (push! log 'first-deferred))
+              (defer (push! log 'second-deferred))
+              (push! log 'working)
+              (when fail (throw 'failed))
+              'done)
+            (work false)
+            (try (work true) (catch e e))
+            log
+            (do (defer (push! log 'form-done)) (push! log 'form))
+            log
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[2].to_string(), "done");
+        assert_eq!(res[3].to_string(), "failed");
+        assert_eq!(
+            res[4].to_string(),
+            "(working second-deferred first-deferred working second-deferred first-deferred)"
+        );
+        assert!(res[6].to_string().ends_with("form form-done)"));
+    }
+
+    #[test]
+    fn identity_and_hashing() {
+        let text = r#"
+            (define (f x) x)
+            (define g f)
+            (define e (error 'oops "bad"))
+            (list (identical? f g) (identical? e e) (identical? e (error 'oops "bad")) (identical? 'a 'a))
+            (list (identical? '(1) '(1)) (equal? '(1 "a") (list 1 "a")) (equal? 1 1.0) (= 1 1.0))
+            (= (hash '(1 "a" sym)) (hash (list 1 "a" 'sym)))
+            (= (hash (set-of 1 2 3)) (hash (set-of 3 2 1)))
+            (= (hash 'a) (hash 'b))
+            (hash "stable")
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[3].to_string(), "(true true false true)");
+        assert_eq!(res[4].to_string(), "(false true false true)");
+        assert_eq!(res[5], True);
+        assert_eq!(res[6], True);
+        assert_eq!(res[7], False);
+        assert_eq!(res[8], run(text.to_string()).unwrap()[8]);
+        // The same in every build too, whatever features it has.
+        assert_eq!(res[8], Token::Long(8082867100178106992));
+        let res = run("(hash 42)".to_string()).unwrap();
+        assert_eq!(res[0], Token::Long(3339330328152615322));
+    }
+
+    #[test]
+    fn deep_copies() {
+        let text = r#"
+            (define data (list (list 1 2) "text"))
+            (define copy (deep-copy data))
+            (set-nth! data 0 '(changed))
+            (list data copy (equal? copy (list (list 1 2) "text")))
+            (define (f) 1)
+            (identical? f (deep-copy f))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[3].to_string(), "(((changed) text) ((1 2) text) true)");
+        assert_eq!(res[5], True);
+    }
+
+    #[test]
+    fn frozen_values() {
+        let text = r#"
+            (define primes (freeze '(2 3 5)))
+            (list (frozen? primes) (frozen? '(2 3 5)) (length primes) (nth primes 1) (= primes '(2 3 5)))
+            (map (lambda (p) (* p p)) primes)
+            (let (((first & rest) primes)) rest)
+            (define copy (deep-copy primes))
+            (push! copy 7)
+            copy
+            (list (type-of primes) (equal? primes '(2 3 5)) (= (hash primes) (hash '(2 3 5))))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[1].to_string(), "(true false 3 3 true)");
+        assert_eq!(res[2].to_string(), "(4 9 25)");
+        assert_eq!(res[3].to_string(), "(3 5)");
+        assert_eq!(res[6].to_string(), "(2 3 5 7)");
+        assert_eq!(res[7].to_string(), "(list true true)");
+        for change in ["(push! primes 7)", "(pop! primes)", "(set-nth! primes 0 1)"] {
+            let text = format!("(define primes (freeze '(2 3 5))) {}", change);
+            assert!(run(text).unwrap_err().contains("frozen"));
+        }
+        let text = "(define (f lst) (push! lst 1)) (f (freeze '()))";
+        assert!(run(text.to_string()).unwrap_err().contains("frozen"));
+    }
+
+    #[test]
+    fn persistent_maps_and_vectors() {
+        let text = r#"
+            (define ages (map-of 'ann 31 'bob 42))
+            (define older (put ages 'ann 32))
+            (list (get ages 'ann) (get older 'ann) (get older 'cid) (get older 'cid 0) (length older))
+            (list (assoc 'bob older) (assoc 'cid older) (length (remove older 'bob)) (equal? ages (map-of 'bob 42 'ann 31)))
+            (define v (vector 1 2 3))
+            (define w (append v '(4 5)))
+            (list v w (nth w 3) (last w) (get (put v 0 'x) 0) (put v 3 4))
+            (map (lambda (x) (* x 10)) v)
+            (type-of v)
+            (define big (map-of))
+            (define i 0)
+            (while (< i 1000) (do (set big (put big i (* i i))) (inc! i)))
+            (define half big)
+            (while (> i 500) (do (dec! i) (set half (remove half i))))
+            (list (length big) (get big 999) (length half) (get half 499) (get half 500))
+            (define from-alist (alist->map (acons 'ann 30 '((ann 31) (bob 42)))))
+            (list (equal? from-alist (map-of 'ann 30 'bob 42)) (length (alist->map '())))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[2].to_string(), "(31 32 nil 0 2)");
+        assert_eq!(res[3].to_string(), "((bob 42) false 1 true)");
+        assert_eq!(res[6].to_string(), "([1 2 3] [1 2 3 4 5] 4 5 x [1 2 3 4])");
+        assert_eq!(res[7].to_string(), "(10 20 30)");
+        assert_eq!(res[8].to_string(), "vector");
+        assert_eq!(res[14].to_string(), "(1000 998001 500 249001 nil)");
+        assert_eq!(res[16].to_string(), "(true 0)");
+        let text = "(alist->map '((a 1) b))";
+        assert!(run(text.to_string())
+            .unwrap_err()
+            .contains("(key value) pairs"));
+        assert!(run("(put (vector 1) 5 2)".to_string()).is_err());
+    }
+
+    #[test]
+    fn native_values() {
+        struct Entity {
+            name: String,
+            health: Cell<i32>,
+        }
+        fn name(entity: &Entity, _args: Vec<Token>) -> Result<Token, String> {
+            Ok(Token::Str(entity.name.clone()))
+        }
+        fn hurt(entity: &Entity, args: Vec<Token>) -> Result<Token, String> {
+            match args[..] {
+                [Token::Int(damage)] => {
+                    entity.health.set(entity.health.get() - damage);
+                    Ok(Token::Int(entity.health.get()))
+                }
+                _ => Err("hurt expects the damage".to_string()),
+            }
+        }
+        let mut interpreter = Interpreter::new();
+        interpreter.add_method("name", name);
+        interpreter.add_method("hurt", hurt);
+        let entity = Token::native(Entity {
+            name: "orc".to_string(),
+            health: Cell::new(10),
+        });
+        interpreter.define_global("enemy", entity.clone());
+        let text = r#"
+            (define (attack target) (hurt target 3))
+            (attack enemy)
+            (list (name enemy) (type-of enemy) (identical? enemy enemy) (map name (list enemy)))
+            enemy
+            "#;
+        let res = interpreter.run(text.to_string()).unwrap();
+        assert_eq!(res[1], Token::Int(7));
+        assert_eq!(res[2].to_string(), "(orc native true (orc))");
+        assert_eq!(res[3].downcast::<Entity>().unwrap().health.get(), 7);
+        assert_eq!(res[3], entity);
+        assert!(interpreter.run("(name 1)".to_string()).is_err());
+        assert!(interpreter.run("(hurt enemy)".to_string()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn threads_and_channels() {
+        let text = r#"
+            (define results (chan))
+            (define data '(1 2 3))
+            (define (worker n) (lambda () (send results (* n (fold + 0 data)))))
+            (spawn (worker 1))
+            (spawn (worker 10))
+            (+ (recv results) (recv results))
+            (define back (chan))
+            (spawn (lambda () (send back (list 'got (recv results)))))
+            (send results "ping")
+            (recv back)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[5], Token::Int(66));
+        assert_eq!(res[9].to_string(), "(got ping)");
+        // Errors of spawned threads go to the program's stderr, whenever the thread fails.
+        let err = Captured::default();
+        let mut interpreter = Interpreter::with_output(Box::new(Streams {
+            out: io::sink(),
+            err: err.clone(),
+        }));
+        let res = interpreter.run("(spawn (lambda () (car '())))".to_string());
+        assert_eq!(res.unwrap(), vec![Token::Nil]);
+        for _ in 0..500 {
+            if !err.0.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            String::from_utf8(err.0.lock().unwrap().clone()).unwrap(),
+            "error in spawned thread: unknown symbol car\n"
+        );
+        assert!(run("(send (chan) (lambda () 1) 2)".to_string()).is_err());
+        assert!(run("(recv 1)".to_string()).is_err());
+        let closures = r#"
+            (define back (chan))
+            (define (make n) (lambda () n))
+            (spawn (lambda () (send back (make 42))))
+            ((recv back))
+            (define (countdown) (define (step i) (if (= i 0) 'done (step (- i 1)))) step)
+            (send back (countdown))
+            ((recv back) 3)
+            "#;
+        let res = run(closures.to_string()).unwrap();
+        assert_eq!(res[3], Token::Int(42));
+        assert_eq!(res[6].to_string(), "done");
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn atoms() {
+        let text = r#"
+            (define counter (atom 0))
+            (define done (chan))
+            (define (bump) (define i 0) (while (< i 100) (do (swap! counter + 1) (inc! i))) (send done 'ok))
+            (spawn bump)
+            (spawn bump)
+            (spawn bump)
+            (list (recv done) (recv done) (recv done))
+            (deref counter)
+            (reset! counter '(a b))
+            (swap! counter reverse)
+            (list (deref counter) (type-of counter) (identical? counter counter))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[7], Token::Int(300));
+        assert_eq!(res[9].to_string(), "(b a)");
+        assert_eq!(res[10].to_string(), "((b a) atom true)");
+        assert!(run("(swap! (list 1) reverse)".to_string()).is_err());
+    }
+
+    #[test]
+    fn generators() {
+        let text = r#"
+            (define (counting from)
+                (generator (lambda ()
+                    (let ((n from))
+                        (while true (do (yield n) (inc! n)))))))
+            (define g (counting 5))
+            (list (next g) (next g) (next g))
+            (define pair (generator (lambda () (yield 'a) (yield 'b))))
+            (list (next pair) (next pair) (next pair) (next pair) (type-of pair))
+            (define failing (generator (lambda () (yield 1) (car 2))))
+            (next failing)
+            (try (next failing) (catch e (error-message e)))
+            (next failing)
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert_eq!(res[2].to_string(), "(5 6 7)");
+        assert_eq!(res[4].to_string(), "(a b nil nil generator)");
+        assert_eq!(
+            res[7],
+            Token::Str("error in generator: unknown symbol car".to_string())
+        );
+        assert_eq!(res[8], Token::Nil);
+        let res = run("(yield 1)".to_string());
+        assert_eq!(res.unwrap_err(), "yield only works inside a generator");
+        let shared = r#"
+            (define total 0)
+            (define (summing step)
+                (generator (lambda () (while true (do (set total (+ total step)) (yield total))))))
+            (define s (summing 10))
+            (list (next s) (next s) total)
+            (define n 1)
+            (define peek (generator (lambda () (while true (yield n)))))
+            (next peek)
+            (set n 5)
+            (next peek)
+            (define secrets (generator (lambda () (let ((secret 'hidden)) (yield (lambda () secret))))))
+            ((next secrets))
+            (with-output-to-string (next (generator (lambda () (prin "hi") (yield 1)))))
+            "#;
+        let res = run(shared.to_string()).unwrap();
+        assert_eq!(res[3].to_string(), "(10 20 20)");
+        assert_eq!(res[6], Token::Int(1));
+        assert_eq!(res[8], Token::Int(5));
+        assert_eq!(res[10].to_string(), "hidden");
+        assert_eq!(res[11], Token::Str("hi".to_string()));
+
+        // Dropping a generator waiting at a `yield` runs its cleanup code once nothing else
+        // is running, rather than in the middle of the `set` dropping it.
+        let dropped = r#"
+            (define x 5)
+            (define cleaned '())
+            (define g nil)
+            (next (set g (generator (lambda () (unwind-protect (yield 1) (push! cleaned x))))))
+            (set g nil)
+            cleaned
+            (define (once) (let ((g (generator (lambda () (unwind-protect (yield 1) (push! cleaned 'inner)))))) (next g)))
+            (list (once) (next (generator (lambda () (yield 2)))) cleaned)
+            "#;
+        let res = run(dropped.to_string()).unwrap();
+        assert_eq!(res[5].to_string(), "(5)");
+        assert_eq!(res[7].to_string(), "(1 2 (5 inner))");
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut interpreter = Interpreter::new();
+        let handle = interpreter.interrupt_handle();
+        let stopper = handle.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            stopper.interrupt();
+        });
+        let text = r#"
+            (define (spin) (while true 1))
+            (try (spin) (catch e (list (error-type e) (error-message e))))
+            "#;
+        let res = interpreter.run(text.to_string()).unwrap();
+        thread.join().unwrap();
+        assert_eq!(res[1].to_string(), "(interrupted program interrupted)");
+        handle.interrupt();
+        let stopped = interpreter.run("(define (loop n) (loop (+ n 1))) (loop 0)".to_string());
+        assert!(stopped.unwrap_err().contains("program interrupted"));
+        assert_eq!(
+            interpreter.run("(+ 1 2)".to_string()).unwrap()[0],
+            Token::Int(3)
+        );
+    }
+
+    #[test]
+    fn images() {
+        let mut interpreter = Interpreter::new();
+        let text = r#"
+            (define scores (map-of 'ann (list 1 2) 'bob (/ 1 3)))
+            (define names (freeze (set-of "a" "b")))
+            (define nothing (when false 1))
+            (define (greet name) (str "hi " name))
+            (define fib (memoize (lambda (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))
+            (defmacro (twice e) (list 'do e e))
+            (define-reader up (lambda (text) (upcase text)))
+            (define oops (error 'bad "broken" '(1 2)))
+            (define input (open-input-file "Cargo.toml"))
+            "#;
+        interpreter.run(text.to_string()).unwrap();
+        let (image, skipped) = interpreter.save_state();
+        assert_eq!(skipped, vec![Name::new("input")]);
+        let mut restored = Interpreter::new();
+        restored.load_state(&image).unwrap();
+        let check = r#"
+            (list (equal? scores (map-of 'bob (/ 1 3) 'ann '(1 2))) (frozen? names) nothing)
+            (list (greet "you") (fib 20) (twice 1) #up"x")
+            (list (error-type oops) (error-message oops) (error-data oops))
+            "#;
+        let res = restored.run(check.to_string()).unwrap();
+        assert_eq!(res[0].to_string(), "(true true nil)");
+        assert_eq!(res[1].to_string(), "(hi you 6765 (1 1) X)");
+        assert_eq!(res[2].to_string(), "(bad broken (1 2))");
+
+        let path = std::env::temp_dir().join("micro-lisp-image-test.mlsp");
+        let save = format!("(save-image {:?})", path.to_str().unwrap());
+        assert_eq!(interpreter.run(save).unwrap()[0].to_string(), "(input)");
+        assert_eq!(fs::read_to_string(&path).unwrap(), image);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn javascript() {
+        let compile = |text: &str| {
+            let prelude = parse(Lexer::new(interpreter::PRELUDE.to_string())).unwrap();
+            js::compile(&parse(Lexer::new(text.to_string())).unwrap(), &prelude)
+        };
+        let code = compile(
+            "(define (fact n) \"The factorial.\" (if (<= n 1) 1 (* n (fact (- n 1)))))\n\
+             (define total 0)\n\
+             (let ((i 0)) (while (< i 3) (do (inc! total) (set i (+ i 1)))))\n\
+             (println (map (lambda (x) (+ x 1)) (range 0 3)) (fold + 0 '(a)) (is-empty? '()))",
+        )
+        .unwrap();
+        let (runtime, program) = code.split_at(code.find("\n\n").unwrap());
+        assert!(runtime.starts_with("function $show(x) {"));
+        for name in ["const map", "const fold", "const println", "$push"] {
+            assert!(runtime.contains(name), "{} missing from {}", name, runtime);
+        }
+        assert!(!runtime.contains("const filter"));
+        assert_eq!(
+            program.trim(),
+            "// The numbers from `from` up to, but not including, `to`.
+function range(from, to) {
+  let items = [];
+  let i = from;
+  while (i < to) {
+    $push(items, i);
+    i = i + 1;
+  }
+  return items;
+}
+// The factorial.
+function fact(n) {
+  if (n <= 1) {
+    return 1;
+  } else {
+    return n * fact(n - 1);
+  }
+}
+let total = 0;
+{
+  let i = 0;
+  while (i < 3) {
+    total += 1;
+    i = i + 1;
+  }
+}
+println(map((x) => (x + 1), range(0, 3)), fold($add, 0, [Symbol.for(\"a\")]), is_empty_p([]));"
+        );
+        // Variables first set in a loop are declared before it, and truthiness is the
+        // language's.
+        let code = compile("(define (f lst) (while lst (set x (pop! lst))) (and x 0))").unwrap();
+        assert!(code.contains("  let x;\n  while ($truthy(lst)) {\n    x = $pop(lst);\n  }\n"));
+        assert!(code.contains("return $and(() => x, () => 0);"));
+        // A named let calling itself in tail position loops instead of recursing, unless it
+        // makes closures over its parameters.
+        let code =
+            compile("(let loop ((i 0) (s 0)) (if (< i 100000) (loop (+ i 1) (+ s i)) s))").unwrap();
+        assert!(code.contains(
+            "(function loop(i, s) {\n  while (true) {\n    if (i < 100000) {\n      \
+             [i, s] = [i + 1, s + i];\n      continue;\n"
+        ));
+        let code = compile("(let loop ((i 0)) (if (< i 3) (loop (+ i 1)) (lambda () i)))").unwrap();
+        assert!(code.contains("return loop(i + 1);"));
+        // `case` on integers or symbols is a `switch`, on anything else a chain of `if`s.
+        let code = compile("(case x ((1 2) 'low) (else 'high) (3 'never))").unwrap();
+        assert!(code.contains(
+            "switch ($v1) {\n  case 1:\n  case 2:\n    Symbol.for(\"low\");\n    break;\n  \
+             default:\n    Symbol.for(\"high\");\n    break;\n}\n"
+        ));
+        assert!(compile("(case x (\"a\" 1))")
+            .unwrap()
+            .contains("if ($equal($v1, \"a\")) {"));
+        assert!(compile("(prin 1)")
+            .unwrap()
+            .contains("process.stdout.write("));
+        assert_eq!(
+            compile("(call/cc (lambda (k) 1))"),
+            Err("call/cc can't be compiled to JavaScript".to_string())
+        );
+        assert_eq!(
+            compile("(sh \"ls\")"),
+            Err("sh has no JavaScript version".to_string())
+        );
+    }
+
+    #[test]
+    fn dot_graphs() {
+        let ast = parse(Lexer::new("(print 'x) \"say \\\"hi\\\"\"".to_string())).unwrap();
+        assert_eq!(
+            dot::graph(&ast),
+            r#"digraph program {
+  ordering=out;
+  node [shape=box, fontname="monospace"];
+  n0 [label="program", shape=ellipse];
+  n1 [label="()", shape=circle];
+  n0 -> n1;
+  n2 [label="print"];
+  n1 -> n2;
+  n3 [label="()", shape=circle];
+  n1 -> n3;
+  n4 [label="quote"];
+  n3 -> n4;
+  n5 [label="x"];
+  n3 -> n5;
+  n6 [label="\"say \\\"hi\\\"\""];
+  n0 -> n6;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn control_flow_graphs() {
+        let text = "(define (count n) (while (> n 0) (dec! n)) n) (when ok (print 1))";
+        let ast = parse(Lexer::new(text.to_string())).unwrap();
+        assert_eq!(
+            cfg::graph(&ast),
+            r#"digraph cfg {
+  node [shape=box, fontname="monospace"];
+  subgraph cluster_f1 {
+    label="count";
+    f1_b0 [label="b0\l"];
+    f1_b1 [label="b1\lif (> n 0)\l"];
+    f1_b2 [label="b2\l(dec! n)\l"];
+    f1_b3 [label="b3\ln\l"];
+    f1_b0 -> f1_b1;
+    f1_b1 -> f1_b2 [label="true"];
+    f1_b1 -> f1_b3 [label="false"];
+    f1_b2 -> f1_b1 [label="loop"];
+  }
+  subgraph cluster_top {
+    label="program";
+    top_b0 [label="b0\lif ok\l"];
+    top_b1 [label="b1\l(print 1)\l"];
+    top_b2 [label="b2\l"];
+    top_b0 -> top_b1 [label="true"];
+    top_b0 -> top_b2 [label="false"];
+    top_b1 -> top_b2;
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn webassembly() {
+        let compile = |text: &str| wasm::compile(&parse(Lexer::new(text.to_string())).unwrap());
+        let (module, skipped) =
+            compile("(define (double x) (* x 2)) (println (double 2))").unwrap();
+        let mut expected = b"\0asm\x01\0\0\0".to_vec();
+        expected.extend([1, 6, 1, 0x60, 1, 0x7c, 1, 0x7c]);
+        expected.extend([3, 2, 1, 0]);
+        expected.extend([7, 10, 1, 6]);
+        expected.extend(b"double\0\0");
+        expected.extend([10, 16, 1, 14, 0, 0x20, 0, 0x44]);
+        expected.extend(2.0f64.to_le_bytes());
+        expected.extend([0xa2, 0x0b]);
+        assert_eq!(module, expected);
+        assert!(skipped.is_empty());
+        // Functions on anything but numbers are left out, with the ones calling them.
+        let (_, skipped) = compile(
+            "(define (fact n) (if (<= n 1) 1 (* n (fact (- n 1)))))
+             (define (greet name) (str \"hi \" name))
+             (define (loud name) (upcase (greet name)))
+             (define (positive? x) (> x 0))",
+        )
+        .unwrap();
+        let skipped: Vec<String> = skipped
+            .iter()
+            .map(|(name, reason)| format!("{}: {}", name, reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                "greet: str can't be compiled to WebAssembly",
+                "loud: upcase can't be compiled to WebAssembly",
+                "positive?: the test (> x 0) isn't a number",
+            ]
+        );
+        assert_eq!(
+            compile("(println 1)"),
+            Err("no function can be compiled to WebAssembly".to_string())
+        );
+    }
+
+    #[test]
+    fn iteration() {
+        let text = r#"
+            (do
+                (set i 5)
+                (while (> i 0) (do (print i) (set i (- i 1)))))
+            "#;
+        let res = run(text.to_string()).unwrap();
+        assert!(matches!(res[0], Token::List(_)));
+    }
+}
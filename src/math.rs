@@ -0,0 +1,185 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::{error, Eval, Token, Unwind};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+builtins! {
+    /// Finds the math builtin called `name`.
+    "sqrt" => sqrt,
+    "sin" => sin,
+    "cos" => cos,
+    "floor" => floor,
+    "ceil" => ceil,
+    "round" => round,
+    "pow" => pow,
+    "neg" => neg,
+    "inc" => inc,
+    "dec" => dec,
+    "abs" => abs,
+    "bit-and" => bit_and,
+    "bit-or" => bit_or,
+    "bit-xor" => bit_xor,
+    "bit-not" => bit_not,
+    "shl" => shl,
+    "shr" => shr,
+}
+
+fn number(name: &str, args: &[Token]) -> Result<f64, Unwind> {
+    arity(name, args, 1, 1)?;
+    match args[0].as_float() {
+        Some(number) => Ok(number),
+        None => error(format!("{} expects a number", name)),
+    }
+}
+
+/// Rounds floats with `round`, integers are already round and ratios round to integers.
+fn rounding(name: &str, args: Vec<Token>, round: fn(f64) -> f64) -> Eval {
+    let number = number(name, &args)?;
+    match args[0] {
+        Token::Int(_) | Token::Long(_) => Ok(args[0].clone()),
+        Token::Ratio(..) => Ok(Token::integer(round(number) as i64)),
+        _ => Ok(Token::Float(round(number))),
+    }
+}
+
+/// `(sqrt 2)`
+fn sqrt(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Float(number("sqrt", &args)?.sqrt()))
+}
+
+/// `(sin 3.14)`, in radians.
+fn sin(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Float(number("sin", &args)?.sin()))
+}
+
+/// `(cos 3.14)`, in radians.
+fn cos(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Float(number("cos", &args)?.cos()))
+}
+
+/// `(floor 2.5)`
+fn floor(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    rounding("floor", args, f64::floor)
+}
+
+/// `(ceil 2.5)`
+fn ceil(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    rounding("ceil", args, f64::ceil)
+}
+
+/// `(round 2.5)`, halfway cases round away from zero.
+fn round(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    rounding("round", args, f64::round)
+}
+
+/// `(pow 2 10)`, stays an integer for an integer base and a non-negative integer exponent.
+fn pow(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("pow", &args, 2, 2)?;
+    if let (Some(base), Token::Int(exponent)) = (args[0].as_integer(), &args[1]) {
+        if let Ok(exponent) = u32::try_from(*exponent) {
+            return match base.checked_pow(exponent) {
+                Some(power) => Ok(Token::integer(power)),
+                None => error("pow overflowed"),
+            };
+        }
+    }
+    match (args[0].as_float(), args[1].as_float()) {
+        (Some(base), Some(exponent)) => Ok(Token::Float(base.powf(exponent))),
+        _ => error("pow expects numbers"),
+    }
+}
+
+/// `(neg 5)` is -5, the same as `(- 5)`.
+fn neg(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("neg", &args, 1, 1)?;
+    Token::Int(0) - args[0].clone()
+}
+
+/// `(inc 5)` is 6, `(inc! x)` adds 1 to `x` itself.
+fn inc(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("inc", &args, 1, 1)?;
+    args[0].clone() + Token::Int(1)
+}
+
+/// `(dec 5)` is 4, `(dec! x)` subtracts 1 from `x` itself.
+fn dec(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("dec", &args, 1, 1)?;
+    args[0].clone() - Token::Int(1)
+}
+
+/// `(abs -4)` is 4, the distance of a number from zero.
+fn abs(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    number("abs", &args)?;
+    match args[0].compare(&Token::Int(0))? {
+        Ordering::Less => Token::Int(0) - args[0].clone(),
+        _ => Ok(args[0].clone()),
+    }
+}
+
+fn integers(name: &str, args: &[Token]) -> Result<Vec<i64>, Unwind> {
+    args.iter()
+        .map(|arg| match arg.as_integer() {
+            Some(i) => Ok(i),
+            None => error(format!("{} expects integers", name)),
+        })
+        .collect()
+}
+
+/// Combines two or more integers with a bitwise operation.
+fn bitwise(name: &str, args: Vec<Token>, operation: fn(i64, i64) -> i64) -> Eval {
+    arity(name, &args, 2, usize::MAX)?;
+    let integers = integers(name, &args)?;
+    Ok(Token::integer(
+        integers[1..]
+            .iter()
+            .fold(integers[0], |acc, i| operation(acc, *i)),
+    ))
+}
+
+/// `(bit-and 12 10)`
+fn bit_and(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    bitwise("bit-and", args, |a, b| a & b)
+}
+
+/// `(bit-or 12 10)`
+fn bit_or(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    bitwise("bit-or", args, |a, b| a | b)
+}
+
+/// `(bit-xor 12 10)`
+fn bit_xor(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    bitwise("bit-xor", args, |a, b| a ^ b)
+}
+
+/// `(bit-not 12)`
+fn bit_not(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("bit-not", &args, 1, 1)?;
+    Ok(Token::integer(!integers("bit-not", &args)?[0]))
+}
+
+/// Shifts an integer by 0 to 63 bits. Like arithmetic, a result too big for an `Int` becomes a
+/// `Long`, and only one too big for that is an error.
+fn shift(name: &str, args: Vec<Token>, operation: fn(i64, u32) -> i64) -> Eval {
+    arity(name, &args, 2, 2)?;
+    let integers = integers(name, &args)?;
+    let bits = match u32::try_from(integers[1]) {
+        Ok(bits) if bits < 64 => bits,
+        _ => return error(format!("{} can shift only by 0 to 63 bits", name)),
+    };
+    let shifted = operation(integers[0], bits);
+    if name == "shl" && shifted >> bits != integers[0] {
+        return error("integer overflow");
+    }
+    Ok(Token::integer(shifted))
+}
+
+/// `(shl 1 4)`
+fn shl(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    shift("shl", args, |i, bits| i << bits)
+}
+
+/// `(shr -16 2)`, keeps the sign.
+fn shr(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    shift("shr", args, |i, bits| i >> bits)
+}
@@ -0,0 +1,122 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::system::allowed;
+use crate::{error, Eval, Token, Unwind};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+
+builtins! {
+    /// Finds the network builtin called `name`.
+    "tcp-connect" => tcp_connect,
+    "tcp-listen" => tcp_listen,
+    "tcp-accept" => tcp_accept,
+    "tcp-send" => tcp_send,
+    "tcp-recv" => tcp_recv,
+    "tcp-recv-bytes" => tcp_recv_bytes,
+}
+
+fn port(name: &str, value: &Token) -> Result<u16, Unwind> {
+    match value {
+        Token::Int(port) if (0..=65535).contains(port) => Ok(*port as u16),
+        _ => error(format!("{} expects a port number", name)),
+    }
+}
+
+fn connection<'a>(name: &str, value: &'a Token) -> Result<&'a RefCell<TcpStream>, Unwind> {
+    match value {
+        Token::Connection(stream) => Ok(stream),
+        _ => error(format!("{} expects a connection", name)),
+    }
+}
+
+/// `(tcp-connect "example.com" 80)`
+fn tcp_connect(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("tcp-connect", &args, 2, 2)?;
+    allowed("tcp-connect", interpreter)?;
+    let host = text("tcp-connect", &args[0])?;
+    let port = port("tcp-connect", &args[1])?;
+    match TcpStream::connect((host, port)) {
+        Ok(stream) => Ok(Token::Connection(Rc::new(RefCell::new(stream)))),
+        Err(e) => error(format!(
+            "tcp-connect: can't connect to {}:{}: {}",
+            host, port, e
+        )),
+    }
+}
+
+/// `(tcp-listen 8080)` listens on localhost, `(tcp-listen 8080 "0.0.0.0")` on all interfaces.
+fn tcp_listen(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("tcp-listen", &args, 1, 2)?;
+    allowed("tcp-listen", interpreter)?;
+    let port = port("tcp-listen", &args[0])?;
+    let host = match args.get(1) {
+        Some(host) => text("tcp-listen", host)?,
+        None => "127.0.0.1",
+    };
+    match TcpListener::bind((host, port)) {
+        Ok(listener) => Ok(Token::Listener(Rc::new(listener))),
+        Err(e) => error(format!(
+            "tcp-listen: can't listen on {}:{}: {}",
+            host, port, e
+        )),
+    }
+}
+
+/// `(tcp-accept listener)` waits for the next incoming connection.
+fn tcp_accept(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("tcp-accept", &args, 1, 1)?;
+    match &args[0] {
+        Token::Listener(listener) => match listener.accept() {
+            Ok((stream, _)) => Ok(Token::Connection(Rc::new(RefCell::new(stream)))),
+            Err(e) => error(format!("tcp-accept: {}", e)),
+        },
+        _ => error("tcp-accept expects a listener"),
+    }
+}
+
+/// `(tcp-send conn "hello")` evaluates to the number of bytes sent, the data can be a string
+/// or bytes.
+fn tcp_send(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("tcp-send", &args, 2, 2)?;
+    let stream = connection("tcp-send", &args[0])?;
+    let data = match &args[1] {
+        Token::Bytes(data) => data,
+        data => text("tcp-send", data)?.as_bytes(),
+    };
+    match stream.borrow_mut().write_all(data) {
+        Ok(()) => Ok(Token::Int(data.len() as i32)),
+        Err(e) => error(format!("tcp-send: {}", e)),
+    }
+}
+
+/// `(tcp-recv conn 1024)` waits for up to n bytes, evaluates to an empty string once the
+/// other side closed the connection.
+fn tcp_recv(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let data = receive("tcp-recv", args)?;
+    Ok(Token::Str(String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// `(tcp-recv-bytes conn 1024)` is `tcp-recv` for binary data, evaluating to bytes.
+fn tcp_recv_bytes(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Bytes(receive("tcp-recv-bytes", args)?))
+}
+
+fn receive(name: &str, args: Vec<Token>) -> Result<Vec<u8>, Unwind> {
+    arity(name, &args, 2, 2)?;
+    let stream = connection(name, &args[0])?;
+    let size = match args[1] {
+        Token::Int(size) if size > 0 => size as usize,
+        _ => return error(format!("{} expects a positive number of bytes", name)),
+    };
+    let mut buffer = vec![0; size];
+    match stream.borrow_mut().read(&mut buffer) {
+        Ok(read) => {
+            buffer.truncate(read);
+            Ok(buffer)
+        }
+        Err(e) => error(format!("{}: {}", name, e)),
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, Stderr, Stdout, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Where programs' output goes, split into what they print and diagnostics so embedders can
+/// redirect or capture each of them.
+pub trait Output {
+    /// Printed output: `print`, `println`, `prin`.
+    fn out(&mut self, text: &str) -> io::Result<()>;
+    /// Diagnostics: `eprint` and errors.
+    fn err(&mut self, text: &str) -> io::Result<()>;
+}
+
+/// Output to a pair of writers, e.g. `Vec<u8>`s to capture everything a program prints.
+pub struct Streams<O, E> {
+    pub out: O,
+    pub err: E,
+}
+
+impl Streams<Stdout, Stderr> {
+    /// Output to stdout and stderr.
+    pub fn stdio() -> Self {
+        Self {
+            out: io::stdout(),
+            err: io::stderr(),
+        }
+    }
+}
+
+impl<O: Write, E: Write> Output for Streams<O, E> {
+    fn out(&mut self, text: &str) -> io::Result<()> {
+        // Flushed right away so prompts and `prin` show up before a newline.
+        self.out.write_all(text.as_bytes())?;
+        self.out.flush()
+    }
+
+    fn err(&mut self, text: &str) -> io::Result<()> {
+        self.err.write_all(text.as_bytes())
+    }
+}
+
+/// An output shared by an interpreter and the ones of the threads its program spawns, which
+/// print to it one at a time.
+#[derive(Clone)]
+pub struct Sink(Arc<Mutex<Box<dyn Output + Send>>>);
+
+impl Sink {
+    pub fn new(output: Box<dyn Output + Send>) -> Self {
+        Self(Arc::new(Mutex::new(output)))
+    }
+
+    /// Sends output to `output` from now on, returning where it went before.
+    pub fn replace(&self, output: Box<dyn Output + Send>) -> Box<dyn Output + Send> {
+        std::mem::replace(&mut *self.lock(), output)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn Output + Send>> {
+        // A thread panicking while printing leaves nothing half-done worth refusing output for.
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Output for Sink {
+    fn out(&mut self, text: &str) -> io::Result<()> {
+        self.lock().out(text)
+    }
+
+    fn err(&mut self, text: &str) -> io::Result<()> {
+        self.lock().err(text)
+    }
+}
@@ -0,0 +1,536 @@
+//! `pmap`, spreading calls over threads, and `spawn` with channels and atoms for scripts
+//! running code concurrently. Values can't be shared between threads as they may hold `Rc`s,
+//! so everything a thread needs or is sent is deep-copied into a `Plain` value first.
+
+use crate::builtins::{arity, builtins};
+use crate::interpreter::{Context, Interpreter};
+use crate::lists::items;
+use crate::persistent::Map;
+use crate::symbols::Name;
+use crate::vars::{Env, Scope};
+use crate::{error, Eval, Token, Unwind};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+builtins! {
+    /// Finds the parallel builtin called `name`.
+    "pmap" => pmap,
+    "spawn" => spawn,
+    "chan" => chan,
+    "send" => send,
+    "recv" => recv,
+    "atom" => atom,
+    "deref" => deref,
+    "reset!" => reset,
+}
+
+/// A channel between threads, any of which can send values on it or receive them.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    sender: Sender<Plain>,
+    receiver: Arc<Mutex<Receiver<Plain>>>,
+}
+
+impl Channel {
+    /// Whether two channels are the same one.
+    pub fn same(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.receiver, &other.receiver)
+    }
+
+    /// Where the channel is in memory, the same for all copies of it.
+    pub fn address(&self) -> usize {
+        Arc::as_ptr(&self.receiver) as usize
+    }
+}
+
+/// A value threads share, changed by one at a time. The version counts the changes, telling
+/// `swap!` whether another thread changed the value while it was computing the new one.
+#[derive(Debug, Clone)]
+pub struct Atom(Arc<Mutex<(u64, Plain)>>);
+
+impl Atom {
+    /// Whether two atoms are the same one.
+    pub fn same(&self, other: &Atom) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Where the atom is in memory, the same for all copies of it.
+    pub fn address(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+
+    fn lock(&self) -> MutexGuard<'_, (u64, Plain)> {
+        // A thread panicking while holding the lock can't leave a half-written value behind.
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sets the value to `f` of it. `f` runs without holding the lock, so it runs again when
+    /// another thread changed the value in the meantime.
+    pub fn update(
+        &self,
+        interpreter: &mut Interpreter,
+        mut f: impl FnMut(&mut Interpreter, Token) -> Eval,
+    ) -> Eval {
+        loop {
+            let (version, old) = {
+                let current = self.lock();
+                (current.0, current.1.token(&interpreter.scope()))
+            };
+            let new = f(interpreter, old)?;
+            let plain = match Plain::copy(&new) {
+                Some(plain) => plain,
+                None => return error("atoms can't hold ports, connections or continuations"),
+            };
+            let mut current = self.lock();
+            if current.0 == version {
+                *current = (version + 1, plain);
+                return Ok(new);
+            }
+        }
+    }
+}
+
+/// A value without any shared state, so it can move to another thread. The frames closures in
+/// it captured are copied along, each once, so closures sharing a frame still share it after.
+#[derive(Debug)]
+struct Plain {
+    value: Data,
+    frames: Vec<Frame>,
+}
+
+/// A frame a closure captured, with the frame around it, outside the global one, if any.
+#[derive(Debug)]
+struct Frame {
+    parent: Option<usize>,
+    vars: Vec<(Name, Data)>,
+}
+
+#[derive(Debug)]
+enum Data {
+    Int(i32),
+    Long(i64),
+    Ratio(i64, i64),
+    Float(f64),
+    #[cfg(feature = "complex")]
+    Complex(f64, f64),
+    Symbol(Name),
+    Str(String),
+    List(Vec<Data>),
+    Set(Vec<Data>),
+    Map(Vec<(Data, Data)>),
+    Vector(Vec<Data>),
+    /// A function, with the index of the frame it was created in, `None` for the global one.
+    Lambda(Vec<Data>, Vec<Data>, Option<usize>),
+    Values(Vec<Data>),
+    Bytes(Vec<u8>),
+    Frozen(Box<Data>),
+    Channel(Channel),
+    Atom(Atom),
+    True,
+    False,
+    Nil,
+}
+
+impl Plain {
+    /// A copy of `value`, `None` when it holds something tied to this thread: a continuation,
+    /// port or connection.
+    fn copy(value: &Token) -> Option<Plain> {
+        let mut copier = Copier::default();
+        let value = copier.copy(value)?;
+        Some(Plain {
+            value,
+            frames: copier.frames,
+        })
+    }
+
+    /// This thread's own copy of the value, with the global variables of its closures being
+    /// the ones around `scope`.
+    fn token(&self, scope: &Scope) -> Token {
+        let global = Env::outermost(scope);
+        let mut scopes = vec![None; self.frames.len()];
+        for index in 0..self.frames.len() {
+            self.scope(index, &global, &mut scopes);
+        }
+        let scopes: Vec<Scope> = scopes.into_iter().flatten().collect();
+        for (frame, scope) in self.frames.iter().zip(&scopes) {
+            for (name, value) in &frame.vars {
+                let value = value.token(&global, &scopes);
+                scope.borrow_mut().insert(*name, value);
+            }
+        }
+        self.value.token(&global, &scopes)
+    }
+
+    /// The new scope for the frame at `index`, made after the ones around it.
+    fn scope(&self, index: usize, global: &Scope, scopes: &mut Vec<Option<Scope>>) -> Scope {
+        if let Some(scope) = &scopes[index] {
+            return scope.clone();
+        }
+        let parent = match self.frames[index].parent {
+            Some(parent) => self.scope(parent, global, scopes),
+            None => global.clone(),
+        };
+        let scope = Env::child(&parent);
+        scopes[index] = Some(scope.clone());
+        scope
+    }
+}
+
+/// Copies values, and the frames of the closures in them, which can refer back to themselves.
+#[derive(Default)]
+struct Copier {
+    frames: Vec<Frame>,
+    copied: HashMap<*const RefCell<Env>, usize>,
+}
+
+impl Copier {
+    fn copy(&mut self, value: &Token) -> Option<Data> {
+        let data = match value {
+            Token::Int(i) => Data::Int(*i),
+            Token::Long(i) => Data::Long(*i),
+            Token::Ratio(n, d) => Data::Ratio(*n, *d),
+            Token::Float(f) => Data::Float(*f),
+            #[cfg(feature = "complex")]
+            Token::Complex(re, im) => Data::Complex(*re, *im),
+            Token::Symbol(name) => Data::Symbol(*name),
+            Token::Str(s) => Data::Str(s.clone()),
+            Token::List(items) => Data::List(self.all(items)?),
+            Token::Set(items) => Data::Set(self.all(items)?),
+            Token::Map(map) => Data::Map(
+                map.entries()
+                    .into_iter()
+                    .map(|(key, value)| Some((self.copy(key)?, self.copy(value)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Token::Vector(vector) => Data::Vector(self.all(&vector.items())?),
+            Token::Lambda(params, body, env) => {
+                Data::Lambda(self.all(params)?, self.all(body)?, self.frame(env)?)
+            }
+            Token::Values(values) => Data::Values(self.all(values)?),
+            Token::Bytes(data) => Data::Bytes(data.clone()),
+            Token::Frozen(value) => Data::Frozen(Box::new(self.copy(value)?)),
+            Token::Channel(channel) => Data::Channel(channel.clone()),
+            Token::Atom(atom) => Data::Atom(atom.clone()),
+            Token::True => Data::True,
+            Token::False => Data::False,
+            Token::Nil => Data::Nil,
+            _ => return None,
+        };
+        Some(data)
+    }
+
+    fn all<'a>(&mut self, values: impl IntoIterator<Item = &'a Token>) -> Option<Vec<Data>> {
+        values.into_iter().map(|value| self.copy(value)).collect()
+    }
+
+    /// The index of the copy of the frame `scope`, `None` for the global frame, which isn't
+    /// copied: the thread getting the value has its own.
+    fn frame(&mut self, scope: &Scope) -> Option<Option<usize>> {
+        let env = scope.borrow();
+        let parent = match env.parent() {
+            Some(parent) => parent,
+            None => return Some(None),
+        };
+        if let Some(index) = self.copied.get(&Rc::as_ptr(scope)) {
+            return Some(Some(*index));
+        }
+        // Claimed before copying the variables, which may hold closures created in the frame.
+        let index = self.frames.len();
+        self.frames.push(Frame {
+            parent: None,
+            vars: vec![],
+        });
+        self.copied.insert(Rc::as_ptr(scope), index);
+        let parent = self.frame(parent)?;
+        let vars = env
+            .vars()
+            .map(|(name, value)| Some((name, self.copy(value)?)))
+            .collect::<Option<_>>()?;
+        self.frames[index] = Frame { parent, vars };
+        Some(Some(index))
+    }
+}
+
+fn tokens<T: FromIterator<Token>>(values: &[Data], global: &Scope, scopes: &[Scope]) -> T {
+    values
+        .iter()
+        .map(|value| value.token(global, scopes))
+        .collect()
+}
+
+impl Data {
+    fn token(&self, global: &Scope, scopes: &[Scope]) -> Token {
+        match self {
+            Data::Int(i) => Token::Int(*i),
+            Data::Long(i) => Token::Long(*i),
+            Data::Ratio(n, d) => Token::Ratio(*n, *d),
+            Data::Float(f) => Token::Float(*f),
+            #[cfg(feature = "complex")]
+            Data::Complex(re, im) => Token::Complex(*re, *im),
+            Data::Symbol(name) => Token::Symbol(*name),
+            Data::Str(s) => Token::Str(s.clone()),
+            Data::List(items) => Token::List(tokens(items, global, scopes)),
+            Data::Set(items) => Token::Set(tokens(items, global, scopes)),
+            Data::Map(entries) => {
+                Token::Map(entries.iter().fold(Map::default(), |map, (key, value)| {
+                    map.put(key.token(global, scopes), value.token(global, scopes))
+                }))
+            }
+            Data::Vector(items) => Token::Vector(tokens(items, global, scopes)),
+            Data::Lambda(params, body, frame) => {
+                let env = match frame {
+                    Some(index) => scopes[*index].clone(),
+                    None => global.clone(),
+                };
+                Token::Lambda(
+                    tokens(params, global, scopes),
+                    tokens(body, global, scopes),
+                    env,
+                )
+            }
+            Data::Values(values) => Token::Values(tokens(values, global, scopes)),
+            Data::Bytes(data) => Token::Bytes(data.clone()),
+            Data::Frozen(value) => Token::Frozen(Rc::new(value.token(global, scopes))),
+            Data::Channel(channel) => Token::Channel(channel.clone()),
+            Data::Atom(atom) => Token::Atom(atom.clone()),
+            Data::True => Token::True,
+            Data::False => Token::False,
+            Data::Nil => Token::Nil,
+        }
+    }
+}
+
+/// `(pmap f '(1 2 3))` is `map` with the calls spread over as many threads as there are
+/// cores. Each thread gets a copy of the variables visible to `f`, leaving out ports and
+/// connections, so `f` shouldn't rely on side effects.
+fn pmap(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("pmap", &args, 2, 2)?;
+    let function = match Plain::copy(&args[0]) {
+        Some(function) => function,
+        None => return error("pmap expects a function that can move to another thread"),
+    };
+    let list = items("pmap", args[1].clone())?;
+    let list = match list.iter().map(Plain::copy).collect::<Option<Vec<_>>>() {
+        Some(list) => list,
+        None => return error("pmap can't move ports, connections or continuations to threads"),
+    };
+    let (scope, snapshot) = snapshot(&args[0], interpreter);
+
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = list.len().div_ceil(workers).max(1);
+    let mut chunks = vec![];
+    let mut list = list.into_iter();
+    loop {
+        let chunk: Vec<Plain> = list.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        chunks.push(chunk);
+    }
+
+    let results: Vec<Result<Vec<Plain>, String>> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let (function, snapshot) = (&function, &snapshot);
+                scope.spawn(move || work(function, snapshot, chunk))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| match handle.join() {
+                Ok(result) => result,
+                Err(_) => Err("pmap worker panicked".to_string()),
+            })
+            .collect()
+    });
+    let mut values = vec![];
+    for result in results {
+        match result {
+            Ok(chunk) => values.extend(chunk.iter().map(|value| value.token(&scope))),
+            Err(message) => return error(message),
+        }
+    }
+    Ok(Token::List(values))
+}
+
+/// What a thread's interpreter starts from: copies of the variables visible to the function
+/// it runs and of the macros, leaving out ports and connections, and what it shares with the
+/// program's interpreter, the output included.
+struct Snapshot {
+    vars: Vec<(Name, Plain)>,
+    macros: Vec<(Name, Plain)>,
+    context: Context,
+}
+
+/// The scope `function` runs in, and the snapshot for threads running it.
+fn snapshot(function: &Token, interpreter: &Interpreter) -> (Scope, Snapshot) {
+    let scope = match function {
+        Token::Lambda(_, _, env) => env.clone(),
+        _ => interpreter.scope(),
+    };
+    let visible = scope.borrow().visible();
+    let snapshot = Snapshot {
+        vars: copies(visible.iter().map(|(name, value)| (*name, value))),
+        macros: copies(interpreter.macros()),
+        context: interpreter.context(),
+    };
+    (scope, snapshot)
+}
+
+/// Copies of the values that can move to another thread, by name.
+fn copies<'a>(values: impl Iterator<Item = (Name, &'a Token)>) -> Vec<(Name, Plain)> {
+    values
+        .filter_map(|(name, value)| Some((name, Plain::copy(value)?)))
+        .collect()
+}
+
+impl Snapshot {
+    /// An interpreter of a thread's own, starting from the snapshot.
+    fn interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::in_context(&self.context);
+        let scope = interpreter.scope();
+        for (name, value) in &self.vars {
+            let value = value.token(&scope);
+            scope.borrow_mut().insert(*name, value);
+        }
+        for (name, expander) in &self.macros {
+            interpreter.define_macro(*name, expander.token(&scope));
+        }
+        interpreter
+    }
+}
+
+/// Calls `function` with `args` in `interpreter`, `name` being the builtin it's called by.
+fn call_in(
+    name: &str,
+    interpreter: &mut Interpreter,
+    function: &Plain,
+    args: Vec<Plain>,
+) -> Result<Token, String> {
+    let scope = interpreter.scope();
+    let args = args.iter().map(|arg| arg.token(&scope)).collect();
+    interpreter
+        .call(function.token(&scope), args)
+        .map_err(|unwind| match unwind {
+            Unwind::Error(message) => message,
+            Unwind::Escape(..) => format!("{} can't escape to a continuation", name),
+            Unwind::Thrown(value) => format!("uncaught {}", value),
+            Unwind::Return(_) => format!("{} can't return outside a function", name),
+        })
+}
+
+/// Calls `function` with each item of `chunk` in an interpreter of its own.
+fn work(function: &Plain, snapshot: &Snapshot, chunk: Vec<Plain>) -> Result<Vec<Plain>, String> {
+    let mut interpreter = snapshot.interpreter();
+    chunk
+        .into_iter()
+        .map(|item| {
+            let value = call_in("pmap", &mut interpreter, function, vec![item])?;
+            Plain::copy(&value).ok_or_else(|| "pmap results can't hold ports".to_string())
+        })
+        .collect()
+}
+
+/// `(spawn (lambda () ...))` calls the function on a thread of its own and goes on without
+/// waiting for it. Like with `pmap`, the thread gets a copy of the variables visible to the
+/// function, so it shares only channels and atoms with the rest of the program. Its errors
+/// are printed to the program's stderr.
+fn spawn(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("spawn", &args, 1, 1)?;
+    let function = match Plain::copy(&args[0]) {
+        Some(function) => function,
+        None => return error("spawn expects a function that can move to another thread"),
+    };
+    let (_, snapshot) = snapshot(&args[0], interpreter);
+    thread::spawn(move || {
+        let mut interpreter = snapshot.interpreter();
+        if let Err(message) = call_in("spawn", &mut interpreter, &function, vec![]) {
+            let _ = interpreter.write_err(format!("error in spawned thread: {}\n", message));
+        }
+    });
+    Ok(Token::Nil)
+}
+
+/// `(chan)`, a channel to `send` values to other threads on, which `recv` them.
+fn chan(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("chan", &args, 0, 0)?;
+    let (sender, receiver) = mpsc::channel();
+    Ok(Token::Channel(Channel {
+        sender,
+        receiver: Arc::new(Mutex::new(receiver)),
+    }))
+}
+
+/// `(send c value)` puts a copy of `value` on the channel `c` and goes on.
+fn send(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("send", &args, 2, 2)?;
+    let channel = match &args[0] {
+        Token::Channel(channel) => channel,
+        _ => return error("send expects a channel"),
+    };
+    let value = match Plain::copy(&args[1]) {
+        Some(value) => value,
+        None => return error("send can't move ports, connections or continuations to threads"),
+    };
+    match channel.sender.send(value) {
+        Ok(()) => Ok(args[1].clone()),
+        Err(_) => error("send on a closed channel"),
+    }
+}
+
+/// `(recv c)` waits for a value on the channel `c` and takes it.
+fn recv(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("recv", &args, 1, 1)?;
+    let channel = match &args[0] {
+        Token::Channel(channel) => channel,
+        _ => return error("recv expects a channel"),
+    };
+    let receiver = match channel.receiver.lock() {
+        Ok(receiver) => receiver,
+        Err(_) => return error("recv on a broken channel"),
+    };
+    match receiver.recv() {
+        Ok(value) => Ok(value.token(&interpreter.scope())),
+        Err(_) => error("recv on a closed channel"),
+    }
+}
+
+/// `(atom 0)`, a value `spawn`ed threads share, read with `deref` and changed with `reset!` or
+/// `swap!`.
+fn atom(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("atom", &args, 1, 1)?;
+    match Plain::copy(&args[0]) {
+        Some(value) => Ok(Token::Atom(Atom(Arc::new(Mutex::new((0, value)))))),
+        None => error("atoms can't hold ports, connections or continuations"),
+    }
+}
+
+fn atom_of<'a>(name: &str, value: &'a Token) -> Result<&'a Atom, Unwind> {
+    match value {
+        Token::Atom(atom) => Ok(atom),
+        _ => error(format!("{} expects an atom", name)),
+    }
+}
+
+/// `(deref a)`, the value of the atom `a` now.
+fn deref(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("deref", &args, 1, 1)?;
+    let atom = atom_of("deref", &args[0])?;
+    let value = atom.lock().1.token(&interpreter.scope());
+    Ok(value)
+}
+
+/// `(reset! a value)` sets the atom `a` to `value`, whatever it was.
+fn reset(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("reset!", &args, 2, 2)?;
+    let value = args[1].clone();
+    atom_of("reset!", &args[0])?.update(interpreter, |_, _| Ok(value.clone()))
+}
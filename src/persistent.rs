@@ -0,0 +1,371 @@
+//! Maps and vectors changed by making new ones, which share everything but the path to the
+//! change with the old ones. Adding to or changing one costs O(log n) instead of a copy, and the
+//! old one stays as it was, for programs passing versions of their data around.
+
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::lists::items;
+use crate::{error, Eval, Token, Unwind};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::rc::Rc;
+
+/// A binary trie of entries by the bits of their keys' hashes, lowest first, so it branches
+/// only as deep as needed to tell the hashes apart.
+#[derive(Debug, Clone, Default)]
+enum Trie {
+    #[default]
+    Empty,
+    /// The entries whose keys have the same hash.
+    Leaf(u64, Rc<Vec<(Token, Token)>>),
+    Branch(Rc<Trie>, Rc<Trie>),
+}
+
+fn bit(hash: u64, depth: u32) -> bool {
+    hash >> depth & 1 == 1
+}
+
+impl Trie {
+    fn get(&self, hash: u64, key: &Token) -> Option<&Token> {
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            match node {
+                Trie::Empty => return None,
+                Trie::Leaf(h, entries) if *h == hash => {
+                    return entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+                }
+                Trie::Leaf(..) => return None,
+                Trie::Branch(zero, one) => {
+                    node = if bit(hash, depth) { one } else { zero };
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// The trie with `key` set to `value`, and whether the key is new.
+    fn insert(&self, hash: u64, depth: u32, key: Token, value: Token) -> (Trie, bool) {
+        match self {
+            Trie::Empty => (Trie::Leaf(hash, Rc::new(vec![(key, value)])), true),
+            Trie::Leaf(h, entries) if *h == hash => {
+                let mut entries = entries.as_ref().clone();
+                let added = match entries.iter_mut().find(|(k, _)| *k == key) {
+                    Some(entry) => {
+                        entry.1 = value;
+                        false
+                    }
+                    None => {
+                        entries.push((key, value));
+                        true
+                    }
+                };
+                (Trie::Leaf(hash, Rc::new(entries)), added)
+            }
+            Trie::Leaf(h, _) => {
+                // Moves the leaf a level down, where the hashes may tell apart.
+                let leaf = Rc::new(self.clone());
+                let empty = Rc::new(Trie::Empty);
+                let branch = if bit(*h, depth) {
+                    Trie::Branch(empty, leaf)
+                } else {
+                    Trie::Branch(leaf, empty)
+                };
+                branch.insert(hash, depth, key, value)
+            }
+            Trie::Branch(zero, one) => {
+                let (zero, one, added) = if bit(hash, depth) {
+                    let (one, added) = one.insert(hash, depth + 1, key, value);
+                    (zero.clone(), Rc::new(one), added)
+                } else {
+                    let (zero, added) = zero.insert(hash, depth + 1, key, value);
+                    (Rc::new(zero), one.clone(), added)
+                };
+                (Trie::Branch(zero, one), added)
+            }
+        }
+    }
+
+    /// The trie without `key`, `None` when it isn't there.
+    fn remove(&self, hash: u64, depth: u32, key: &Token) -> Option<Trie> {
+        match self {
+            Trie::Leaf(h, entries) if *h == hash => {
+                let position = entries.iter().position(|(k, _)| k == key)?;
+                let mut entries = entries.as_ref().clone();
+                entries.remove(position);
+                if entries.is_empty() {
+                    return Some(Trie::Empty);
+                }
+                Some(Trie::Leaf(hash, Rc::new(entries)))
+            }
+            Trie::Branch(zero, one) => {
+                let (zero, one) = if bit(hash, depth) {
+                    (zero.clone(), Rc::new(one.remove(hash, depth + 1, key)?))
+                } else {
+                    (Rc::new(zero.remove(hash, depth + 1, key)?), one.clone())
+                };
+                // A leaf left alone moves back up, so lookups don't walk through empty branches.
+                match (zero.as_ref(), one.as_ref()) {
+                    (Trie::Empty, Trie::Empty) => Some(Trie::Empty),
+                    (Trie::Empty, Trie::Leaf(..)) => Some(one.as_ref().clone()),
+                    (Trie::Leaf(..), Trie::Empty) => Some(zero.as_ref().clone()),
+                    _ => Some(Trie::Branch(zero, one)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn entries<'a>(&'a self, found: &mut Vec<&'a (Token, Token)>) {
+        match self {
+            Trie::Empty => {}
+            Trie::Leaf(_, entries) => found.extend(entries.iter()),
+            Trie::Branch(zero, one) => {
+                zero.entries(found);
+                one.entries(found);
+            }
+        }
+    }
+}
+
+/// A persistent map from any values to any values.
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    trie: Trie,
+    len: usize,
+}
+
+fn hash_of(key: &Token) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Map {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, key: &Token) -> Option<&Token> {
+        self.trie.get(hash_of(key), key)
+    }
+
+    /// The map with `key` set to `value`.
+    pub fn put(&self, key: Token, value: Token) -> Map {
+        let (trie, added) = self.trie.insert(hash_of(&key), 0, key, value);
+        Map {
+            trie,
+            len: self.len + added as usize,
+        }
+    }
+
+    /// The map without `key`.
+    pub fn remove(&self, key: &Token) -> Map {
+        match self.trie.remove(hash_of(key), 0, key) {
+            Some(trie) => Map {
+                trie,
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// The keys and values, in no particular order.
+    pub fn entries(&self) -> Vec<&(Token, Token)> {
+        let mut found = Vec::with_capacity(self.len);
+        self.trie.entries(&mut found);
+        found
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self
+                .entries()
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+/// A persistent vector, its items keyed by their indexes in a trie. Consecutive indexes differ
+/// in their lowest bits, so the trie stays balanced.
+#[derive(Debug, Clone, Default)]
+pub struct Vector {
+    trie: Trie,
+    len: usize,
+}
+
+impl Vector {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Token> {
+        self.trie.get(index as u64, &Token::integer(index as i64))
+    }
+
+    /// The vector with the item at `index`, up to and including the length, set to `value`.
+    pub fn set(&self, index: usize, value: Token) -> Vector {
+        let key = Token::integer(index as i64);
+        let (trie, added) = self.trie.insert(index as u64, 0, key, value);
+        Vector {
+            trie,
+            len: self.len + added as usize,
+        }
+    }
+
+    /// The vector with `value` added at the end.
+    pub fn push(&self, value: Token) -> Vector {
+        self.set(self.len, value)
+    }
+
+    pub fn items(&self) -> Vec<Token> {
+        (0..self.len).filter_map(|i| self.get(i).cloned()).collect()
+    }
+}
+
+impl FromIterator<Token> for Vector {
+    fn from_iter<T: IntoIterator<Item = Token>>(items: T) -> Self {
+        items
+            .into_iter()
+            .fold(Vector::default(), |vector, item| vector.push(item))
+    }
+}
+
+impl PartialEq for Vector {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && (0..self.len).all(|i| self.get(i) == other.get(i))
+    }
+}
+
+builtins! {
+    /// Finds the builtin making or changing persistent maps and vectors called `name`.
+    "map-of" => map_of,
+    "alist->map" => alist_to_map,
+    "vector" => vector,
+    "put" => put,
+    "get" => get,
+    "remove" => remove,
+    "keys" => keys,
+    "vals" => vals,
+}
+
+/// `(map-of 'a 1 'b 2)`, a map with keys and values in turns.
+fn map_of(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    if args.len() % 2 == 1 {
+        return error("map-of expects keys and values in pairs");
+    }
+    let mut map = Map::default();
+    let mut args = args.into_iter();
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        map = map.put(key, value);
+    }
+    Ok(Token::Map(map))
+}
+
+/// `(alist->map '((a 1) (b 2)))`, a map with the pairs of an association list. A key the
+/// list has twice gets the value of its first pair, the one `assoc` finds.
+fn alist_to_map(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("alist->map", &args, 1, 1)?;
+    let mut map = Map::default();
+    for pair in items("alist->map", args.remove(0))?.into_iter().rev() {
+        match pair {
+            Token::List(mut pair) if pair.len() == 2 => {
+                let value = pair.pop().unwrap();
+                map = map.put(pair.pop().unwrap(), value);
+            }
+            _ => return error("alist->map expects a list of (key value) pairs"),
+        }
+    }
+    Ok(Token::Map(map))
+}
+
+/// `(vector 1 2 3)`
+fn vector(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Vector(args.into_iter().collect()))
+}
+
+fn index(name: &str, index: &Token, vector: &Vector, extend: bool) -> Result<usize, Unwind> {
+    let len = vector.len() + extend as usize;
+    match index.as_integer() {
+        Some(i) if i >= 0 && (i as usize) < len => Ok(i as usize),
+        Some(i) => error(format!(
+            "index {} out of bounds for vector of length {}",
+            i,
+            vector.len()
+        )),
+        None => error(format!("{} expects an integer index into a vector", name)),
+    }
+}
+
+/// `(put m 'a 1)` is `m` with the key `a` set to 1, `(put v 0 'x)` `v` with `x` as its first
+/// item, or added at the end when the index is the length. `m` and `v` stay as they are.
+fn put(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("put", &args, 3, 3)?;
+    let value = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    match &args[0] {
+        Token::Map(map) => Ok(Token::Map(map.put(key, value))),
+        Token::Vector(vector) => {
+            let index = index("put", &key, vector, true)?;
+            Ok(Token::Vector(vector.set(index, value)))
+        }
+        _ => error("put expects a map or a vector"),
+    }
+}
+
+/// `(get m 'a)` is the value of the key `a`, `(get v 0)` the first item. Missing ones are
+/// `nil`, or the value given after the key like `(get m 'a 0)`.
+fn get(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("get", &args, 2, 3)?;
+    let default = if args.len() == 3 {
+        args.pop().unwrap()
+    } else {
+        Token::Nil
+    };
+    let found = match &args[0] {
+        Token::Map(map) => map.get(&args[1]),
+        Token::Vector(vector) => match args[1].as_integer() {
+            Some(i) if i >= 0 => vector.get(i as usize),
+            Some(_) => None,
+            None => return error("get expects an integer index into a vector"),
+        },
+        _ => return error("get expects a map or a vector"),
+    };
+    Ok(found.cloned().unwrap_or(default))
+}
+
+/// `(remove m 'a)`, `m` without the key `a`.
+fn remove(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("remove", &args, 2, 2)?;
+    match &args[0] {
+        Token::Map(map) => Ok(Token::Map(map.remove(&args[1]))),
+        _ => error("remove expects a map"),
+    }
+}
+
+fn map_entries(name: &str, args: &[Token]) -> Result<Vec<(Token, Token)>, Unwind> {
+    arity(name, args, 1, 1)?;
+    match &args[0] {
+        Token::Map(map) => Ok(map.entries().into_iter().cloned().collect()),
+        _ => error(format!("{} expects a map", name)),
+    }
+}
+
+/// `(keys m)`, in no particular order.
+fn keys(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let entries = map_entries("keys", &args)?;
+    Ok(Token::List(
+        entries.into_iter().map(|(key, _)| key).collect(),
+    ))
+}
+
+/// `(vals m)`, in the order of `(keys m)`.
+fn vals(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let entries = map_entries("vals", &args)?;
+    Ok(Token::List(
+        entries.into_iter().map(|(_, value)| value).collect(),
+    ))
+}
@@ -0,0 +1,138 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::system::allowed;
+use crate::{error, Eval, Token, Unwind};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
+
+/// An open file that programs read from or write to a bit at a time.
+#[derive(Debug)]
+pub enum Port {
+    Input(BufReader<File>),
+    Output(BufWriter<File>),
+    Closed,
+}
+
+builtins! {
+    /// Finds the port builtin called `name`.
+    "open-input-file" => open_input_file,
+    "open-output-file" => open_output_file,
+    "read-char" => read_char,
+    "read-line" => read_line,
+    "write-string" => write_string,
+    "close-port" => close_port,
+}
+
+fn port<'a>(name: &str, value: &'a Token) -> Result<&'a Rc<RefCell<Port>>, Unwind> {
+    match value {
+        Token::Port(port) => Ok(port),
+        _ => error(format!("{} expects a port, got {}", name, value)),
+    }
+}
+
+fn opened(name: &str, path: &str, file: std::io::Result<File>) -> Result<File, Unwind> {
+    match file {
+        Ok(file) => Ok(file),
+        Err(e) => error(format!("{}: can't open {:?}: {}", name, path, e)),
+    }
+}
+
+/// `(open-input-file "notes.txt")`
+fn open_input_file(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("open-input-file", &args, 1, 1)?;
+    allowed("open-input-file", interpreter)?;
+    let path = text("open-input-file", &args[0])?;
+    let file = opened("open-input-file", path, File::open(path))?;
+    Ok(Token::Port(Rc::new(RefCell::new(Port::Input(
+        BufReader::new(file),
+    )))))
+}
+
+/// `(open-output-file "notes.txt")` creates the file or empties an existing one.
+fn open_output_file(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("open-output-file", &args, 1, 1)?;
+    allowed("open-output-file", interpreter)?;
+    let path = text("open-output-file", &args[0])?;
+    let file = opened("open-output-file", path, File::create(path))?;
+    Ok(Token::Port(Rc::new(RefCell::new(Port::Output(
+        BufWriter::new(file),
+    )))))
+}
+
+/// `(read-char port)` evaluates to the next character as a string, nil at the end of the file.
+fn read_char(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("read-char", &args, 1, 1)?;
+    let mut port = port("read-char", &args[0])?.borrow_mut();
+    let reader = match &mut *port {
+        Port::Input(reader) => reader,
+        _ => return error("read-char expects an open input port"),
+    };
+    let mut bytes = [0; 4];
+    let length = match reader.read(&mut bytes[..1]) {
+        Ok(0) => return Ok(Token::Nil),
+        // The leading byte of a UTF-8 sequence tells how many bytes follow it.
+        Ok(_) => match bytes[0] {
+            0xf0..=0xff => 4,
+            0xe0..=0xef => 3,
+            0xc0..=0xdf => 2,
+            _ => 1,
+        },
+        Err(e) => return error(format!("read-char: {}", e)),
+    };
+    if let Err(e) = reader.read_exact(&mut bytes[1..length]) {
+        return error(format!("read-char: {}", e));
+    }
+    match std::str::from_utf8(&bytes[..length]) {
+        Ok(c) => Ok(Token::Str(c.to_string())),
+        Err(e) => error(format!("read-char: {}", e)),
+    }
+}
+
+/// `(read-line port)` evaluates to the next line without its line ending, nil at the end of the
+/// file.
+fn read_line(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("read-line", &args, 1, 1)?;
+    let mut port = port("read-line", &args[0])?.borrow_mut();
+    let reader = match &mut *port {
+        Port::Input(reader) => reader,
+        _ => return error("read-line expects an open input port"),
+    };
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Ok(Token::Nil),
+        Ok(_) => Ok(Token::Str(
+            line.trim_end_matches(&['\r', '\n'][..]).to_string(),
+        )),
+        Err(e) => error(format!("read-line: {}", e)),
+    }
+}
+
+/// `(write-string port "hello")`
+fn write_string(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("write-string", &args, 2, 2)?;
+    let mut port = port("write-string", &args[0])?.borrow_mut();
+    let writer = match &mut *port {
+        Port::Output(writer) => writer,
+        _ => return error("write-string expects an open output port"),
+    };
+    match writer.write_all(text("write-string", &args[1])?.as_bytes()) {
+        Ok(()) => Ok(Token::Nil),
+        Err(e) => error(format!("write-string: {}", e)),
+    }
+}
+
+/// `(close-port port)` flushes what was written, closing a port again does nothing.
+fn close_port(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("close-port", &args, 1, 1)?;
+    let mut port = port("close-port", &args[0])?.borrow_mut();
+    if let Port::Output(writer) = &mut *port {
+        if let Err(e) = writer.flush() {
+            return error(format!("close-port: {}", e));
+        }
+    }
+    *port = Port::Closed;
+    Ok(Token::Nil)
+}
@@ -0,0 +1,32 @@
+use crate::builtins::builtins;
+use crate::interpreter::Interpreter;
+use crate::{Eval, Token};
+
+builtins! {
+    /// Finds the printing builtin called `name`.
+    "println" => println,
+    "prin" => prin,
+    "eprint" => eprint,
+}
+
+fn concatenated(args: &[Token]) -> String {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// `(println "x is " x)` prints its arguments one after another and ends the line.
+fn println(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    interpreter.write(concatenated(&args) + "\n")?;
+    Ok(Token::Nil)
+}
+
+/// `(prin "x is " x)` prints its arguments one after another without ending the line.
+fn prin(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    interpreter.write(concatenated(&args))?;
+    Ok(Token::Nil)
+}
+
+/// `(eprint "oops: " x)` prints its arguments to stderr and ends the line.
+fn eprint(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    interpreter.write_err(concatenated(&args) + "\n")?;
+    Ok(Token::Nil)
+}
@@ -0,0 +1,28 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::{Eval, Token};
+
+builtins! {
+    /// Finds the reading builtin called `name`.
+    "read-all-stdin" => read_all_stdin,
+    "read-stdin-lines" => read_stdin_lines,
+}
+
+/// `(read-all-stdin)` reads everything left on stdin into a string.
+fn read_all_stdin(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("read-all-stdin", &args, 0, 0)?;
+    Ok(Token::Str(interpreter.read_all()?))
+}
+
+/// `(read-stdin-lines)` reads everything left on stdin into a list of lines, without their
+/// line endings.
+fn read_stdin_lines(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("read-stdin-lines", &args, 0, 0)?;
+    Ok(Token::List(
+        interpreter
+            .read_all()?
+            .lines()
+            .map(|line| Token::Str(line.to_string()))
+            .collect(),
+    ))
+}
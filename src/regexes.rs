@@ -0,0 +1,75 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::{error, Eval, Token, Unwind};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    /// Patterns compiled so far, scripts tend to use the same few ones in loops.
+    static PATTERNS: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+builtins! {
+    /// Finds the regex builtin called `name`.
+    "re-match" => re_match,
+    "re-find-all" => re_find_all,
+    "re-replace" => re_replace,
+}
+
+fn compiled(name: &str, pattern: &Token) -> Result<Regex, Unwind> {
+    let pattern = text(name, pattern)?;
+    PATTERNS.with(|patterns| {
+        if let Some(regex) = patterns.borrow().get(pattern) {
+            return Ok(regex.clone());
+        }
+        match Regex::new(pattern) {
+            Ok(regex) => {
+                let mut patterns = patterns.borrow_mut();
+                patterns.insert(pattern.to_string(), regex.clone());
+                Ok(regex)
+            }
+            Err(e) => error(format!("{}: invalid pattern: {}", name, e)),
+        }
+    })
+}
+
+/// `(re-match "(\w+)@(\w+)" "me@host")` is the list of the first match and its groups
+/// (`nil` for groups that didn't participate), or `false` when there is no match.
+fn re_match(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("re-match", &args, 2, 2)?;
+    let regex = compiled("re-match", &args[0])?;
+    match regex.captures(text("re-match", &args[1])?) {
+        None => Ok(Token::False),
+        Some(captures) => Ok(Token::List(
+            captures
+                .iter()
+                .map(|group| match group {
+                    Some(group) => Token::Str(group.as_str().to_string()),
+                    None => Token::Nil,
+                })
+                .collect(),
+        )),
+    }
+}
+
+/// `(re-find-all "[0-9]+" "1 2 3")` is the list of all non-overlapping matches.
+fn re_find_all(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("re-find-all", &args, 2, 2)?;
+    let regex = compiled("re-find-all", &args[0])?;
+    Ok(Token::List(
+        regex
+            .find_iter(text("re-find-all", &args[1])?)
+            .map(|found| Token::Str(found.as_str().to_string()))
+            .collect(),
+    ))
+}
+
+/// `(re-replace "(\w+)@" "me@host" "$1 at ")` replaces every match, `$1` refers to groups.
+fn re_replace(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("re-replace", &args, 3, 3)?;
+    let regex = compiled("re-replace", &args[0])?;
+    let replaced = regex.replace_all(text("re-replace", &args[1])?, text("re-replace", &args[2])?);
+    Ok(Token::Str(replaced.into_owned()))
+}
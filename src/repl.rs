@@ -0,0 +1,258 @@
+//! An interactive session, started when no source file is given. Lines starting with `:` are
+//! commands for the session itself rather than code, see `HELP`. Also `--step`, pausing a
+//! program after each of its top-level forms, and `repl --listen`, the session over TCP.
+
+use crate::formatter::source;
+use crate::interpreter::{Interpreter, SPECIAL_FORMS};
+use crate::macros::BUILTIN_NAMES;
+#[cfg(feature = "net")]
+use crate::output::Streams;
+use crate::symbols::Name;
+use crate::{builtins, parse, report, Lexer, Token};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+#[cfg(feature = "net")]
+use std::io::BufReader;
+use std::io::{self, BufRead};
+#[cfg(feature = "net")]
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "net")]
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::time::Instant;
+
+const HELP: &str = "\
+:env              list the variables defined so far
+:load <file>      run a source file
+:type <expr>      show the type of an expression's value
+:time <expr>      show an expression's value and how long it took
+:complete <code>  list what the symbol the code ends with could be
+:reset            forget every variable
+:quit             end the session
+";
+
+/// Reads code from `input` and prints the value of each form until the input ends or `:quit`.
+/// `prelude` is whether the prelude is defined again after `:reset`.
+pub fn run(interpreter: &mut Interpreter, input: impl BufRead, prelude: bool) -> io::Result<()> {
+    let mut pending = String::new();
+    let mut lines = input.lines();
+    loop {
+        let prompt = if pending.is_empty() { "> " } else { "  " };
+        let _ = interpreter.write(prompt.to_string());
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if pending.is_empty() && line.trim_start().starts_with(':') {
+            let line = line.trim();
+            let (command, argument) = match line.find(' ') {
+                Some(space) => (&line[..space], line[space..].trim()),
+                None => (line, ""),
+            };
+            if command == ":quit" {
+                break;
+            }
+            let reply = match command {
+                ":env" => Ok(environment(interpreter)),
+                ":load" => load(interpreter, argument),
+                ":type" => evaluate(interpreter, argument).map(|value| value.type_name().into()),
+                ":time" => {
+                    let start = Instant::now();
+                    evaluate(interpreter, argument)
+                        .map(|value| format!("{}\ntime: {:?}", value, start.elapsed()))
+                }
+                ":complete" => Ok(completions(interpreter, argument).join("\n")),
+                ":reset" => {
+                    interpreter.reset();
+                    if prelude {
+                        interpreter.load_prelude().map(|_| String::new())
+                    } else {
+                        Ok(String::new())
+                    }
+                }
+                ":help" => Ok(HELP.trim_end().to_string()),
+                _ => Err(format!("unknown command {}, see :help", command)),
+            };
+            reply_with(interpreter, reply);
+            continue;
+        }
+        pending += &line;
+        pending.push('\n');
+        if !complete(&pending) {
+            continue;
+        }
+        let text = std::mem::take(&mut pending);
+        // A Ctrl-C at the prompt is for the session, not the code run next.
+        interpreter.interrupt_handle().take();
+        let values = interpreter.run(text);
+        match values {
+            Ok(values) => values.iter().for_each(|value| {
+                let _ = interpreter.write(format!("{}\n", value));
+            }),
+            Err(message) => reply_with(interpreter, Err(message)),
+        }
+    }
+    Ok(())
+}
+
+/// `repl --listen 127.0.0.1:7878`: the session for clients connecting to `address`, one at a
+/// time, each line they send read like a line typed at the prompt. Variables stay between
+/// clients, so a long-running interpreter can be looked into from another terminal.
+#[cfg(feature = "net")]
+pub fn listen(interpreter: &mut Interpreter, address: &str, prelude: bool) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("[REPL listening on {}]", listener.local_addr()?);
+    for client in listener.incoming() {
+        let client = match client {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        let peer = client.peer_addr()?;
+        eprintln!("[{} connected]", peer);
+        if let Err(e) = serve(interpreter, client, prelude) {
+            eprintln!("[{} disconnected: {}]", peer, e);
+        }
+    }
+    Ok(())
+}
+
+/// The session for one client, printing to it instead of stdout and stderr until it sends
+/// `:quit` or disconnects.
+#[cfg(feature = "net")]
+pub fn serve(interpreter: &mut Interpreter, client: TcpStream, prelude: bool) -> io::Result<()> {
+    let output = Streams {
+        out: client.try_clone()?,
+        err: client.try_clone()?,
+    };
+    let local = interpreter.set_output(Box::new(output));
+    // A bug in the interpreter ends the client's session, not the server other clients use.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run(interpreter, BufReader::new(client), prelude)
+    }));
+    interpreter.set_output(local);
+    result.unwrap_or_else(|_| {
+        interpreter.recover();
+        Err(io::Error::other("evaluation panicked"))
+    })
+}
+
+/// Runs a top-level form for `--step`: prints it, its value and the variables it defined or
+/// changed, then waits for a line from `input` before going on.
+pub fn step(
+    interpreter: &mut Interpreter,
+    form: &Token,
+    input: &mut impl BufRead,
+) -> Result<(), String> {
+    let _ = interpreter.write(format!("{}\n", source(form)));
+    let before = interpreter.scope().borrow().visible();
+    let value = interpreter.run_parsed(slice::from_ref(form))?.remove(0);
+    let after = interpreter.scope().borrow().visible();
+    let mut text = format!("=> {}\n", value);
+    for change in changes(&before, &after) {
+        text += &format!("  {}\n", change);
+    }
+    text += "[Enter to continue]";
+    let _ = interpreter.write(text);
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("can't read input: {}", e)),
+    }
+}
+
+/// The variables added (`+`) or given a new value (`~`) between two snapshots.
+fn changes(before: &[(Name, Token)], after: &[(Name, Token)]) -> Vec<String> {
+    let before: HashMap<Name, &Token> = before.iter().map(|(name, value)| (*name, value)).collect();
+    after
+        .iter()
+        .filter_map(|(name, value)| match before.get(name) {
+            None => Some(format!("+ {} = {}", name, value)),
+            Some(old) if *old != value => Some(format!("~ {} = {} (was {})", name, value, old)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn reply_with(interpreter: &mut Interpreter, reply: Result<String, String>) {
+    let _ = match reply {
+        Ok(text) if text.is_empty() => Ok(()),
+        Ok(text) => interpreter.write(text + "\n"),
+        Err(message) => interpreter.write_err(format!("error: {}\n", message)),
+    };
+}
+
+/// Whether `text` closes every parenthesis it opens, outside of string literals.
+fn complete(text: &str) -> bool {
+    let (mut depth, mut in_string, mut escaped) = (0, false, false);
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0 && !in_string
+}
+
+/// Evaluates a single expression.
+fn evaluate(interpreter: &mut Interpreter, text: &str) -> Result<Token, String> {
+    let ast = parse(Lexer::new(text.to_string())).map_err(|errors| report(&errors))?;
+    match &ast[..] {
+        [_] => interpreter
+            .run_parsed(&ast)
+            .map(|mut values| values.remove(0)),
+        _ => Err(format!("expected one expression, found {}", ast.len())),
+    }
+}
+
+fn load(interpreter: &mut Interpreter, path: &str) -> Result<String, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    interpreter.run(text)?;
+    Ok(format!("loaded {}", path))
+}
+
+/// The variables, macros, special forms and builtins whose names start with the partly typed
+/// symbol `code` ends with, sorted. The symbol can be inside nested forms, as in `(map (fil`.
+fn completions(interpreter: &Interpreter, code: &str) -> Vec<String> {
+    let start = code
+        .rfind(|c: char| c.is_whitespace() || "()'`,\"".contains(c))
+        .map_or(0, |delimiter| delimiter + 1);
+    let prefix = &code[start..];
+    let variables: Vec<String> = interpreter
+        .scope()
+        .borrow()
+        .visible()
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let macros = interpreter.macros().map(|(name, _)| name.to_string());
+    let fixed = SPECIAL_FORMS
+        .iter()
+        .chain(BUILTIN_NAMES)
+        .copied()
+        .chain(builtins::names())
+        .map(String::from);
+    let names: BTreeSet<String> = variables
+        .into_iter()
+        .chain(macros)
+        .chain(fixed)
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    names.into_iter().collect()
+}
+
+/// Each variable defined so far with its value, sorted by name.
+fn environment(interpreter: &Interpreter) -> String {
+    let mut lines: Vec<String> = interpreter
+        .scope()
+        .borrow()
+        .visible()
+        .iter()
+        .map(|(name, value)| format!("{} = {}", name, value))
+        .collect();
+    lines.sort();
+    lines.join("\n")
+}
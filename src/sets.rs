@@ -0,0 +1,84 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::{error, Eval, Token, Unwind};
+use std::collections::HashSet;
+
+builtins! {
+    /// Finds the set builtin called `name`.
+    "set-of" => set_of,
+    "union" => union,
+    "intersection" => intersection,
+    "difference" => difference,
+    "member?" => member,
+    "set->list" => set_to_list,
+}
+
+fn sets(name: &str, args: Vec<Token>) -> Result<Vec<HashSet<Token>>, Unwind> {
+    arity(name, &args, 1, usize::MAX)?;
+    args.into_iter()
+        .map(|arg| match arg {
+            Token::Set(set) => Ok(set),
+            _ => error(format!("{} expects sets", name)),
+        })
+        .collect()
+}
+
+/// `(set-of 1 2 3)`
+fn set_of(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Set(args.into_iter().collect()))
+}
+
+/// `(union a b ...)`
+fn union(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let mut sets = sets("union", args)?.into_iter();
+    let first = sets.next().unwrap();
+    Ok(Token::Set(sets.fold(first, |mut acc, set| {
+        acc.extend(set);
+        acc
+    })))
+}
+
+/// `(intersection a b ...)`
+fn intersection(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let mut sets = sets("intersection", args)?.into_iter();
+    let first = sets.next().unwrap();
+    Ok(Token::Set(sets.fold(first, |mut acc, set| {
+        acc.retain(|item| set.contains(item));
+        acc
+    })))
+}
+
+/// `(difference a b ...)` keeps the items of `a` that are in none of the other sets.
+fn difference(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    let mut sets = sets("difference", args)?.into_iter();
+    let first = sets.next().unwrap();
+    Ok(Token::Set(sets.fold(first, |mut acc, set| {
+        acc.retain(|item| !set.contains(item));
+        acc
+    })))
+}
+
+/// `(member? 2 (set-of 1 2 3))`, also works on lists.
+fn member(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("member?", &args, 2, 2)?;
+    let found = match &args[1] {
+        Token::Set(set) => set.contains(&args[0]),
+        Token::List(items) => items.contains(&args[0]),
+        _ => return error("member? expects a set or a list"),
+    };
+    Ok(if found { Token::True } else { Token::False })
+}
+
+/// The items of `set` in the order `Token::order` puts them, the same in every run.
+pub fn sorted(set: &HashSet<Token>) -> Vec<&Token> {
+    let mut items: Vec<&Token> = set.iter().collect();
+    items.sort_by(|a, b| a.order(b));
+    items
+}
+
+/// `(set->list (set-of 3 1 2))`, sorted like `(1 2 3)`.
+fn set_to_list(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("set->list", &args, 1, 1)?;
+    let set = sets("set->list", args)?.remove(0);
+    Ok(Token::List(sorted(&set).into_iter().cloned().collect()))
+}
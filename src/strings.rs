@@ -0,0 +1,114 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::lists::items;
+use crate::{error, Eval, Token, Unwind};
+
+builtins! {
+    /// Finds the string builtin called `name`.
+    "substring" => substring,
+    "split" => split,
+    "join" => join,
+    "trim" => trim,
+    "upcase" => upcase,
+    "downcase" => downcase,
+    "replace" => replace,
+    "str" => stringify,
+}
+
+pub fn text<'a>(name: &str, value: &'a Token) -> Result<&'a str, Unwind> {
+    match value {
+        Token::Str(text) => Ok(text),
+        _ => error(format!("{} expects a string", name)),
+    }
+}
+
+/// `(str "x is " 1.5)` joins its arguments the way `println` prints them, it's what
+/// `"x is ${x}"` runs as.
+fn stringify(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    Ok(Token::Str(args.iter().map(Token::to_string).collect()))
+}
+
+/// `(substring "hello" 1 3)` takes the characters from the start index up to, but without,
+/// the end index, which defaults to the end of the string.
+fn substring(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("substring", &args, 2, 3)?;
+    let chars: Vec<char> = text("substring", &args[0])?.chars().collect();
+    let index = |arg: Option<&Token>, default: usize| match arg {
+        None => Ok(default),
+        Some(Token::Int(i)) if *i >= 0 && *i as usize <= chars.len() => Ok(*i as usize),
+        Some(_) => error(format!(
+            "substring expects indexes between 0 and {}",
+            chars.len()
+        )),
+    };
+    let start = index(args.get(1), 0)?;
+    let end = index(args.get(2), chars.len())?;
+    if start > end {
+        return error("substring start is past its end");
+    }
+    Ok(Token::Str(chars[start..end].iter().collect()))
+}
+
+/// `(split "a,b,c" ",")`, without a separator splits on whitespace.
+fn split(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("split", &args, 1, 2)?;
+    let text = text("split", &args[0])?;
+    let parts: Vec<&str> = match args.get(1) {
+        None => text.split_whitespace().collect(),
+        Some(separator) => match self::text("split", separator)? {
+            "" => return error("split separator can't be empty"),
+            separator => text.split(separator).collect(),
+        },
+    };
+    Ok(Token::List(
+        parts
+            .into_iter()
+            .map(|part| Token::Str(part.to_string()))
+            .collect(),
+    ))
+}
+
+/// `(join '("a" "b") ", ")`, the separator defaults to an empty string.
+fn join(mut args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("join", &args, 1, 2)?;
+    let separator = match args.get(1) {
+        None => "",
+        Some(separator) => text("join", separator)?,
+    }
+    .to_string();
+    let parts = items("join", args.remove(0))?
+        .iter()
+        .map(|part| text("join", part).map(str::to_string))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Token::Str(parts.join(&separator)))
+}
+
+/// `(trim "  hello ")`
+fn trim(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("trim", &args, 1, 1)?;
+    Ok(Token::Str(text("trim", &args[0])?.trim().to_string()))
+}
+
+/// `(upcase "hello")`
+fn upcase(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("upcase", &args, 1, 1)?;
+    Ok(Token::Str(text("upcase", &args[0])?.to_uppercase()))
+}
+
+/// `(downcase "HELLO")`
+fn downcase(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("downcase", &args, 1, 1)?;
+    Ok(Token::Str(text("downcase", &args[0])?.to_lowercase()))
+}
+
+/// `(replace "a-b-c" "-" "+")` replaces every occurrence.
+fn replace(args: Vec<Token>, _interpreter: &mut Interpreter) -> Eval {
+    arity("replace", &args, 3, 3)?;
+    let from = text("replace", &args[1])?;
+    if from.is_empty() {
+        return error("replace can't replace an empty string");
+    }
+    Ok(Token::Str(
+        text("replace", &args[0])?.replace(from, text("replace", &args[2])?),
+    ))
+}
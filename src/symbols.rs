@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+/// An interned symbol: a number standing for its name, so symbols are copied, compared and
+/// hashed without touching the heap.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Name(u32);
+
+/// Names of all the symbols seen so far. Each name is leaked to live as long as the program, so
+/// `as_str` can hand it out without copying, which means symbols made at run time, like with
+/// `string->symbol`, take up memory for good.
+#[derive(Default)]
+struct Table {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+fn table() -> &'static Mutex<Table> {
+    static TABLE: OnceLock<Mutex<Table>> = OnceLock::new();
+    TABLE.get_or_init(Default::default)
+}
+
+thread_local! {
+    /// This thread's copy of the table's names, which only ever grow, so names are looked up
+    /// without the lock, only taken to catch up with names interned since.
+    static NAMES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Name {
+    /// The symbol called `name`.
+    pub fn new(name: &str) -> Self {
+        let mut table = table().lock().unwrap();
+        if let Some(id) = table.ids.get(name) {
+            return Name(*id);
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = table.names.len() as u32;
+        table.names.push(name);
+        table.ids.insert(name, id);
+        Name(id)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        let index = self.0 as usize;
+        NAMES.with(|names| {
+            let mut names = names.borrow_mut();
+            if index >= names.len() {
+                let table = table().lock().unwrap();
+                let known = names.len();
+                names.extend_from_slice(&table.names[known..]);
+            }
+            names[index]
+        })
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,39 @@
+use crate::builtins::{arity, builtins};
+use crate::interpreter::Interpreter;
+use crate::strings::text;
+use crate::{error, Eval, Token, Unwind};
+use std::process::Command;
+
+builtins! {
+    /// Finds the builtin called `name` that reaches outside the interpreter.
+    "sh" => sh,
+}
+
+/// Fails when `--sandbox` disabled access to the system.
+pub fn allowed(name: &str, interpreter: &Interpreter) -> Result<(), Unwind> {
+    if interpreter.sandboxed() {
+        return error(format!("{} isn't available in the sandbox", name));
+    }
+    Ok(())
+}
+
+/// `(sh "ls -la")` runs a shell command, evaluating to `(status stdout stderr)`. The status is
+/// -1 when the command was killed by a signal.
+fn sh(args: Vec<Token>, interpreter: &mut Interpreter) -> Eval {
+    arity("sh", &args, 1, 1)?;
+    allowed("sh", interpreter)?;
+    let command = text("sh", &args[0])?;
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    };
+    match output {
+        Ok(output) => Ok(Token::List(vec![
+            Token::Int(output.status.code().unwrap_or(-1)),
+            Token::Str(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Token::Str(String::from_utf8_lossy(&output.stderr).into_owned()),
+        ])),
+        Err(e) => error(format!("sh: can't run {:?}: {}", command, e)),
+    }
+}
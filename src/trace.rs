@@ -0,0 +1,76 @@
+//! Traces of the inputs a run got from outside the program, the clock and stdin, which
+//! `--record=trace.bin` saves and `--replay=trace.bin` feeds back in to reproduce the run.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+const MAGIC: &[u8] = b"MLSPT1\n";
+
+/// An input the program got, in the order it got them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The timestamp `date-now` evaluated to.
+    Time(i64),
+    /// Everything read from stdin.
+    Input(String),
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Time(_) => "the time",
+            Event::Input(_) => "input",
+        }
+    }
+}
+
+pub enum Trace {
+    Recording(Vec<Event>),
+    Replaying(VecDeque<Event>),
+}
+
+pub fn encode(events: &[Event]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    for event in events {
+        match event {
+            Event::Time(timestamp) => {
+                bytes.push(b't');
+                bytes.extend_from_slice(&timestamp.to_le_bytes());
+            }
+            Event::Input(text) => {
+                bytes.push(b'i');
+                bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+/// The events traced in `bytes`, `None` when it isn't a trace or is cut short.
+pub fn decode(mut bytes: &[u8]) -> Option<Vec<Event>> {
+    let mut take = |count: usize| {
+        if bytes.len() < count {
+            return None;
+        }
+        let (taken, rest) = bytes.split_at(count);
+        bytes = rest;
+        Some(taken)
+    };
+    if take(MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let mut events = vec![];
+    while let Some(tag) = take(1) {
+        let event = match tag[0] {
+            b't' => Event::Time(i64::from_le_bytes(take(8)?.try_into().ok()?)),
+            b'i' => {
+                let length = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+                Event::Input(String::from_utf8(take(length)?.to_vec()).ok()?)
+            }
+            _ => return None,
+        };
+        events.push(event);
+    }
+    Some(events)
+}
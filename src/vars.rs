@@ -0,0 +1,242 @@
+use crate::symbols::Name;
+use crate::{error, Token, Unwind};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Variables in scope. Most frames bind a few, which are searched in turn, and frames binding
+/// many, like the global one, index them by name too.
+#[derive(Clone, Default)]
+pub struct Vars {
+    entries: Vec<(Name, Token)>,
+    index: Option<HashMap<Name, usize>>,
+}
+
+/// How many variables a frame binds before it indexes them.
+const INDEXED: usize = 16;
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn position(&self, name: Name) -> Option<usize> {
+        match &self.index {
+            Some(index) => index.get(&name).copied(),
+            None => self.entries.iter().position(|(bound, _)| *bound == name),
+        }
+    }
+
+    pub fn get(&self, name: Name) -> Option<&Token> {
+        Some(&self.entries[self.position(name)?].1)
+    }
+
+    pub fn get_mut(&mut self, name: Name) -> Option<&mut Token> {
+        let position = self.position(name)?;
+        Some(&mut self.entries[position].1)
+    }
+
+    pub fn insert(&mut self, name: Name, value: Token) {
+        if let Some(position) = self.position(name) {
+            self.entries[position].1 = value;
+            return;
+        }
+        self.entries.push((name, value));
+        match &mut self.index {
+            Some(index) => {
+                index.insert(name, self.entries.len() - 1);
+            }
+            None if self.entries.len() > INDEXED => {
+                let index = self.entries.iter().enumerate();
+                self.index = Some(
+                    index
+                        .map(|(position, (name, _))| (*name, position))
+                        .collect(),
+                );
+            }
+            None => {}
+        }
+    }
+
+    /// The bound variables, in the order they were first bound.
+    pub fn iter(&self) -> impl Iterator<Item = (Name, &Token)> {
+        self.entries.iter().map(|(name, value)| (*name, value))
+    }
+
+    /// Binds the variable named by the symbol `name`. A list of names destructures a list
+    /// value instead, binding each name to the item in the same place, nested lists included.
+    /// `(first & rest)` binds the items after `first` to `rest`.
+    pub fn bind(&mut self, name: &Token, value: Token) -> Result<(), Unwind> {
+        match (name, value) {
+            (Token::List(_), Token::Frozen(value)) => self.bind(name, (*value).clone()),
+            (Token::Symbol(name), value) => {
+                self.insert(*name, value);
+                Ok(())
+            }
+            (Token::List(names), Token::List(mut items)) => {
+                let rest = names
+                    .iter()
+                    .position(|name| matches!(name, Token::Symbol(s) if s.as_str() == "&"));
+                let count = rest.unwrap_or(names.len());
+                let fits = match rest {
+                    Some(_) => names.len() == count + 2 && items.len() >= count,
+                    None => items.len() == count,
+                };
+                if !fits {
+                    return error(format!(
+                        "can't destructure {} into {}",
+                        Token::List(items),
+                        Token::List(names.clone())
+                    ));
+                }
+                let tail = items.split_off(count);
+                for (name, item) in names.iter().zip(items) {
+                    self.bind(name, item)?;
+                }
+                match rest {
+                    Some(_) => self.bind(&names[count + 1], Token::List(tail)),
+                    None => Ok(()),
+                }
+            }
+            (Token::List(names), value) => error(format!(
+                "can't destructure {} into {}, it's not a list",
+                value,
+                Token::List(names.clone())
+            )),
+            (name, _) => error(format!("{} can't be used as a variable name", name)),
+        }
+    }
+}
+
+/// A frame of the environment, shared between the code running in it and the closures created
+/// there, so changes to its variables are visible to all of them.
+pub type Scope = Rc<RefCell<Env>>;
+
+/// Variables bound by a function call, `let` or the program itself, and the frame around them.
+pub struct Env {
+    vars: Vars,
+    parent: Option<Scope>,
+    /// Whether this is the frame of a named `let`, holding the function looping through it,
+    /// which `return` goes through to leave the function around the loop.
+    internal: bool,
+}
+
+impl Env {
+    /// The outermost frame, holding the program's global variables.
+    pub fn global() -> Scope {
+        Rc::new(RefCell::new(Env {
+            vars: Vars::new(),
+            parent: None,
+            internal: false,
+        }))
+    }
+
+    /// A new frame inside `parent`.
+    pub fn child(parent: &Scope) -> Scope {
+        Rc::new(RefCell::new(Env {
+            vars: Vars::new(),
+            parent: Some(parent.clone()),
+            internal: false,
+        }))
+    }
+
+    /// A new frame inside `parent` for a named `let`, the functions created in which return
+    /// from the function around them.
+    pub fn internal(parent: &Scope) -> Scope {
+        let scope = Env::child(parent);
+        scope.borrow_mut().internal = true;
+        scope
+    }
+
+    /// Whether `return` goes through the functions created in this frame.
+    pub fn is_internal(&self) -> bool {
+        self.internal
+    }
+
+    /// The outermost frame around `scope`, holding the global variables.
+    pub fn outermost(scope: &Scope) -> Scope {
+        match &scope.borrow().parent {
+            Some(parent) => Env::outermost(parent),
+            None => scope.clone(),
+        }
+    }
+
+    /// The value of the innermost variable called `name`, searching each frame from this one
+    /// outwards. Names can't be resolved to a frame and a place in it ahead of time, `define`
+    /// and `set` bind new variables in frames code already runs in.
+    pub fn get(&self, name: Name) -> Option<Token> {
+        match self.vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// The frame around this one, `None` for the global frame.
+    #[cfg(feature = "parallel")]
+    pub fn parent(&self) -> Option<&Scope> {
+        self.parent.as_ref()
+    }
+
+    /// The variables bound in this frame itself.
+    #[cfg(feature = "parallel")]
+    pub fn vars(&self) -> impl Iterator<Item = (Name, &Token)> {
+        self.vars.iter()
+    }
+
+    /// Binds a variable in this frame by name.
+    #[cfg(feature = "parallel")]
+    pub fn insert(&mut self, name: Name, value: Token) {
+        self.vars.insert(name, value);
+    }
+
+    /// Binds a variable in this frame, shadowing any outer one.
+    pub fn define(&mut self, name: &Token, value: Token) -> Result<(), Unwind> {
+        self.vars.bind(name, value)
+    }
+
+    /// Assigns to the innermost variable called `name`, or binds it in this frame when there's
+    /// none.
+    pub fn set(&mut self, name: &Token, value: Token) -> Result<(), Unwind> {
+        match name {
+            Token::Symbol(symbol) if self.binds(*symbol) => {
+                self.update(*symbol, |variable| *variable = value);
+                Ok(())
+            }
+            _ => self.vars.bind(name, value),
+        }
+    }
+
+    fn binds(&self, name: Name) -> bool {
+        self.vars.get(name).is_some()
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.borrow().binds(name))
+    }
+
+    /// Passes the innermost variable called `name` to `f`, to change it in place.
+    pub fn update<R>(&mut self, name: Name, f: impl FnOnce(&mut Token) -> R) -> Option<R> {
+        match self.vars.get_mut(name) {
+            Some(variable) => Some(f(variable)),
+            None => self.parent.as_ref()?.borrow_mut().update(name, f),
+        }
+    }
+
+    /// All variables visible from this frame, outer ones first so inner ones can shadow them.
+    pub fn visible(&self) -> Vec<(Name, Token)> {
+        let mut visible = match &self.parent {
+            Some(parent) => parent.borrow().visible(),
+            None => vec![],
+        };
+        visible.extend(self.vars.iter().map(|(name, value)| (name, value.clone())));
+        visible
+    }
+}
+
+/// Frames refer to closures which refer back to frames, printing them would never end.
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<env>")
+    }
+}
@@ -0,0 +1,37 @@
+//! Walking parsed programs, for tools that look at code without running it: linters, metrics,
+//! refactoring scripts. The interpreter itself doesn't use it.
+#![allow(dead_code)]
+
+use crate::Token;
+
+/// Hooks called while walking a program, each doing nothing unless overridden.
+pub trait Visitor {
+    /// Called with a list before its items, which are skipped when this returns `false`.
+    fn enter(&mut self, _items: &[Token]) -> bool {
+        true
+    }
+
+    /// Called with a list after its items.
+    fn leave(&mut self, _items: &[Token]) {}
+
+    /// Called with everything that isn't a list: numbers, strings and symbols.
+    fn atom(&mut self, _atom: &Token) {}
+}
+
+/// Walks `node` depth first, calling `visitor` on it and everything nested in it.
+pub fn visit(node: &Token, visitor: &mut impl Visitor) {
+    match node {
+        Token::List(items) => {
+            if visitor.enter(items) {
+                items.iter().for_each(|item| visit(item, visitor));
+            }
+            visitor.leave(items);
+        }
+        atom => visitor.atom(atom),
+    }
+}
+
+/// Walks each top-level form of a program in turn.
+pub fn visit_all(ast: &[Token], visitor: &mut impl Visitor) {
+    ast.iter().for_each(|node| visit(node, visitor));
+}
@@ -0,0 +1,525 @@
+//! Compiling programs to WebAssembly for `compile --emit=wasm`: a standalone module exporting
+//! the program's top-level functions on numbers, usable from any WebAssembly host.
+//!
+//! Numbers are `f64`s, so every function takes and returns them. A function is left out when it
+//! uses anything else, like strings, lists or a builtin with no WebAssembly instruction, or calls
+//! a function that's left out. Tests, as in `if`, have to be comparisons, optionally joined by
+//! `and` and `or`, since only `false` and `nil` are false while `0.0` is the only false `f64`.
+
+use crate::symbols::Name;
+use crate::Token;
+
+const F64: u8 = 0x7c;
+const I32: u8 = 0x7f;
+const EMPTY: u8 = 0x40;
+
+// The instructions used, by opcode.
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1a;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I32_CONST: u8 = 0x41;
+const F64_CONST: u8 = 0x44;
+const I32_EQZ: u8 = 0x45;
+
+/// A function being compiled.
+struct Function<'a> {
+    /// The functions that can be called, in the order of their indexes.
+    callable: &'a [(Name, usize)],
+    /// The variables in scope and their locals, innermost last.
+    variables: Vec<(Name, u32)>,
+    locals: u32,
+    code: Vec<u8>,
+}
+
+/// The functions left out of a module and why.
+pub type Skipped = Vec<(Name, String)>;
+
+/// `program` as a WebAssembly module, with the functions left out.
+pub fn compile(program: &[Token]) -> Result<(Vec<u8>, Skipped), String> {
+    let mut candidates: Vec<(Name, &[Token], &[Token])> = program
+        .iter()
+        .filter_map(|form| match form {
+            Token::List(list) if list.len() > 2 && symbol(&list[0]) == Some("define") => {
+                match &list[1] {
+                    Token::List(signature) => match signature.split_first() {
+                        Some((Token::Symbol(name), params)) => Some((*name, params, &list[2..])),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+    let mut skipped = vec![];
+    // Leaving a function out can leave out the ones calling it, so it's repeated until they all
+    // compile.
+    loop {
+        let callable: Vec<(Name, usize)> = candidates
+            .iter()
+            .map(|(name, params, _)| (*name, params.len()))
+            .collect();
+        let mut bodies = vec![];
+        let mut failed = None;
+        for (index, (name, params, body)) in candidates.iter().enumerate() {
+            match function(&callable, params, body) {
+                Ok(code) => bodies.push(code),
+                Err(reason) => {
+                    failed = Some((index, *name, reason));
+                    break;
+                }
+            }
+        }
+        match failed {
+            Some((index, name, reason)) => {
+                skipped.push((name, reason));
+                candidates.remove(index);
+            }
+            None if bodies.is_empty() => {
+                return Err("no function can be compiled to WebAssembly".to_string())
+            }
+            None => return Ok((module(&callable, &bodies), skipped)),
+        }
+    }
+}
+
+fn symbol(form: &Token) -> Option<&str> {
+    match form {
+        Token::Symbol(name) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// The code of a function, its locals' declaration included.
+fn function(
+    callable: &[(Name, usize)],
+    params: &[Token],
+    body: &[Token],
+) -> Result<Vec<u8>, String> {
+    let mut function = Function {
+        callable,
+        variables: vec![],
+        locals: 0,
+        code: vec![],
+    };
+    for param in params {
+        match param {
+            Token::Symbol(name) if name.as_str() != "&" => {
+                let name = name.as_str();
+                let name = match name.find(':') {
+                    Some(colon) if colon > 0 => &name[..colon],
+                    _ => name,
+                };
+                function.declare(Name::new(name));
+            }
+            other => return Err(format!("the parameter {} isn't a number", other)),
+        }
+    }
+    let body = match body {
+        [Token::Str(_), rest @ ..] if !rest.is_empty() => rest,
+        _ => body,
+    };
+    function.body(body)?;
+    let mut code = vec![];
+    let extra = function.locals - params.len() as u32;
+    match extra {
+        0 => code.push(0),
+        _ => {
+            code.push(1);
+            unsigned(&mut code, extra as u64);
+            code.push(F64);
+        }
+    }
+    code.extend(function.code);
+    code.push(END);
+    Ok(code)
+}
+
+impl Function<'_> {
+    fn declare(&mut self, name: Name) -> u32 {
+        let local = self.locals;
+        self.locals += 1;
+        self.variables.push((name, local));
+        local
+    }
+
+    fn local(&self, name: Name) -> Result<u32, String> {
+        match self.variables.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, local)) => Ok(*local),
+            None => Err(format!("{} isn't a local number", name)),
+        }
+    }
+
+    /// Evaluates `forms` in turn, leaving the last one's value.
+    fn body(&mut self, forms: &[Token]) -> Result<(), String> {
+        let (last, statements) = match forms.split_last() {
+            Some(split) => split,
+            None => return Err("an empty body has no number".to_string()),
+        };
+        let scope = self.variables.len();
+        for form in statements {
+            self.statement(form)?;
+        }
+        self.value(last)?;
+        self.variables.truncate(scope);
+        Ok(())
+    }
+
+    /// A form evaluated for its effect, leaving nothing.
+    fn statement(&mut self, form: &Token) -> Result<(), String> {
+        let list = match form {
+            Token::List(list) if !list.is_empty() => list,
+            _ => return Ok(()),
+        };
+        match (symbol(&list[0]), list.len()) {
+            (Some("define"), 3) if matches!(list[1], Token::Symbol(_)) => {
+                self.value(&list[2])?;
+                let local = match &list[1] {
+                    Token::Symbol(name) => self.declare(*name),
+                    _ => unreachable!(),
+                };
+                self.code.push(LOCAL_SET);
+                unsigned(&mut self.code, local as u64);
+            }
+            (Some("set"), 3) | (Some("inc!"), 2) | (Some("dec!"), 2) => {
+                self.assignment(list)?;
+                self.code.push(LOCAL_SET);
+                let local = self.local(name_of(&list[1])?)?;
+                unsigned(&mut self.code, local as u64);
+            }
+            (Some("while"), 3) => {
+                self.code.extend([BLOCK, EMPTY, LOOP, EMPTY]);
+                self.test(&list[1])?;
+                self.code.extend([I32_EQZ, BR_IF, 1]);
+                self.statement(&list[2])?;
+                self.code.extend([BR, 0, END, END]);
+            }
+            (Some("let"), 3..) => self.let_form(&list[1..], false)?,
+            (Some("do"), _) => {
+                for form in &list[1..] {
+                    self.statement(form)?;
+                }
+            }
+            (Some("when"), 3..) | (Some("unless"), 3..) => {
+                self.test(&list[1])?;
+                if symbol(&list[0]) == Some("unless") {
+                    self.code.push(I32_EQZ);
+                }
+                self.code.extend([IF, EMPTY]);
+                for form in &list[2..] {
+                    self.statement(form)?;
+                }
+                self.code.push(END);
+            }
+            _ => {
+                self.value(form)?;
+                self.code.push(DROP);
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the new value of the variable `set`, `inc!` or `dec!` change.
+    fn assignment(&mut self, list: &[Token]) -> Result<(), String> {
+        let local = self.local(name_of(&list[1])?)?;
+        match symbol(&list[0]) {
+            Some("set") => self.value(&list[2]),
+            step => {
+                self.code.push(LOCAL_GET);
+                unsigned(&mut self.code, local as u64);
+                self.constant(1.0);
+                self.code
+                    .push(if step == Some("inc!") { 0xa0 } else { 0xa1 });
+                Ok(())
+            }
+        }
+    }
+
+    fn constant(&mut self, value: f64) {
+        self.code.push(F64_CONST);
+        self.code.extend(value.to_le_bytes());
+    }
+
+    /// A form evaluated to a number, left as an `f64`.
+    fn value(&mut self, form: &Token) -> Result<(), String> {
+        let number = match form {
+            Token::Int(i) => Some(*i as f64),
+            Token::Long(i) => Some(*i as f64),
+            Token::Ratio(n, d) => Some(*n as f64 / *d as f64),
+            Token::Float(f) => Some(*f),
+            _ => None,
+        };
+        if let Some(number) = number {
+            self.constant(number);
+            return Ok(());
+        }
+        let list = match form {
+            Token::Symbol(name) => {
+                let local = self.local(*name)?;
+                self.code.push(LOCAL_GET);
+                unsigned(&mut self.code, local as u64);
+                return Ok(());
+            }
+            Token::List(list) if !list.is_empty() => list,
+            other => return Err(format!("{} isn't a number", other)),
+        };
+        let head = match symbol(&list[0]) {
+            Some(head) => head,
+            None => {
+                return Err(format!(
+                    "{} calls a function value",
+                    Token::List(list.clone())
+                ))
+            }
+        };
+        let args = &list[1..];
+        match (head, args.len()) {
+            ("+", 0) => self.constant(0.0),
+            ("*", 0) => self.constant(1.0),
+            ("-", 1) | ("neg", 1) => {
+                self.value(&args[0])?;
+                self.code.push(0x9a);
+            }
+            ("/", 1) => {
+                self.constant(1.0);
+                self.value(&args[0])?;
+                self.code.push(0xa3);
+            }
+            ("+" | "-" | "*" | "/", _) => {
+                let opcode = match head {
+                    "+" => 0xa0,
+                    "-" => 0xa1,
+                    "*" => 0xa2,
+                    _ => 0xa3,
+                };
+                self.value(&args[0])?;
+                for arg in &args[1..] {
+                    self.value(arg)?;
+                    self.code.push(opcode);
+                }
+            }
+            ("inc" | "dec", 1) => {
+                self.value(&args[0])?;
+                self.constant(1.0);
+                self.code.push(if head == "inc" { 0xa0 } else { 0xa1 });
+            }
+            ("abs" | "ceil" | "floor" | "sqrt", 1) => {
+                self.value(&args[0])?;
+                self.code.push(match head {
+                    "abs" => 0x99,
+                    "ceil" => 0x9b,
+                    "floor" => 0x9c,
+                    _ => 0x9f,
+                });
+            }
+            ("min" | "max", 2) if !self.callable.iter().any(|(name, _)| name == head) => {
+                self.value(&args[0])?;
+                self.value(&args[1])?;
+                self.code.push(if head == "min" { 0xa4 } else { 0xa5 });
+            }
+            ("<" | ">" | "<=" | ">=" | "=" | "!=" | "and" | "or", _) => {
+                return Err(format!("the test {} isn't a number", form))
+            }
+            ("if", 3) => {
+                self.test(&args[0])?;
+                self.code.extend([IF, F64]);
+                self.value(&args[1])?;
+                self.code.push(ELSE);
+                self.value(&args[2])?;
+                self.code.push(END);
+            }
+            ("cond", _) => self.cond(args)?,
+            ("do", _) if args.len() == 1 => self.value(&args[0])?,
+            ("let", 2..) => self.let_form(args, true)?,
+            ("set", 2) | ("inc!", 1) | ("dec!", 1) => {
+                self.assignment(list)?;
+                self.code.push(LOCAL_TEE);
+                let local = self.local(name_of(&args[0])?)?;
+                unsigned(&mut self.code, local as u64);
+            }
+            _ => match self.callable.iter().position(|(name, _)| name == head) {
+                Some(index) if self.callable[index].1 == args.len() => {
+                    for arg in args {
+                        self.value(arg)?;
+                    }
+                    self.code.push(CALL);
+                    unsigned(&mut self.code, index as u64);
+                }
+                Some(_) => return Err(format!("wrong number of arguments to {}", head)),
+                None => return Err(format!("{} can't be compiled to WebAssembly", head)),
+            },
+        }
+        Ok(())
+    }
+
+    /// `(let ((name value)...) body...)`, leaving the body's value when `value` is set and
+    /// nothing otherwise.
+    fn let_form(&mut self, args: &[Token], value: bool) -> Result<(), String> {
+        let bindings = match &args[0] {
+            Token::List(bindings) => bindings,
+            _ => return Err("named let loops can't be compiled to WebAssembly".to_string()),
+        };
+        // The values are evaluated before any of the variables exist, as `let` does.
+        let mut names = vec![];
+        for binding in bindings {
+            match binding {
+                Token::List(pair) if pair.len() == 2 => {
+                    self.value(&pair[1])?;
+                    names.push(name_of(&pair[0])?);
+                }
+                _ => return Err("let bindings need to be (name value) pairs".to_string()),
+            }
+        }
+        let scope = self.variables.len();
+        let locals: Vec<u32> = names.into_iter().map(|name| self.declare(name)).collect();
+        for local in locals.iter().rev() {
+            self.code.push(LOCAL_SET);
+            unsigned(&mut self.code, *local as u64);
+        }
+        match value {
+            true => self.body(&args[1..])?,
+            false => {
+                for form in &args[1..] {
+                    self.statement(form)?;
+                }
+            }
+        }
+        self.variables.truncate(scope);
+        Ok(())
+    }
+
+    /// `cond` as nested `if`s, which needs an `else` clause to always have a number.
+    fn cond(&mut self, clauses: &[Token]) -> Result<(), String> {
+        let (clause, rest) = match clauses.split_first() {
+            Some((Token::List(clause), rest)) if clause.len() > 1 => (clause, rest),
+            Some(_) => return Err("cond clauses need a test and a body".to_string()),
+            None => return Err("cond needs an else clause to always have a number".to_string()),
+        };
+        if symbol(&clause[0]) == Some("else") {
+            return self.body(&clause[1..]);
+        }
+        self.test(&clause[0])?;
+        self.code.extend([IF, F64]);
+        self.body(&clause[1..])?;
+        self.code.push(ELSE);
+        self.cond(rest)?;
+        self.code.push(END);
+        Ok(())
+    }
+
+    /// A test, left as an `i32` that's 1 when it holds.
+    fn test(&mut self, form: &Token) -> Result<(), String> {
+        let list = match form {
+            Token::True | Token::False => {
+                self.code.extend([I32_CONST, (*form == Token::True) as u8]);
+                return Ok(());
+            }
+            Token::List(list) if !list.is_empty() => list,
+            other => return Err(format!("the test {} isn't a comparison", other)),
+        };
+        let args = &list[1..];
+        match (symbol(&list[0]), args.len()) {
+            (Some(comparison @ ("<" | ">" | "<=" | ">=" | "=" | "!=")), 2) => {
+                self.value(&args[0])?;
+                self.value(&args[1])?;
+                self.code.push(match comparison {
+                    "=" => 0x61,
+                    "!=" => 0x62,
+                    "<" => 0x63,
+                    ">" => 0x64,
+                    "<=" => 0x65,
+                    _ => 0x66,
+                });
+            }
+            (Some(junction @ ("and" | "or")), 1..) => {
+                self.test(&args[0])?;
+                for arg in &args[1..] {
+                    self.code.extend([IF, I32]);
+                    match junction {
+                        "and" => {
+                            self.test(arg)?;
+                            self.code.extend([ELSE, I32_CONST, 0]);
+                        }
+                        _ => {
+                            self.code.extend([I32_CONST, 1, ELSE]);
+                            self.test(arg)?;
+                        }
+                    }
+                    self.code.push(END);
+                }
+            }
+            _ => return Err(format!("the test {} isn't a comparison", form)),
+        }
+        Ok(())
+    }
+}
+
+fn name_of(form: &Token) -> Result<Name, String> {
+    match form {
+        Token::Symbol(name) => Ok(*name),
+        other => Err(format!("{} isn't a variable", other)),
+    }
+}
+
+/// Appends `value` in unsigned LEB128.
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, count: usize, contents: Vec<u8>) {
+    let mut body = vec![];
+    unsigned(&mut body, count as u64);
+    body.extend(contents);
+    out.push(id);
+    unsigned(out, body.len() as u64);
+    out.extend(body);
+}
+
+/// The module with a function of `f64`s for each of `functions`, exported under its name.
+fn module(functions: &[(Name, usize)], bodies: &[Vec<u8>]) -> Vec<u8> {
+    let mut module = b"\0asm".to_vec();
+    module.extend([1, 0, 0, 0]);
+    let mut types = vec![];
+    for (_, arity) in functions {
+        types.push(0x60);
+        unsigned(&mut types, *arity as u64);
+        types.extend(vec![F64; *arity]);
+        types.extend([1, F64]);
+    }
+    section(&mut module, 1, functions.len(), types);
+    let mut indexes = vec![];
+    (0..functions.len()).for_each(|index| unsigned(&mut indexes, index as u64));
+    section(&mut module, 3, functions.len(), indexes);
+    let mut exports = vec![];
+    for (index, (name, _)) in functions.iter().enumerate() {
+        unsigned(&mut exports, name.as_str().len() as u64);
+        exports.extend(name.as_str().as_bytes());
+        exports.push(0);
+        unsigned(&mut exports, index as u64);
+    }
+    section(&mut module, 7, functions.len(), exports);
+    let mut code = vec![];
+    for body in bodies {
+        unsigned(&mut code, body.len() as u64);
+        code.extend(body);
+    }
+    section(&mut module, 10, bodies.len(), code);
+    module
+}